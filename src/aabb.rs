@@ -1,9 +1,11 @@
-use std::i32;
-
-use crate::{interval::Interval, ray::Ray, vec3::Vec3};
+use crate::{
+    interval::Interval,
+    ray::Ray,
+    vec3::{vec3, Vec3},
+};
 
 // Struct for an axis-aligned bounding box, definde by intervals in all 3 spacial dimensions
-#[derive(Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct Aabb {
     pub x: Interval,
     pub y: Interval,
@@ -59,12 +61,47 @@ impl Aabb {
     pub fn longest_axis(&self) -> i32 {
         // Returns the index of the longest axis of the bounding box.
         if self.x.size() > self.y.size() {
-            return if self.x.size() > self.z.size() { 0 } else { 2 };
+            if self.x.size() > self.z.size() {
+                0
+            } else {
+                2
+            }
         } else {
-            return if self.y.size() > self.z.size() { 1 } else { 2 };
+            if self.y.size() > self.z.size() {
+                1
+            } else {
+                2
+            }
+        }
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        vec3(
+            (self.x.min + self.x.max) / 2.0,
+            (self.y.min + self.y.max) / 2.0,
+            (self.z.min + self.z.max) / 2.0,
+        )
+    }
+
+    /// Returns the box grown by `delta` along every axis, split evenly between both sides.
+    pub fn expand(&self, delta: f64) -> Aabb {
+        Aabb {
+            x: self.x.expand(delta),
+            y: self.y.expand(delta),
+            z: self.z.expand(delta),
         }
     }
 
+    /// Whether the point lies inside the box or on its boundary.
+    pub fn contains(&self, p: &Vec3) -> bool {
+        self.x.contains(p.x) && self.y.contains(p.y) && self.z.contains(p.z)
+    }
+
     pub fn axis_interval(&self, n: i32) -> &Interval {
         match n {
             1 => &self.y,
@@ -147,3 +184,78 @@ impl std::ops::Add<Vec3> for &Aabb {
         Aabb::from_interval(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::ray;
+
+    #[test]
+    fn from_point_orders_the_corners() {
+        let bbox = Aabb::from_point(&vec3(1.0, -2.0, 3.0), &vec3(-1.0, 2.0, -3.0));
+        assert_eq!((bbox.x.min, bbox.x.max), (-1.0, 1.0));
+        assert_eq!((bbox.y.min, bbox.y.max), (-2.0, 2.0));
+        assert_eq!((bbox.z.min, bbox.z.max), (-3.0, 3.0));
+    }
+
+    #[test]
+    fn flat_boxes_are_padded() {
+        let bbox = Aabb::from_point(&vec3(0.0, 1.0, 0.0), &vec3(2.0, 1.0, 2.0));
+        assert!(bbox.y.size() > 0.0);
+        assert!(bbox.contains(&vec3(1.0, 1.0, 1.0)));
+        assert_eq!(bbox.x.size(), 2.0);
+    }
+
+    #[test]
+    fn from_aabb_encloses_both_boxes() {
+        let a = Aabb::from_point(&vec3(0.0, 0.0, 0.0), &vec3(1.0, 1.0, 1.0));
+        let b = Aabb::from_point(&vec3(-2.0, 0.5, 3.0), &vec3(-1.0, 4.0, 5.0));
+        let union = Aabb::from_aabb(&a, &b);
+        assert_eq!((union.x.min, union.x.max), (-2.0, 1.0));
+        assert_eq!((union.y.min, union.y.max), (0.0, 4.0));
+        assert_eq!((union.z.min, union.z.max), (0.0, 5.0));
+
+        // The empty box is the identity of the union.
+        let same = Aabb::from_aabb(&Aabb::empty(), &a);
+        assert_eq!((same.x.min, same.x.max), (a.x.min, a.x.max));
+    }
+
+    #[test]
+    fn adding_an_offset_moves_the_box() {
+        let bbox =
+            Aabb::from_point(&vec3(0.0, 0.0, 0.0), &vec3(1.0, 2.0, 3.0)) + vec3(1.0, -1.0, 2.0);
+        assert_eq!((bbox.x.min, bbox.x.max), (1.0, 2.0));
+        assert_eq!((bbox.y.min, bbox.y.max), (-1.0, 1.0));
+        assert_eq!((bbox.z.min, bbox.z.max), (2.0, 5.0));
+    }
+
+    #[test]
+    fn longest_axis_and_centroid() {
+        let bbox = Aabb::from_point(&vec3(0.0, 0.0, 0.0), &vec3(1.0, 4.0, 2.0));
+        assert_eq!(bbox.longest_axis(), 1);
+        let centroid = bbox.centroid();
+        assert_eq!((centroid.x, centroid.y, centroid.z), (0.5, 2.0, 1.0));
+    }
+
+    #[test]
+    fn hit_respects_box_and_interval() {
+        let bbox = Aabb::from_point(&vec3(-1.0, -1.0, -1.0), &vec3(1.0, 1.0, 1.0));
+        let universe = Interval::universe();
+
+        assert!(bbox.hit(&ray(vec3(0.0, 0.0, -5.0), vec3(0.0, 0.0, 1.0)), universe));
+        assert!(bbox.hit(&ray(vec3(-5.0, -5.0, -5.0), vec3(1.0, 1.0, 1.0)), universe));
+        assert!(!bbox.hit(&ray(vec3(0.0, 2.0, -5.0), vec3(0.0, 0.0, 1.0)), universe));
+        // Pointing away from the box
+        assert!(!bbox.hit(
+            &ray(vec3(0.0, 0.0, -5.0), vec3(0.0, 0.0, -1.0)),
+            Interval::new(0.0, f64::INFINITY)
+        ));
+        // The box lies beyond the end of the interval
+        assert!(!bbox.hit(
+            &ray(vec3(0.0, 0.0, -5.0), vec3(0.0, 0.0, 1.0)),
+            Interval::new(0.0, 3.0)
+        ));
+        // Parallel to an axis, inside its slab
+        assert!(bbox.hit(&ray(vec3(0.5, -5.0, 0.5), vec3(0.0, 1.0, 0.0)), universe));
+    }
+}
@@ -0,0 +1,656 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    hash::Hasher,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    aov::{Aov, AovSample},
+    color::{self, Color},
+    framebuffer::Framebuffer,
+};
+
+/// Running sums of the samples taken for every pixel of an image. Rendering can add samples in
+/// any number of passes, and the current estimate of the image can be read between them.
+///
+/// Besides the colors the squared luminance of the samples is summed up, which gives the variance
+/// of every pixel and with it an estimate of how far its average still is from the converged
+/// value.
+///
+/// The image itself is made from separate sums of the samples weighted by the reconstruction
+/// filter, which spreads samples over the pixels around the one they were taken for.
+///
+/// Accumulators created with [`Accumulator::with_aovs`] also sum up the AOVs of every sample.
+#[derive(Clone)]
+pub struct Accumulator {
+    width: u32,
+    height: u32,
+    sums: Vec<Color>,
+    squares: Vec<f64>,
+    counts: Vec<u32>,
+    filtered_sums: Vec<Color>,
+    filter_weights: Vec<f64>,
+    aovs: Option<Vec<AovSample>>,
+}
+
+/// Pixels of one row of an [`Accumulator`], handed out to the render threads
+pub(crate) struct AccumulatorRow<'a> {
+    sums: &'a mut [Color],
+    squares: &'a mut [f64],
+    pub counts: &'a mut [u32],
+    aovs: Option<&'a mut [AovSample]>,
+}
+
+impl AccumulatorRow<'_> {
+    /// Adds a sample to the sums of pixel `i`. The sample count has to be updated separately.
+    pub fn add(&mut self, i: usize, sample: Color) {
+        self.sums[i] += sample;
+        self.squares[i] += sample.luminance() * sample.luminance();
+    }
+
+    /// Whether the accumulator keeps AOVs
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+
+    /// Adds the AOVs of a sample to the sums of pixel `i`, if the accumulator keeps AOVs.
+    pub fn add_aovs(&mut self, i: usize, sample: &AovSample) {
+        if let Some(aovs) = &mut self.aovs {
+            aovs[i].add(sample);
+        }
+    }
+
+    /// Estimated error of pixel `i`, see [`Accumulator::error`]
+    pub fn error(&self, i: usize) -> f64 {
+        estimated_error(&self.sums[i], self.squares[i], self.counts[i])
+    }
+}
+
+/// Filtered samples taken for one scanline, spread over the rows around it
+pub(crate) struct Splats {
+    width: u32,
+    first_row: u32,
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+}
+
+impl Splats {
+    /// Creates empty sums for the rows `first_row..=last_row` of an image `width` pixels wide
+    pub fn new(width: u32, first_row: u32, last_row: u32) -> Splats {
+        let pixel_count = (width * (last_row + 1 - first_row)) as usize;
+        Splats {
+            width,
+            first_row,
+            sums: vec![Color::black(); pixel_count],
+            weights: vec![0.0; pixel_count],
+        }
+    }
+
+    /// Adds a sample with the filter weight it has for pixel `x`, `y`.
+    pub fn add(&mut self, x: u32, y: u32, sample: Color, weight: f64) {
+        let index = ((y - self.first_row) * self.width + x) as usize;
+        self.sums[index] += weight * sample;
+        self.weights[index] += weight;
+    }
+}
+
+/// Filtered sums of an [`Accumulator`], which the splats of every scanline are added to.
+///
+/// Splats are added in the order of their scanlines, whichever order the scanlines finish in,
+/// so the sums come out the same however many threads render the image.
+pub(crate) struct SplatTarget<'a> {
+    width: u32,
+    sums: &'a mut [Color],
+    weights: &'a mut [f64],
+    next_row: usize,
+    pending: BTreeMap<usize, Splats>,
+}
+
+impl SplatTarget<'_> {
+    /// Adds the splats of scanline `row` once the splats of all scanlines above it are in.
+    pub fn add(&mut self, row: usize, splats: Splats) {
+        self.pending.insert(row, splats);
+        while let Some(splats) = self.pending.remove(&self.next_row) {
+            let offset = (splats.first_row * self.width) as usize;
+            let sums = &mut self.sums[offset..offset + splats.sums.len()];
+            for (sum, splat) in sums.iter_mut().zip(&splats.sums) {
+                *sum += *splat;
+            }
+            let weights = &mut self.weights[offset..offset + splats.weights.len()];
+            for (weight, splat) in weights.iter_mut().zip(&splats.weights) {
+                *weight += *splat;
+            }
+            self.next_row += 1;
+        }
+    }
+}
+
+impl Accumulator {
+    /// Creates an accumulator without any samples for an image of the given dimensions
+    pub fn new(width: u32, height: u32) -> Accumulator {
+        let pixel_count = (width * height) as usize;
+        Accumulator {
+            width,
+            height,
+            sums: vec![Color::black(); pixel_count],
+            squares: vec![0.0; pixel_count],
+            counts: vec![0; pixel_count],
+            filtered_sums: vec![Color::black(); pixel_count],
+            filter_weights: vec![0.0; pixel_count],
+            aovs: None,
+        }
+    }
+
+    /// Creates an accumulator that also keeps the [`Aov`]s of the samples.
+    pub fn with_aovs(width: u32, height: u32) -> Accumulator {
+        Accumulator {
+            aovs: Some(vec![AovSample::default(); (width * height) as usize]),
+            ..Accumulator::new(width, height)
+        }
+    }
+
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Number of samples taken for the pixel
+    pub fn sample_count(&self, x: u32, y: u32) -> u32 {
+        self.counts[(y * self.width + x) as usize]
+    }
+
+    /// Estimated error of the pixel's average, as the standard error of its luminance after the
+    /// sRGB encoding of the output, before any exposure or tone mapping. Pixels with less than
+    /// two samples have an infinite error.
+    pub fn error(&self, x: u32, y: u32) -> f64 {
+        let index = (y * self.width + x) as usize;
+        estimated_error(&self.sums[index], self.squares[index], self.counts[index])
+    }
+
+    /// Variance of the luminance of the pixel's average, which shrinks with the number of samples.
+    /// Pixels with less than two samples have an infinite variance.
+    pub fn variance(&self, x: u32, y: u32) -> f64 {
+        let index = (y * self.width + x) as usize;
+        let count = self.counts[index];
+        sample_variance(&self.sums[index], self.squares[index], count) / count as f64
+    }
+
+    /// Lowest number of samples taken for any pixel
+    pub fn min_sample_count(&self) -> u32 {
+        self.counts.iter().copied().min().unwrap_or(0)
+    }
+
+    /// Number of samples taken for all pixels together
+    pub fn total_sample_count(&self) -> u64 {
+        self.counts.iter().map(|&count| count as u64).sum()
+    }
+
+    /// Returns the current estimate of the image, the filtered average of the samples around
+    /// every pixel. Pixels without samples are black.
+    pub fn average(&self) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(self.width, self.height);
+        for (index, pixel) in framebuffer.pixels_mut().iter_mut().enumerate() {
+            let (weight, count) = (self.filter_weights[index], self.counts[index]);
+            if weight > 0.0 {
+                *pixel = (1.0 / weight) * self.filtered_sums[index];
+            } else if count > 0 {
+                // Filters with negative lobes can cancel out, unfiltered samples are better than
+                // nothing then.
+                *pixel = (1.0 / count as f64) * self.sums[index];
+            }
+        }
+        framebuffer
+    }
+
+    /// Returns the average of an AOV over the samples of every pixel, or `None` if the accumulator
+    /// doesn't keep AOVs. Unlike the image, AOVs aren't filtered.
+    pub fn aov(&self, aov: Aov) -> Option<Framebuffer> {
+        let sums = self.aovs.as_ref()?;
+        let mut framebuffer = Framebuffer::new(self.width, self.height);
+        for ((pixel, sum), &count) in framebuffer
+            .pixels_mut()
+            .iter_mut()
+            .zip(sums)
+            .zip(&self.counts)
+        {
+            if count > 0 {
+                *pixel = sum.average(aov, count);
+            }
+        }
+        Some(framebuffer)
+    }
+
+    /// Returns an image showing how many samples every pixel took, from dark blue for the fewest
+    /// to red for the most.
+    pub fn sample_heatmap(&self) -> Framebuffer {
+        const STOPS: [Color; 5] = [
+            Color::new(0.0, 0.0, 0.5),
+            Color::new(0.0, 0.0, 1.0),
+            Color::new(0.0, 1.0, 1.0),
+            Color::new(1.0, 1.0, 0.0),
+            Color::new(1.0, 0.0, 0.0),
+        ];
+
+        let min = self.min_sample_count();
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+        let mut framebuffer = Framebuffer::new(self.width, self.height);
+        for (pixel, &count) in framebuffer.pixels_mut().iter_mut().zip(&self.counts) {
+            let t = if max > min {
+                (count - min) as f64 / (max - min) as f64
+            } else {
+                0.0
+            };
+            let position = t * (STOPS.len() - 1) as f64;
+            let stop = usize::min(position as usize, STOPS.len() - 2);
+            let f = position - stop as f64;
+            *pixel = (1.0 - f) * STOPS[stop] + f * STOPS[stop + 1];
+        }
+        framebuffer
+    }
+
+    /// Splits the accumulator into its rows from top to bottom, and the filtered sums the splats
+    /// of the rows go to.
+    pub(crate) fn pass_mut(
+        &mut self,
+    ) -> (impl Iterator<Item = AccumulatorRow<'_>>, SplatTarget<'_>) {
+        let width = self.width as usize;
+        let mut aov_rows = self.aovs.as_mut().map(|aovs| aovs.chunks_mut(width));
+        let rows = self
+            .sums
+            .chunks_mut(width)
+            .zip(self.squares.chunks_mut(width))
+            .zip(self.counts.chunks_mut(width))
+            .map(move |((sums, squares), counts)| AccumulatorRow {
+                sums,
+                squares,
+                counts,
+                aovs: aov_rows.as_mut().and_then(|rows| rows.next()),
+            });
+        let target = SplatTarget {
+            width: self.width,
+            sums: &mut self.filtered_sums,
+            weights: &mut self.filter_weights,
+            next_row: 0,
+            pending: BTreeMap::new(),
+        };
+        (rows, target)
+    }
+
+    /// Writes the dimensions and the exact sums and counts of all pixels in little endian byte
+    /// order, followed by the AOV sums if there are any.
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(&self.width.to_le_bytes())?;
+        out.write_all(&self.height.to_le_bytes())?;
+        out.write_all(&[self.has_aovs() as u8])?;
+        for index in 0..self.counts.len() {
+            let (sum, filtered_sum) = (self.sums[index], self.filtered_sums[index]);
+            for value in [
+                sum.r,
+                sum.g,
+                sum.b,
+                self.squares[index],
+                filtered_sum.r,
+                filtered_sum.g,
+                filtered_sum.b,
+                self.filter_weights[index],
+            ] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+            out.write_all(&self.counts[index].to_le_bytes())?;
+        }
+        for sample in self.aovs.iter().flatten() {
+            for value in sample.to_array() {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads an accumulator in the format of [`Accumulator::write`] from `length` bytes of
+    /// input. The dimensions have to match the length before anything is allocated for them, so
+    /// a corrupt header can't exhaust the memory.
+    fn read(input: &mut impl Read, length: u64) -> io::Result<Accumulator> {
+        let width = read_u32(input)?;
+        let height = read_u32(input)?;
+        let mut has_aovs = [0];
+        input.read_exact(&mut has_aovs)?;
+
+        // Every pixel stores eight sums and its count, and the values of its AOVs if any.
+        let mut pixel_size = 8 * size_of::<f64>() + size_of::<u32>();
+        if has_aovs[0] != 0 {
+            pixel_size += AovSample::VALUES * size_of::<f64>();
+        }
+        let header_size = 2 * size_of::<u32>() + 1;
+        let pixel_count = width as u64 * height as u64;
+        if pixel_count.checked_mul(pixel_size as u64) != length.checked_sub(header_size as u64) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("checkpoint size doesn't match its image dimensions {width}x{height}"),
+            ));
+        }
+
+        let mut accumulator = if has_aovs[0] != 0 {
+            Accumulator::with_aovs(width, height)
+        } else {
+            Accumulator::new(width, height)
+        };
+        for index in 0..pixel_count as usize {
+            let [r, g, b] = [read_f64(input)?, read_f64(input)?, read_f64(input)?];
+            accumulator.sums[index] = Color::new(r, g, b);
+            accumulator.squares[index] = read_f64(input)?;
+            let [r, g, b] = [read_f64(input)?, read_f64(input)?, read_f64(input)?];
+            accumulator.filtered_sums[index] = Color::new(r, g, b);
+            accumulator.filter_weights[index] = read_f64(input)?;
+            accumulator.counts[index] = read_u32(input)?;
+        }
+        for sample in accumulator.aovs.iter_mut().flatten() {
+            let mut values = [0.0; AovSample::VALUES];
+            for value in &mut values {
+                *value = read_f64(input)?;
+            }
+            *sample = AovSample::from_array(values);
+        }
+        Ok(accumulator)
+    }
+}
+
+/// Unbiased variance of a pixel's sample luminances, estimated from their sums. Infinite for less
+/// than two samples.
+fn sample_variance(sum: &Color, square: f64, count: u32) -> f64 {
+    if count < 2 {
+        return f64::INFINITY;
+    }
+    let n = count as f64;
+    let mean = sum.luminance() / n;
+    f64::max(square / n - mean * mean, 0.0) * n / (n - 1.0)
+}
+
+/// Standard error of a pixel's sRGB encoded luminance, estimated from the sums of its samples.
+fn estimated_error(sum: &Color, square: f64, count: u32) -> f64 {
+    let variance = sample_variance(sum, square, count);
+    if variance == 0.0 || variance.is_infinite() {
+        return variance;
+    }
+    let n = count as f64;
+    let mean = sum.luminance() / n;
+
+    // Small errors of the linear mean scale with the slope of the encoding at the mean.
+    color::srgb_slope(mean) * f64::sqrt(variance / n)
+}
+
+/// Marks checkpoint files and the version of their layout
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCHKPT4";
+
+/// Identifies the render an accumulator belongs to when saving it as a checkpoint, from which a
+/// later run can continue to add samples.
+///
+/// Every sample is seeded from the camera seed and its index within the pixel, so the seed and
+/// the sample counts are all the random state there is. A resumed render ends up with exactly the
+/// image an uninterrupted one would have produced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Seed the samples were taken with
+    pub seed: u64,
+    /// Identifies the scene and settings, see [`SceneHasher`]
+    pub scene_hash: u64,
+}
+
+impl Checkpoint {
+    /// Writes the accumulated samples to `path`. The data goes to a temporary file next to it
+    /// first, which then replaces the old checkpoint, so a crash while saving doesn't lose it.
+    pub fn save(&self, path: impl AsRef<Path>, accumulator: &Accumulator) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut partial_name = path.file_name().unwrap_or_default().to_owned();
+        partial_name.push(".partial");
+        let partial_path = path.with_file_name(partial_name);
+
+        let mut out = BufWriter::new(File::create(&partial_path)?);
+        out.write_all(CHECKPOINT_MAGIC)?;
+        out.write_all(&self.seed.to_le_bytes())?;
+        out.write_all(&self.scene_hash.to_le_bytes())?;
+        accumulator.write(&mut out)?;
+        out.into_inner()?.sync_all()?;
+
+        fs::rename(&partial_path, path)
+    }
+
+    /// Reads a checkpoint written by [`Checkpoint::save`] together with its samples.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<(Checkpoint, Accumulator)> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a render checkpoint",
+            ));
+        }
+
+        let checkpoint = Checkpoint {
+            seed: read_u64(&mut input)?,
+            scene_hash: read_u64(&mut input)?,
+        };
+        let header_size = (CHECKPOINT_MAGIC.len() + 2 * size_of::<u64>()) as u64;
+        let accumulator = Accumulator::read(&mut input, length.saturating_sub(header_size))?;
+        Ok((checkpoint, accumulator))
+    }
+}
+
+/// 64 bit FNV-1a hash, which unlike the standard library hashers is the same in every build, so
+/// hashes stored in checkpoints stay comparable.
+pub struct SceneHasher(u64);
+
+impl Default for SceneHasher {
+    fn default() -> Self {
+        SceneHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for SceneHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f64(input: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("{name}-{}", process::id()))
+    }
+
+    #[test]
+    fn average_skips_pixels_without_samples() {
+        let mut accumulator = Accumulator::new(2, 1);
+        accumulator.sums[0] = Color::new(3.0, 6.0, 9.0);
+        accumulator.counts[0] = 3;
+
+        let image = accumulator.average();
+        let pixel = image.pixel(0, 0);
+        assert_eq!([pixel.r, pixel.g, pixel.b], [1.0, 2.0, 3.0]);
+        let pixel = image.pixel(1, 0);
+        assert_eq!([pixel.r, pixel.g, pixel.b], [0.0, 0.0, 0.0]);
+        assert_eq!(accumulator.min_sample_count(), 0);
+        assert_eq!(accumulator.total_sample_count(), 3);
+    }
+
+    #[test]
+    fn error_shrinks_with_samples() {
+        let mut accumulator = Accumulator::new(1, 1);
+        let mut errors = Vec::new();
+        for sample in 0..64 {
+            let value = if sample % 2 == 0 { 0.2 } else { 0.6 };
+            let mut row = accumulator.pass_mut().0.next().unwrap();
+            row.add(0, Color::new(value, value, value));
+            row.counts[0] += 1;
+            errors.push(accumulator.error(0, 0));
+        }
+
+        assert_eq!(errors[0], f64::INFINITY);
+        assert!(errors[3] > errors[15] && errors[15] > errors[63]);
+        // Standard error of the mean 0.4 with a standard deviation of 0.2, scaled by the slope
+        // of the sRGB encoding at the mean.
+        let slope = 1.055 / 2.4 * f64::powf(0.4, 1.0 / 2.4 - 1.0);
+        let expected = slope * 0.2 * f64::sqrt(64.0 / 63.0) / 8.0;
+        assert!((errors[63] - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn error_follows_the_srgb_encoding() {
+        for linear in [0.001, 0.003, 0.01, 0.2, 0.9] {
+            let h = 1e-7;
+            let difference =
+                (color::linear_to_srgb(linear + h) - color::linear_to_srgb(linear - h)) / (2.0 * h);
+            let slope = color::srgb_slope(linear);
+            assert!((difference - slope).abs() < 1e-3 * slope, "{linear}");
+        }
+
+        // Dark pixels are in the linear part of the curve, without blowing up the error.
+        let mut accumulator = Accumulator::new(1, 1);
+        let mut row = accumulator.pass_mut().0.next().unwrap();
+        for value in [0.0, 0.002] {
+            row.add(0, Color::new(value, value, value));
+        }
+        row.counts[0] = 2;
+        let expected = 12.92 * f64::sqrt(0.002 * 0.002 / 2.0) / f64::sqrt(2.0);
+        assert!((accumulator.error(0, 0) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let mut accumulator = Accumulator::with_aovs(3, 2);
+        accumulator.filter_weights = vec![0.5, 1.0, 1.5, 2.0, 2.5, 3.0];
+        for (index, sample) in accumulator.aovs.iter_mut().flatten().enumerate() {
+            sample.depth = index as f64 + 0.5;
+            sample.material_id = index as u32;
+        }
+        for (j, row) in accumulator.pass_mut().0.enumerate() {
+            for i in 0..3 {
+                row.sums[i] = Color::new(0.1 * i as f64, 1.0 / 3.0, j as f64);
+                row.squares[i] = 0.25 * i as f64;
+                row.counts[i] = (i + j) as u32;
+            }
+        }
+        let checkpoint = Checkpoint {
+            seed: 42,
+            scene_hash: 0x1234_5678_9abc_def0,
+        };
+
+        let path = temp_path("checkpoint_round_trip");
+        checkpoint.save(&path, &accumulator).unwrap();
+        let (loaded, loaded_accumulator) = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded_accumulator.counts, accumulator.counts);
+        assert_eq!(loaded_accumulator.squares, accumulator.squares);
+        assert_eq!(
+            loaded_accumulator.filter_weights,
+            accumulator.filter_weights
+        );
+        let channels = |a: &Accumulator| -> Vec<[u64; 3]> {
+            a.sums
+                .iter()
+                .map(|c| [c.r.to_bits(), c.g.to_bits(), c.b.to_bits()])
+                .collect()
+        };
+        assert_eq!(channels(&loaded_accumulator), channels(&accumulator));
+        let aov_values = |a: &Accumulator| -> Vec<[f64; 2]> {
+            a.aovs
+                .iter()
+                .flatten()
+                .map(|sample| [sample.depth, sample.material_id as f64])
+                .collect()
+        };
+        assert_eq!(aov_values(&loaded_accumulator), aov_values(&accumulator));
+    }
+
+    #[test]
+    fn load_rejects_other_files() {
+        let path = temp_path("load_rejects_other_files");
+        fs::write(&path, b"P3\n1 1\n255\n0 0 0\n").unwrap();
+        let error = Checkpoint::load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn load_rejects_corrupt_headers() {
+        let path = temp_path("load_rejects_corrupt_headers");
+        let checkpoint = Checkpoint {
+            seed: 1,
+            scene_hash: 2,
+        };
+        checkpoint
+            .save(&path, &Accumulator::with_aovs(4, 3))
+            .unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let load = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            Checkpoint::load(&path).map(|(_, accumulator)| accumulator)
+        };
+        assert_eq!(load(&bytes).unwrap().average().width(), 4);
+
+        // Dimensions far beyond the file, which must not be allocated.
+        let dimensions = 24..32;
+        let mut huge = bytes.clone();
+        huge[dimensions.clone()].fill(0xff);
+        // The same number of pixels, but claiming that the file has no AOVs.
+        let mut without_aovs = bytes.clone();
+        without_aovs[dimensions.clone()].copy_from_slice(&[2, 0, 0, 0, 6, 0, 0, 0]);
+        without_aovs[32] = 0;
+        let mut larger = bytes.clone();
+        larger[dimensions].copy_from_slice(&[5, 0, 0, 0, 3, 0, 0, 0]);
+
+        for corrupt in [&huge, &without_aovs, &larger, &bytes[..bytes.len() - 1]] {
+            let error = load(corrupt).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn scene_hasher_is_fnv1a() {
+        let mut hasher = SceneHasher::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+}
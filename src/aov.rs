@@ -0,0 +1,389 @@
+use std::{any::Any, collections::HashMap, io, path::Path, str::FromStr, sync::Arc};
+
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec,
+    WritableImage,
+};
+use image::{ImageError, ImageResult};
+
+use crate::{
+    bvh::BvhNode,
+    color::Color,
+    constant_medium::ConstantMedium,
+    framebuffer::Framebuffer,
+    hittable_list::{HittableList, RotateY, Transform, Translate},
+    material::Material,
+    quad::Quad,
+    sphere::{Hittable, Sphere},
+    triangle::{Triangle, TriangleMesh},
+    vec3::{vec3, Vec3},
+};
+
+/// Arbitrary output variable, an extra image rendered alongside the beauty image from the first
+/// surface every camera ray hits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// Color of the surface without any lighting, from the material's texture
+    Albedo,
+    /// Shading normal, facing towards the camera
+    Normal,
+    /// Distance from the camera
+    Depth,
+    /// Position in world space
+    Position,
+    /// Texture coordinates
+    Uv,
+    /// Number of the material, counted from 1 in the order the materials appear in the world
+    MaterialId,
+    /// Light reflected by the surface straight from light sources or the background
+    Direct,
+    /// Light reflected by the surface after bouncing off other surfaces first
+    Indirect,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 8] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::Uv,
+        Aov::MaterialId,
+        Aov::Direct,
+        Aov::Indirect,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Uv => "uv",
+            Aov::MaterialId => "material_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    /// Names of the channels the AOV is made of, stored in the red, green and blue components of
+    /// its framebuffer in this order
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo | Aov::Direct | Aov::Indirect => &["R", "G", "B"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::Uv => &["U", "V"],
+            Aov::MaterialId => &["ID"],
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "albedo" => Ok(Aov::Albedo),
+            "normal" => Ok(Aov::Normal),
+            "depth" => Ok(Aov::Depth),
+            "position" => Ok(Aov::Position),
+            "uv" => Ok(Aov::Uv),
+            "material_id" | "material-id" | "id" => Ok(Aov::MaterialId),
+            "direct" => Ok(Aov::Direct),
+            "indirect" => Ok(Aov::Indirect),
+            _ => Err(format!(
+                "unknown AOV `{s}`, expected one of albedo, normal, depth, position, uv, \
+                 material_id, direct, indirect"
+            )),
+        }
+    }
+}
+
+/// Values of all AOVs for one camera sample, or summed up over the samples of a pixel. The
+/// material ID isn't summed up, a pixel keeps the ID of its first sample that hits a surface.
+///
+/// Camera rays that miss everything have the background color as albedo and zero everywhere
+/// else.
+#[derive(Clone, Copy, Default)]
+pub struct AovSample {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub depth: f64,
+    pub position: Vec3,
+    pub uv: (f64, f64),
+    pub material_id: u32,
+    pub direct: Color,
+    pub indirect: Color,
+}
+
+impl AovSample {
+    /// Adds the values of another sample to these sums.
+    pub fn add(&mut self, sample: &AovSample) {
+        self.albedo += sample.albedo;
+        self.normal = self.normal + sample.normal;
+        self.depth += sample.depth;
+        self.position = self.position + sample.position;
+        self.uv = (self.uv.0 + sample.uv.0, self.uv.1 + sample.uv.1);
+        if self.material_id == 0 {
+            self.material_id = sample.material_id;
+        }
+        self.direct += sample.direct;
+        self.indirect += sample.indirect;
+    }
+
+    /// Value of one AOV as a color, averaged over `count` summed up samples
+    pub fn average(&self, aov: Aov, count: u32) -> Color {
+        let scale = 1.0 / count as f64;
+        let from_vec3 = |v: &Vec3| Color::new(scale * v.x, scale * v.y, scale * v.z);
+        match aov {
+            Aov::Albedo => scale * self.albedo,
+            Aov::Normal => from_vec3(&self.normal),
+            Aov::Depth => Color::new(scale * self.depth, 0.0, 0.0),
+            Aov::Position => from_vec3(&self.position),
+            Aov::Uv => Color::new(scale * self.uv.0, scale * self.uv.1, 0.0),
+            Aov::MaterialId => Color::new(self.material_id as f64, 0.0, 0.0),
+            Aov::Direct => scale * self.direct,
+            Aov::Indirect => scale * self.indirect,
+        }
+    }
+
+    /// Number of values [`AovSample::to_array`] stores
+    pub(crate) const VALUES: usize = 19;
+
+    /// All values in a fixed order, for storing them in a checkpoint
+    pub(crate) fn to_array(self) -> [f64; AovSample::VALUES] {
+        let [albedo, direct, indirect] = [self.albedo, self.direct, self.indirect];
+        let [normal, position] = [self.normal, self.position];
+        [
+            albedo.r,
+            albedo.g,
+            albedo.b,
+            normal.x,
+            normal.y,
+            normal.z,
+            self.depth,
+            position.x,
+            position.y,
+            position.z,
+            self.uv.0,
+            self.uv.1,
+            self.material_id as f64,
+            direct.r,
+            direct.g,
+            direct.b,
+            indirect.r,
+            indirect.g,
+            indirect.b,
+        ]
+    }
+
+    /// Reverses [`AovSample::to_array`].
+    pub(crate) fn from_array(values: [f64; AovSample::VALUES]) -> AovSample {
+        let [r, g, b, nx, ny, nz, depth, px, py, pz, u, v, id, dr, dg, db, ir, ig, ib] = values;
+        AovSample {
+            albedo: Color::new(r, g, b),
+            normal: vec3(nx, ny, nz),
+            depth,
+            position: vec3(px, py, pz),
+            uv: (u, v),
+            material_id: id as u32,
+            direct: Color::new(dr, dg, db),
+            indirect: Color::new(ir, ig, ib),
+        }
+    }
+}
+
+/// Numbers of the materials in a world, counted from 1 in the order a walk through the world's
+/// objects finds them. The background and materials of object types from outside this crate have
+/// number 0.
+#[derive(Default)]
+pub struct MaterialIds {
+    ids: HashMap<usize, u32>,
+}
+
+impl MaterialIds {
+    pub fn new(world: &dyn Hittable) -> MaterialIds {
+        let mut ids = MaterialIds::default();
+        ids.collect(world);
+        ids
+    }
+
+    pub fn get(&self, mat: &Arc<dyn Material>) -> u32 {
+        self.ids.get(&material_key(mat)).copied().unwrap_or(0)
+    }
+
+    /// Number of distinct materials found
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    fn insert(&mut self, mat: &Arc<dyn Material>) {
+        let next_id = self.ids.len() as u32 + 1;
+        self.ids.entry(material_key(mat)).or_insert(next_id);
+    }
+
+    fn collect(&mut self, object: &dyn Hittable) {
+        let object = object as &dyn Any;
+        if let Some(bvh) = object.downcast_ref::<BvhNode>() {
+            for object in bvh.objects() {
+                self.collect(&**object);
+            }
+        } else if let Some(list) = object.downcast_ref::<HittableList>() {
+            for object in &list.objects {
+                self.collect(&**object);
+            }
+        } else if let Some(translate) = object.downcast_ref::<Translate>() {
+            self.collect(translate.object());
+        } else if let Some(rotate) = object.downcast_ref::<RotateY>() {
+            self.collect(rotate.object());
+        } else if let Some(transform) = object.downcast_ref::<Transform>() {
+            self.collect(transform.object());
+        } else if let Some(medium) = object.downcast_ref::<ConstantMedium>() {
+            // Hits inside the medium report its phase function, never the boundary's material.
+            self.insert(medium.phase_function());
+        } else if let Some(sphere) = object.downcast_ref::<Sphere>() {
+            self.insert(sphere.material());
+        } else if let Some(quad) = object.downcast_ref::<Quad>() {
+            self.insert(quad.material());
+        } else if let Some(triangle) = object.downcast_ref::<Triangle>() {
+            self.insert(triangle.material());
+        } else if let Some(mesh) = object.downcast_ref::<TriangleMesh>() {
+            self.insert(mesh.material());
+        }
+    }
+}
+
+/// Identifies a material by the address it's shared at
+fn material_key(mat: &Arc<dyn Material>) -> usize {
+    Arc::as_ptr(mat) as *const () as usize
+}
+
+/// Writes the beauty image and AOVs as layers of one OpenEXR file. The beauty image makes up the
+/// plain `R`, `G` and `B` channels, the AOVs channels prefixed with their name like `albedo.R`.
+pub fn save_exr_layers(
+    path: impl AsRef<Path>,
+    beauty: &Framebuffer,
+    aovs: &[(Aov, Framebuffer)],
+) -> ImageResult<()> {
+    let channel = |name: String, image: &Framebuffer, component: usize| {
+        let samples = image
+            .pixels()
+            .iter()
+            .map(|color| [color.r, color.g, color.b][component] as f32)
+            .collect();
+        AnyChannel::new(name.as_str(), FlatSamples::F32(samples))
+    };
+
+    let mut channels = SmallVec::new();
+    for (component, name) in ["R", "G", "B"].into_iter().enumerate() {
+        channels.push(channel(name.to_string(), beauty, component));
+    }
+    for (aov, image) in aovs {
+        for (component, name) in aov.channels().iter().enumerate() {
+            channels.push(channel(format!("{}.{name}", aov.name()), image, component));
+        }
+    }
+
+    let size = (beauty.width() as usize, beauty.height() as usize);
+    let layer = Layer::new(
+        size,
+        LayerAttributes::default(),
+        Encoding::SMALL_LOSSLESS,
+        AnyChannels::sort(channels),
+    );
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(|error| ImageError::IoError(io::Error::other(error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn names_round_trip() {
+        for aov in Aov::ALL {
+            assert_eq!(aov.name().parse::<Aov>(), Ok(aov));
+        }
+    }
+
+    #[test]
+    fn exr_layers_have_prefixed_channels() {
+        let mut beauty = Framebuffer::new(2, 1);
+        beauty.pixels_mut()[1] = Color::new(0.25, 0.5, 1.0);
+        let mut depth = Framebuffer::new(2, 1);
+        depth.pixels_mut()[0] = Color::new(3.0, 0.0, 0.0);
+
+        let path = std::env::temp_dir().join(format!("aov-layers-{}.exr", std::process::id()));
+        save_exr_layers(&path, &beauty, &[(Aov::Depth, depth)]).unwrap();
+        let image = exr::prelude::read_first_flat_layer_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let channels: Vec<(String, Vec<f32>)> = image
+            .layer_data
+            .channel_data
+            .list
+            .iter()
+            .map(|channel| {
+                let values = channel.sample_data.values_as_f32().collect();
+                (channel.name.to_string(), values)
+            })
+            .collect();
+        assert_eq!(
+            channels,
+            [
+                ("B".to_string(), vec![0.0, 1.0]),
+                ("G".to_string(), vec![0.0, 0.5]),
+                ("R".to_string(), vec![0.0, 0.25]),
+                ("depth.Z".to_string(), vec![3.0, 0.0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn material_ids_follow_scene_order() {
+        let red: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(1.0, 0.0, 0.0)));
+        let green: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.0, 1.0, 0.0)));
+        let other: Arc<dyn Material> = Arc::new(Lambertian::new(Color::white()));
+        let sphere =
+            |x, mat: &Arc<dyn Material>| Arc::new(Sphere::new(vec3(x, 0.0, 0.0), 1.0, mat.clone()));
+
+        let mut group = HittableList::default();
+        group.add(sphere(1.0, &green));
+        group.add(sphere(2.0, &red));
+        let mut world = HittableList::default();
+        world.add(sphere(0.0, &red));
+        world.add(Arc::new(Translate::new(
+            Arc::new(BvhNode::from_list(&mut group)),
+            Vec3::default(),
+        )));
+
+        let ids = MaterialIds::new(&world);
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids.get(&red), 1);
+        assert_eq!(ids.get(&green), 2);
+        assert_eq!(ids.get(&other), 0);
+    }
+
+    #[test]
+    fn material_id_comes_from_the_first_hit() {
+        let mut sums = AovSample::default();
+        let hit = |material_id| AovSample {
+            depth: 2.0,
+            material_id,
+            ..Default::default()
+        };
+        sums.add(&AovSample::default());
+        sums.add(&hit(3));
+        sums.add(&hit(5));
+        assert_eq!(sums.average(Aov::MaterialId, 3).r, 3.0);
+        assert_eq!(sums.average(Aov::Depth, 4).r, 1.0);
+    }
+}
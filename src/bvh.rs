@@ -1,124 +1,669 @@
-use std::{cmp::Ordering, rc::Rc};
+use std::{any::Any, fmt, str::FromStr, sync::Arc};
 
 use crate::{
     aabb::Aabb,
-    hittable_list::HittableList,
+    constant_medium::ConstantMedium,
+    hittable_list::{HittableList, RotateY, Transform, Translate},
     interval::Interval,
     ray::Ray,
-    rtweekend::random_int_from,
+    sampler::{sample_index, Dimension},
     sphere::{HitRecord, Hittable},
+    stats::{self, Counter},
+    vec3::{vec3, Vec3},
 };
 
-/// Singular node of bounding volume hierarchy
+/// Most objects a leaf of a [`BvhNode`] holds
+const MAX_OBJECTS_IN_LEAF: usize = 4;
+
+/// Depth below which nodes are split at the median even with the surface area heuristic, so
+/// that the depth stays within the traversal stack
+const MAX_SAH_DEPTH: usize = 32;
+
+/// Size of the traversal stack, enough for the deepest hierarchy the builder produces
+const STACK_SIZE: usize = 64;
+
+/// Number of buckets the object centroids are sorted into when evaluating SAH splits
+const SAH_BINS: usize = 16;
+
+/// Cost of testing a ray against a node's box, relative to intersecting one object
+const TRAVERSAL_COST: f64 = 0.5;
+
+/// Cost of intersecting a ray with one object
+const INTERSECTION_COST: f64 = 1.0;
+
+/// Strategy for dividing the objects of a node between its two children
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SplitMethod {
+    /// Sort along the longest axis of the node and split at the median object.
+    Median,
+    /// Pick the split with the lowest cost estimated by the surface area heuristic, which
+    /// weighs the number of objects on each side by the chance of a ray hitting that side.
+    #[default]
+    Sah,
+}
+
+impl FromStr for SplitMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "median" => Ok(SplitMethod::Median),
+            "sah" => Ok(SplitMethod::Sah),
+            _ => Err(format!(
+                "unknown split method `{s}`, expected one of median, sah"
+            )),
+        }
+    }
+}
+
+/// Bounding volume hierarchy over a list of objects.
+///
+/// The nodes are stored in a flat array and traversed without recursion, visiting the child
+/// closer to the ray origin first. Leaves hold up to a few objects.
 pub struct BvhNode {
-    pub left: Rc<dyn Hittable>,
-    pub right: Rc<dyn Hittable>,
-    pub bbox: Aabb,
+    objects: Vec<Arc<dyn Hittable>>,
+    hierarchy: FlatBvh,
 }
 
 impl BvhNode {
-    /// Create new node of bounding volume hierarchy
+    /// Create new bounding volume hierarchy, split with the default [`SplitMethod`]
     ///
     /// # Arguments
     ///
     /// * `objects` - Array of hittable objects
-    /// * `start` - Start index of objects contained within node
-    /// * `end` - End index of objects contained within node
-    pub fn new(
-        objects: &mut Vec<Rc<dyn Hittable>>,
-        start: usize,
-        end: usize,
-        level: i32,
-    ) -> BvhNode {
-        // Build the bounding box of the span of source objects.
-        let mut bbox = Aabb::empty();
+    /// * `start` - Start index of objects contained within the hierarchy
+    /// * `end` - End index of objects contained within the hierarchy
+    pub fn new(objects: &mut [Arc<dyn Hittable>], start: usize, end: usize) -> BvhNode {
+        BvhNode::with_split(&objects[start..end], SplitMethod::default())
+    }
+
+    /// Constructs a BVH from a hittable list instance, split with the default [`SplitMethod`]
+    pub fn from_list(list: &mut HittableList) -> BvhNode {
+        BvhNode::with_split(&list.objects, SplitMethod::default())
+    }
 
-        // Optimization that just don't seem to work
-        for object_index in start..end {
-            bbox = Aabb::from_aabb(&bbox, objects[object_index].bounding_box());
+    /// Create new bounding volume hierarchy
+    ///
+    /// # Arguments
+    ///
+    /// * `objects` - Objects contained within the hierarchy
+    /// * `method` - How the objects are divided between the children of each node
+    pub fn with_split(objects: &[Arc<dyn Hittable>], method: SplitMethod) -> BvhNode {
+        let bounds: Vec<Aabb> = objects
+            .iter()
+            .map(|object| *object.bounding_box())
+            .collect();
+        let (hierarchy, order) = FlatBvh::build(&bounds, method, MAX_OBJECTS_IN_LEAF);
+
+        BvhNode {
+            objects: order
+                .iter()
+                .map(|&index| objects[index as usize].clone())
+                .collect(),
+            hierarchy,
         }
+    }
+
+    /// Objects of the hierarchy, in the order of the leaves
+    pub fn objects(&self) -> &[Arc<dyn Hittable>] {
+        &self.objects
+    }
+
+    /// Collects the statistics of the hierarchy.
+    pub fn stats(&self) -> BvhStats {
+        self.hierarchy.stats()
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        self.hierarchy.hit(r, ray_t, |index, interval| {
+            self.objects[index].hit(r, interval, rec).then_some(rec.t)
+        })
+    }
 
-        // Select axis for splitting
-        let axis = bbox.longest_axis();
+    fn bounding_box(&self) -> &Aabb {
+        self.hierarchy.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
 
-        // Select comparator depending on selected axis
-        let comparator = match axis {
-            0 => Self::box_x_compare,
-            1 => Self::box_y_compare,
-            _ => Self::box_z_compare,
+        // Every object is picked with the same probability by `random`.
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(origin, direction))
+            .sum()
+    }
+
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        if self.objects.is_empty() {
+            return vec3(1.0, 0.0, 0.0);
+        }
+
+        let index = sample_index(Dimension::LightSelect, self.objects.len());
+        self.objects[index].random(origin)
+    }
+}
+
+/// Node of a flattened bounding volume hierarchy.
+///
+/// Interior nodes store their first child right after themselves and the second child at
+/// `offset`, leaves reference `count` consecutive primitives starting at `offset`.
+struct FlatNode {
+    bbox: Aabb,
+    offset: u32,
+    count: u16,
+    axis: u8,
+}
+
+/// Bounding volume hierarchy stored as an array of nodes in depth-first order. The primitives
+/// are kept by the owner of the hierarchy, which refers to them by index.
+pub(crate) struct FlatBvh {
+    nodes: Vec<FlatNode>,
+    bbox: Aabb,
+}
+
+impl FlatBvh {
+    /// Builds the hierarchy over primitives with the given bounding boxes. Returns it together
+    /// with the order the owner has to store the primitives in, so that every leaf covers a
+    /// consecutive range of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `bounds` - Bounding box of every primitive
+    /// * `method` - How the primitives are divided between the children of each node
+    /// * `max_leaf_size` - Most primitives a leaf may hold
+    pub(crate) fn build(
+        bounds: &[Aabb],
+        method: SplitMethod,
+        max_leaf_size: usize,
+    ) -> (FlatBvh, Vec<u32>) {
+        let mut order: Vec<u32> = (0..bounds.len() as u32).collect();
+        let mut builder = FlatBvhBuilder {
+            nodes: Vec::with_capacity(2 * bounds.len() / max_leaf_size + 1),
+            bounds,
+            centroids: bounds.iter().map(Aabb::centroid).collect(),
+            method,
+            max_leaf_size: max_leaf_size.clamp(1, u16::MAX as usize),
         };
+        if !bounds.is_empty() {
+            builder.build_node(&mut order, 0, 1);
+        }
+
+        let bbox = builder
+            .nodes
+            .first()
+            .map_or(Aabb::empty(), |root| root.bbox);
+        let hierarchy = FlatBvh {
+            nodes: builder.nodes,
+            bbox,
+        };
+        (hierarchy, order)
+    }
 
-        // Select left and right nodes depending on length of objects
-        let object_span = end - start;
-
-        match object_span {
-            1 => BvhNode {
-                left: objects[start].clone(),
-                right: objects[start].clone(),
-                bbox,
-            },
-            2 => BvhNode {
-                left: objects[start].clone(),
-                right: objects[start + 1].clone(),
-                bbox,
-            },
-            _ => {
-                objects[start..end].sort_by(comparator);
-                let mid = start + object_span / 2;
-                BvhNode {
-                    left: Rc::new(BvhNode::new(objects, start, mid, level + 1)),
-                    right: Rc::new(BvhNode::new(objects, mid, end, level + 1)),
-                    bbox,
+    pub(crate) fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    /// Finds the closest hit along the ray.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - Ray to trace
+    /// * `ray_t` - Interval of the ray to look for hits in
+    /// * `hit_primitive` - Intersects the primitive of the given index with the ray inside the
+    ///   given interval, returning the ray parameter of the hit
+    pub(crate) fn hit<F>(&self, r: &Ray, ray_t: Interval, mut hit_primitive: F) -> bool
+    where
+        F: FnMut(usize, Interval) -> Option<f64>,
+    {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let direction_is_negative = [
+            r.direction().x < 0.0,
+            r.direction().y < 0.0,
+            r.direction().z < 0.0,
+        ];
+        let mut closest_so_far = ray_t.max;
+        let mut hit_anything = false;
+
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_size = 0;
+        let mut current = 0;
+        let mut visits = 0;
+
+        loop {
+            visits += 1;
+            let node = &self.nodes[current];
+            if node.bbox.hit(r, Interval::new(ray_t.min, closest_so_far)) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    for primitive in first..first + node.count as usize {
+                        let interval = Interval::new(ray_t.min, closest_so_far);
+                        if let Some(t) = hit_primitive(primitive, interval) {
+                            hit_anything = true;
+                            closest_so_far = t;
+                        }
+                    }
+                } else {
+                    // Visit the child closer to the ray origin first, so the far child can be
+                    // culled against the nearest hit found so far.
+                    if direction_is_negative[node.axis as usize] {
+                        stack[stack_size] = current as u32 + 1;
+                        current = node.offset as usize;
+                    } else {
+                        stack[stack_size] = node.offset;
+                        current += 1;
+                    }
+                    stack_size += 1;
+                    continue;
                 }
             }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            current = stack[stack_size] as usize;
         }
+
+        stats::count(Counter::BvhNodeVisits, visits);
+        hit_anything
     }
 
-    /// Constructs a BVH node from a hittable list instance
-    pub fn from_list(list: &mut HittableList) -> BvhNode {
-        let end = list.objects.len();
-        BvhNode::new(&mut list.objects, 0, end, 0)
+    /// Collects the statistics of the hierarchy.
+    pub(crate) fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats::default();
+        if !self.nodes.is_empty() {
+            stats.min_leaf_size = usize::MAX;
+            self.add_stats(&mut stats, 0, 1, self.bbox.surface_area());
+        }
+        stats
+    }
+
+    fn add_stats(&self, stats: &mut BvhStats, index: usize, depth: usize, root_area: f64) {
+        let node = &self.nodes[index];
+        let area = node.bbox.surface_area() / root_area;
+        stats.nodes += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+
+        if node.count > 0 {
+            let count = node.count as usize;
+            stats.leaves += 1;
+            stats.objects += count;
+            stats.min_leaf_size = stats.min_leaf_size.min(count);
+            stats.max_leaf_size = stats.max_leaf_size.max(count);
+            stats.sah_cost += INTERSECTION_COST * area * count as f64;
+        } else {
+            stats.sah_cost += TRAVERSAL_COST * area;
+            self.add_stats(stats, index + 1, depth + 1, root_area);
+            self.add_stats(stats, node.offset as usize, depth + 1, root_area);
+        }
     }
+}
+
+/// State shared by the recursive construction of a [`FlatBvh`]
+struct FlatBvhBuilder<'a> {
+    nodes: Vec<FlatNode>,
+    bounds: &'a [Aabb],
+    centroids: Vec<Vec3>,
+    method: SplitMethod,
+    max_leaf_size: usize,
+}
+
+impl FlatBvhBuilder<'_> {
+    /// Recursively builds the node for the primitives in `order`, which start at index `first`
+    /// of the final order. Returns the index of the created node.
+    fn build_node(&mut self, order: &mut [u32], first: usize, depth: usize) -> usize {
+        let mut bbox = Aabb::empty();
+        // Unlike `Aabb::from_point` this doesn't pad the bounds, so coinciding centroids give an
+        // empty axis.
+        let mut centroid_bounds = Aabb::empty();
+        for &primitive in order.iter() {
+            bbox = Aabb::from_aabb(&bbox, &self.bounds[primitive as usize]);
+            let c = &self.centroids[primitive as usize];
+            let point = Aabb {
+                x: Interval::new(c.x, c.x),
+                y: Interval::new(c.y, c.y),
+                z: Interval::new(c.z, c.z),
+            };
+            centroid_bounds = Aabb::from_aabb(&centroid_bounds, &point);
+        }
 
-    fn box_compare(a: &Rc<dyn Hittable>, b: &Rc<dyn Hittable>, axis_index: i32) -> Ordering {
-        let a_axis_interval = a.bounding_box().axis_interval(axis_index);
-        let b_axis_interval = b.bounding_box().axis_interval(axis_index);
-        a_axis_interval
-            .min
-            .partial_cmp(&b_axis_interval.min)
-            .unwrap()
+        let index = self.nodes.len();
+        self.nodes.push(FlatNode {
+            bbox,
+            offset: first as u32,
+            count: order.len() as u16,
+            axis: 0,
+        });
+
+        let sah_split = match self.method {
+            SplitMethod::Sah if depth < MAX_SAH_DEPTH && order.len() > 1 => {
+                self.sah_split(order, &bbox, &centroid_bounds)
+            }
+            _ => None,
+        };
+        let (axis, mid) = match sah_split {
+            // Splitting has to pay off against intersecting all primitives in a leaf.
+            Some((cost, ..))
+                if order.len() <= self.max_leaf_size
+                    && cost >= INTERSECTION_COST * order.len() as f64 =>
+            {
+                return index;
+            }
+            Some((_, axis, mid)) => (axis, mid),
+            None if order.len() <= self.max_leaf_size => return index,
+            None => self.median_split(order, &centroid_bounds),
+        };
+
+        let (left, right) = order.split_at_mut(mid);
+        self.build_node(left, first, depth + 1);
+        let second = self.build_node(right, first + mid, depth + 1);
+
+        let node = &mut self.nodes[index];
+        node.offset = second as u32;
+        node.count = 0;
+        node.axis = axis as u8;
+        index
     }
 
-    fn box_x_compare(a: &Rc<dyn Hittable>, b: &Rc<dyn Hittable>) -> Ordering {
-        Self::box_compare(a, b, 0)
+    /// Splits the primitives at the median centroid along the longest axis of the centroids.
+    /// Returns the axis and the index of the first primitive on the right side.
+    fn median_split(&self, order: &mut [u32], centroid_bounds: &Aabb) -> (i32, usize) {
+        let axis = centroid_bounds.longest_axis();
+        let mid = order.len() / 2;
+        order.select_nth_unstable_by(mid, |&a, &b| {
+            let a = self.centroids[a as usize].axis(axis);
+            let b = self.centroids[b as usize].axis(axis);
+            a.total_cmp(&b)
+        });
+        (axis, mid)
     }
 
-    fn box_y_compare(a: &Rc<dyn Hittable>, b: &Rc<dyn Hittable>) -> Ordering {
-        Self::box_compare(a, b, 1)
+    /// Orders the primitives by the cheapest split found by binning their centroids. Returns
+    /// the estimated cost of the split, its axis and the index of the first primitive on the
+    /// right side, or `None` if the centroids can't be told apart.
+    fn sah_split(
+        &self,
+        order: &mut [u32],
+        bbox: &Aabb,
+        centroid_bounds: &Aabb,
+    ) -> Option<(f64, i32, usize)> {
+        let bin_of = |primitive: u32, axis: i32| {
+            let interval = centroid_bounds.axis_interval(axis);
+            let offset = self.centroids[primitive as usize].axis(axis) - interval.min;
+            let bin = (SAH_BINS as f64 * offset / interval.size()) as usize;
+            bin.min(SAH_BINS - 1)
+        };
+
+        // (cost, axis, number of bins on the left side)
+        let mut best: Option<(f64, i32, usize)> = None;
+        for axis in 0..3 {
+            if centroid_bounds.axis_interval(axis).size() <= 0.0 {
+                continue;
+            }
+
+            let mut counts = [0usize; SAH_BINS];
+            let mut boxes = [Aabb::empty(); SAH_BINS];
+            for &primitive in order.iter() {
+                let bin = bin_of(primitive, axis);
+                counts[bin] += 1;
+                boxes[bin] = Aabb::from_aabb(&boxes[bin], &self.bounds[primitive as usize]);
+            }
+
+            // Sweep from the right to collect what lies right of each split, then from the left.
+            let mut right_areas = [0.0; SAH_BINS];
+            let mut right_counts = [0usize; SAH_BINS];
+            let (mut right_box, mut right_count) = (Aabb::empty(), 0);
+            for split in (1..SAH_BINS).rev() {
+                right_box = Aabb::from_aabb(&right_box, &boxes[split]);
+                right_count += counts[split];
+                right_areas[split] = right_box.surface_area();
+                right_counts[split] = right_count;
+            }
+
+            let (mut left_box, mut left_count) = (Aabb::empty(), 0);
+            for split in 1..SAH_BINS {
+                left_box = Aabb::from_aabb(&left_box, &boxes[split - 1]);
+                left_count += counts[split - 1];
+                if left_count == 0 || right_counts[split] == 0 {
+                    continue;
+                }
+
+                let cost = TRAVERSAL_COST
+                    + INTERSECTION_COST
+                        * (left_box.surface_area() * left_count as f64
+                            + right_areas[split] * right_counts[split] as f64)
+                        / bbox.surface_area();
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, split));
+                }
+            }
+        }
+
+        let (cost, axis, split) = best?;
+        order.sort_by_key(|&primitive| bin_of(primitive, axis));
+        let mid = order.partition_point(|&primitive| bin_of(primitive, axis) < split);
+        Some((cost, axis, mid))
     }
+}
 
-    fn box_z_compare(a: &Rc<dyn Hittable>, b: &Rc<dyn Hittable>) -> Ordering {
-        Self::box_compare(a, b, 2)
+/// Shape and estimated cost of a bounding volume hierarchy
+#[derive(Debug, Default, Clone)]
+pub struct BvhStats {
+    /// Number of nodes, including the leaves
+    pub nodes: usize,
+    /// Number of nodes holding objects
+    pub leaves: usize,
+    /// Number of objects referenced by the leaves
+    pub objects: usize,
+    /// Number of nodes on the longest path from the root to a leaf
+    pub max_depth: usize,
+    pub min_leaf_size: usize,
+    pub max_leaf_size: usize,
+    /// Expected cost of tracing a ray that hits the root, according to the surface area
+    /// heuristic. Lower is better.
+    pub sah_cost: f64,
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} objects, {} nodes, {} leaves of {}-{} objects (mean {:.2}), depth {}, SAH cost {:.2}",
+            self.objects,
+            self.nodes,
+            self.leaves,
+            self.min_leaf_size,
+            self.max_leaf_size,
+            self.objects as f64 / self.leaves.max(1) as f64,
+            self.max_depth,
+            self.sah_cost
+        )
     }
 }
 
-impl Hittable for BvhNode {
-    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
-        if !self.bbox.hit(r, ray_t) {
-            return false;
+/// Finds the bounding volume hierarchies in a world, looking through lists, instances and media
+/// as well as into the objects of other hierarchies.
+pub fn hierarchies(object: &dyn Hittable) -> Vec<&BvhNode> {
+    let mut found = Vec::new();
+    find_hierarchies(object, &mut found);
+    found
+}
+
+fn find_hierarchies<'a>(object: &'a dyn Hittable, found: &mut Vec<&'a BvhNode>) {
+    let object = object as &dyn Any;
+    if let Some(bvh) = object.downcast_ref::<BvhNode>() {
+        found.push(bvh);
+        for object in &bvh.objects {
+            find_hierarchies(&**object, found);
+        }
+    } else if let Some(list) = object.downcast_ref::<HittableList>() {
+        for object in &list.objects {
+            find_hierarchies(&**object, found);
+        }
+    } else if let Some(translate) = object.downcast_ref::<Translate>() {
+        find_hierarchies(translate.object(), found);
+    } else if let Some(rotate) = object.downcast_ref::<RotateY>() {
+        find_hierarchies(rotate.object(), found);
+    } else if let Some(transform) = object.downcast_ref::<Transform>() {
+        find_hierarchies(transform.object(), found);
+    } else if let Some(medium) = object.downcast_ref::<ConstantMedium>() {
+        find_hierarchies(medium.boundary(), found);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::Color,
+        material::Lambertian,
+        quad::bx,
+        ray::ray,
+        rtweekend::{random_double_from, seed_random},
+        sphere::Sphere,
+        vec3::{random_from, random_unit_vector, vec3},
+    };
+
+    /// Random spheres and boxes, some of them overlapping
+    fn test_objects() -> HittableList {
+        seed_random(0, 0);
+        let white = Arc::new(Lambertian::new(Color::white()));
+        let mut list = HittableList::default();
+        for _ in 0..100 {
+            let center = random_from(-10.0, 10.0);
+            list.add(Arc::new(Sphere::new(
+                center,
+                random_double_from(0.1, 1.5),
+                white.clone(),
+            )));
+        }
+        for _ in 0..20 {
+            let corner = random_from(-10.0, 10.0);
+            list.add(bx(
+                &corner,
+                &(corner + random_from(0.1, 3.0)),
+                white.clone(),
+            ));
         }
+        list
+    }
 
-        let hit_left = self.left.hit(r, ray_t, rec);
-        let hit_right = self.right.hit(
-            r,
-            Interval::new(ray_t.min, if hit_left { rec.t } else { ray_t.max }),
-            rec,
+    fn assert_same_box(a: &Aabb, b: &Aabb) {
+        for (a, b) in [(&a.x, &b.x), (&a.y, &b.y), (&a.z, &b.z)] {
+            assert_eq!((a.min, a.max), (b.min, b.max));
+        }
+    }
+
+    /// Checks that every node's box is the union of its children's or its objects' boxes,
+    /// returning the number of objects below the node.
+    fn check_node(bvh: &BvhNode, index: usize) -> usize {
+        let node = &bvh.hierarchy.nodes[index];
+        if node.count > 0 {
+            let objects = &bvh.objects[node.offset as usize..][..node.count as usize];
+            let bbox = objects.iter().fold(Aabb::empty(), |bbox, object| {
+                Aabb::from_aabb(&bbox, object.bounding_box())
+            });
+            assert_same_box(&node.bbox, &bbox);
+            return objects.len();
+        }
+
+        let (first, second) = (index + 1, node.offset as usize);
+        let children = Aabb::from_aabb(
+            &bvh.hierarchy.nodes[first].bbox,
+            &bvh.hierarchy.nodes[second].bbox,
         );
+        assert_same_box(&node.bbox, &children);
+        check_node(bvh, first) + check_node(bvh, second)
+    }
+
+    #[test]
+    fn bounds_enclose_all_objects() {
+        for method in [SplitMethod::Median, SplitMethod::Sah] {
+            let list = test_objects();
+            let count = list.objects.len();
+            let bvh = BvhNode::with_split(&list.objects, method);
+
+            assert_same_box(bvh.bounding_box(), list.bounding_box());
+            assert_eq!(check_node(&bvh, 0), count);
 
-        hit_left || hit_right
+            let stats = bvh.stats();
+            assert_eq!(stats.objects, count);
+            assert_eq!(stats.nodes, 2 * stats.leaves - 1);
+        }
     }
 
-    fn bounding_box(&self) -> &Aabb {
-        &self.bbox
+    #[test]
+    fn sah_lowers_the_cost() {
+        let median = BvhNode::with_split(&test_objects().objects, SplitMethod::Median);
+        let sah = BvhNode::with_split(&test_objects().objects, SplitMethod::Sah);
+        assert!(sah.stats().sah_cost < median.stats().sah_cost);
+    }
+
+    #[test]
+    fn sah_handles_coinciding_centroids() {
+        let white = Arc::new(Lambertian::new(Color::white()));
+        let objects: Vec<Arc<dyn Hittable>> = (1..=5)
+            .map(|radius| {
+                Arc::new(Sphere::new(
+                    vec3(0.0, 0.0, 0.0),
+                    radius as f64,
+                    white.clone(),
+                )) as Arc<dyn Hittable>
+            })
+            .collect();
+        let bvh = BvhNode::with_split(&objects, SplitMethod::Sah);
+        assert_eq!(check_node(&bvh, 0), 5);
+    }
+
+    #[test]
+    fn single_object() {
+        let white = Arc::new(Lambertian::new(Color::white()));
+        let sphere = Arc::new(Sphere::new(vec3(1.0, 2.0, 3.0), 0.5, white));
+        let bvh = BvhNode::from_list(&mut HittableList::new(sphere.clone()));
+        assert_same_box(bvh.bounding_box(), sphere.bounding_box());
+    }
+
+    #[test]
+    fn empty_list() {
+        let bvh = BvhNode::from_list(&mut HittableList::default());
+        let r = ray(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+        assert!(!bvh.hit(&r, Interval::universe(), &mut HitRecord::default()));
+        assert_eq!(bvh.stats().nodes, 0);
+    }
+
+    #[test]
+    fn hits_match_brute_force() {
+        for method in [SplitMethod::Median, SplitMethod::Sah] {
+            assert_hits_match(method);
+        }
+    }
+
+    fn assert_hits_match(method: SplitMethod) {
+        let list = test_objects();
+        let bvh = BvhNode::with_split(&test_objects().objects, method);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let r = ray(random_from(-15.0, 15.0), random_unit_vector());
+            let ray_t = Interval::new(0.001, f64::INFINITY);
+            let (mut expected, mut found) = (HitRecord::default(), HitRecord::default());
+            let is_hit = list.hit(&r, ray_t, &mut expected);
+            assert_eq!(bvh.hit(&r, ray_t, &mut found), is_hit);
+            if is_hit {
+                hits += 1;
+                assert_eq!(found.t, expected.t);
+                assert!(bvh.bounding_box().contains(&found.p));
+            }
+        }
+        assert!(hits > 0, "no ray hit the objects");
     }
 }
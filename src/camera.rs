@@ -1,19 +1,85 @@
 use core::f64;
+use std::{
+    hash::Hasher,
+    io::{self, IsTerminal},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    color::{Color, write_color},
+    accumulator::{Accumulator, AccumulatorRow, Splats},
+    aov::{AovSample, MaterialIds},
+    color::Color,
+    denoise::Denoiser,
+    filter::Filter,
+    framebuffer::Framebuffer,
+    hittable_list::HittableList,
     interval::Interval,
+    material::ScatterRecord,
+    pdf::{HittablePdf, Pdf},
     ray::{ray_with_time, Ray},
-    rtweekend::{degrees_to_radians, random_double},
+    rtweekend::{degrees_to_radians, seed_random},
+    sampler::{
+        sample_1d, sample_2d, set_thread_sampler, start_bounce, start_sample, Dimension,
+        IndependentSampler, PixelSample, Sampler,
+    },
     sphere::{HitRecord, Hittable},
+    stats::{self, Counter, RenderStats},
+    tonemap::DisplayTransform,
     vec3::{cross, random_in_unit_disk, unit_vector, vec3, Vec3},
 };
 
+/// Largest width and height of a rendered image in pixels
+pub const MAX_IMAGE_SIZE: i32 = 16384;
+
+/// Largest number of bounces of a path. The path tracer recurses once per bounce.
+pub const MAX_DEPTH: i32 = 1000;
+
+/// Stack size of the render threads, with room for [`MAX_DEPTH`] bounces in debug builds
+const RENDER_STACK_SIZE: usize = 64 << 20;
+
+/// Largest number of samples a pixel takes in one pass of a progressive render
+const MAX_SAMPLES_PER_PASS: u32 = 16;
+
+/// Largest number of pixels whose coordinates [`InvalidSamples`] keeps
+const MAX_INVALID_PIXELS: usize = 16;
+
+/// Samples of a render whose color came out as NaN or infinite. They count as black, so that a
+/// single broken path can't poison its whole pixel.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InvalidSamples {
+    pub count: u64,
+    /// First pixels with invalid samples in scanline order, at most 16 of them
+    pub pixels: Vec<(u32, u32)>,
+}
+
+impl InvalidSamples {
+    /// Counts an invalid sample of pixel `x`, `y`.
+    fn add(&mut self, x: u32, y: u32) {
+        self.count += 1;
+        if let Err(index) = self.pixels.binary_search_by_key(&(y, x), |&(x, y)| (y, x)) {
+            self.pixels.insert(index, (x, y));
+            self.pixels.truncate(MAX_INVALID_PIXELS);
+        }
+    }
+
+    /// Adds the invalid samples found by another thread, keeping the same pixels whichever
+    /// thread found them.
+    fn merge(&mut self, other: InvalidSamples) {
+        let count = self.count + other.count;
+        for (x, y) in other.pixels {
+            self.add(x, y);
+        }
+        self.count = count;
+    }
+}
+
 pub struct Camera {
     pub aspect_ratio: f64,      // Ratio of image width over height
     pub image_width: i32,       // Rendered image width in pixel count
     pub samples_per_pixel: i32, // Count of random samples for each pixel
-    pub max_depth: i32,         // Maximum number of ray bounces into scene
+    pub max_depth: i32,         // Maximum number of ray bounces into scene, up to MAX_DEPTH
     pub background: Color,      // Scene background color
 
     pub vfov: f64,      // Vertical view angle (field of view)
@@ -24,17 +90,36 @@ pub struct Camera {
     pub defocus_angle: f64, // Variation angle of rays through each pixel
     pub focus_dist: f64,    // Distance from camera lookfrom point to plane of perfect focus
 
-    image_height: i32,       // Rendered image height
-    pixel_sample_scale: f64, // Color scale factor for a sum of pixel samples
-    center: Vec3,            // Camera center
-    pixel00_loc: Vec3,       // Location of pixel 0, 0
-    pixel_delta_u: Vec3,     // Offset to pixel to the right
-    pixel_delta_v: Vec3,     // Offset to pixel below
-    u: Vec3,                 // Camera frame basis vectors
-    v: Vec3,                 //
-    w: Vec3,                 //
-    defocus_disk_u: Vec3,    // Defocus disk horizontal radius
-    defocus_disk_v: Vec3,    // Defocus disk vertical radius
+    pub threads: usize, // Number of render threads, 0 uses all available cores
+    pub seed: u64,      // Seed of the random numbers, equal seeds give identical images
+
+    pub sampler: Arc<dyn Sampler>, // Placement of the samples within pixels and along paths
+    pub filter: Filter,            // Weights of the samples for the pixels around them
+
+    pub indirect_clamp: f64, // Largest luminance of the indirect light of a sample, 0 disables it
+
+    pub denoiser: Option<Denoiser>, // Filter removing the noise from the finished image
+    pub display: DisplayTransform,  // Exposure and tone mapping of the finished image
+
+    pub time_limit: Option<Duration>, // Wall-clock budget, stops the render early
+    pub error_threshold: f64,         // Pixel error to stop sampling at, 0 disables it
+    pub min_samples_per_pixel: i32,   // Samples taken before a pixel may stop early
+    pub progress: bool,               // Report progress and statistics on stderr
+
+    image_height: i32,         // Rendered image height
+    center: Vec3,              // Camera center
+    pixel00_loc: Vec3,         // Location of pixel 0, 0
+    pixel_delta_u: Vec3,       // Offset to pixel to the right
+    pixel_delta_v: Vec3,       // Offset to pixel below
+    u: Vec3,                   // Camera frame basis vectors
+    v: Vec3,                   //
+    w: Vec3,                   //
+    defocus_disk_u: Vec3,      // Defocus disk horizontal radius
+    defocus_disk_v: Vec3,      // Defocus disk vertical radius
+    material_ids: MaterialIds, // Numbers of the world's materials for the AOVs
+
+    invalid_samples: Mutex<InvalidSamples>, // NaN or infinite samples of the last render
+    stats: Mutex<RenderStats>,              // Work done by the last render
 }
 
 impl Default for Camera {
@@ -50,8 +135,18 @@ impl Default for Camera {
             vup: vec3(0.0, 1.0, 0.0),
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            threads: 0,
+            seed: 0,
+            sampler: Arc::new(IndependentSampler),
+            filter: Filter::default(),
+            indirect_clamp: 0.0,
+            denoiser: None,
+            display: DisplayTransform::default(),
+            time_limit: None,
+            error_threshold: 0.0,
+            min_samples_per_pixel: 16,
+            progress: false,
             image_height: Default::default(),
-            pixel_sample_scale: Default::default(),
             center: Default::default(),
             pixel00_loc: Default::default(),
             pixel_delta_u: Default::default(),
@@ -61,39 +156,344 @@ impl Default for Camera {
             w: Default::default(),
             defocus_disk_u: Default::default(),
             defocus_disk_v: Default::default(),
+            material_ids: Default::default(),
+            invalid_samples: Default::default(),
+            stats: Default::default(),
             background: Color::default(),
         }
     }
 }
 
 impl Camera {
-    pub fn render(&mut self, world: &dyn Hittable) {
+    /// Renders the world into a framebuffer of linear color values, denoised if the camera has a
+    /// denoiser.
+    ///
+    /// # Arguments
+    ///
+    /// * `world` - Objects to render
+    /// * `lights` - Light sources to sample directly at every diffuse bounce, may be empty
+    pub fn render(&mut self, world: &dyn Hittable, lights: &HittableList) -> Framebuffer {
+        let (width, height) = self.image_size();
+        let mut accumulator = match self.denoiser {
+            Some(_) => Accumulator::with_aovs(width, height),
+            None => Accumulator::new(width, height),
+        };
+        self.render_into(world, lights, &mut accumulator, |_| {});
+        self.finish(&accumulator)
+    }
+
+    /// Returns the final image of the accumulated samples, which is their average run through the
+    /// denoiser if the camera has one, with the display transform of the camera.
+    ///
+    /// # Panics
+    ///
+    /// If the camera has a denoiser but the accumulator doesn't keep the AOVs it needs.
+    pub fn finish(&self, accumulator: &Accumulator) -> Framebuffer {
+        let mut image = match &self.denoiser {
+            Some(denoiser) => denoiser.denoise(accumulator, self.thread_count()),
+            None => accumulator.average(),
+        };
+        image.set_display(self.display);
+        image
+    }
+
+    /// Adds samples to the accumulated image in passes, until every pixel has
+    /// `samples_per_pixel` samples or the time limit is over. Pixels take few samples per pass at
+    /// first and more later on, so that early results show up quickly.
+    ///
+    /// Accumulators created with [`Accumulator::with_aovs`] get the AOVs of the samples as well.
+    ///
+    /// With an `error_threshold`, pixels whose estimated error drops below it stop early, but not
+    /// before they have `min_samples_per_pixel` samples. Pixels only check their error after
+    /// sample counts that double up to 16 and are multiples of 16 from there on.
+    ///
+    /// Samples are numbered per pixel, so rendering an image in any number of passes gives the
+    /// same result as rendering it at once.
+    ///
+    /// Returns whether the image is finished, which is `false` if the time limit stopped it.
+    ///
+    /// # Arguments
+    ///
+    /// * `world` - Objects to render
+    /// * `lights` - Light sources to sample directly at every diffuse bounce, may be empty
+    /// * `accumulator` - Samples taken so far, sized to [`Camera::image_size`]
+    /// * `on_pass` - Called with the accumulated image after every pass
+    ///
+    /// # Panics
+    ///
+    /// If the size of the accumulator doesn't match the image size.
+    pub fn render_into(
+        &mut self,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        accumulator: &mut Accumulator,
+        mut on_pass: impl FnMut(&Accumulator),
+    ) -> bool {
         Camera::initialize(self);
+        assert!(
+            (accumulator.width(), accumulator.height()) == self.image_size(),
+            "accumulator doesn't match the image size"
+        );
+        if accumulator.has_aovs() {
+            self.material_ids = MaterialIds::new(world);
+        }
+        *self.invalid_samples.get_mut().unwrap() = InvalidSamples::default();
+        *self.stats.get_mut().unwrap() = RenderStats::default();
+
+        let start = Instant::now();
+        let deadline = self.time_limit.map(|limit| start + limit);
+        let progress = self.progress.then(|| {
+            Mutex::new(Progress::new(
+                start,
+                deadline,
+                accumulator.total_sample_count(),
+            ))
+        });
+        let finished = loop {
+            let remaining = self.remaining_samples(accumulator);
+            if remaining == 0 {
+                break true;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break false;
+            }
+            if let Some(progress) = &progress {
+                let taken = self.stats.get_mut().unwrap().primary_rays;
+                progress.lock().unwrap().schedule(taken, remaining);
+            }
+            self.render_pass(world, lights, accumulator, deadline, progress.as_ref());
+            on_pass(accumulator);
+        };
+
+        let stats = self.stats.get_mut().unwrap();
+        stats.wall_time = start.elapsed();
+        if let Some(progress) = progress {
+            progress.into_inner().unwrap().finish(stats, finished);
+        }
+        finished
+    }
+
+    /// Rays traced, intersection tests and time taken by the last call to
+    /// [`Camera::render_into`]
+    pub fn stats(&self) -> RenderStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Samples of the last call to [`Camera::render_into`] whose color was NaN or infinite, which
+    /// were counted as black.
+    pub fn invalid_samples(&self) -> InvalidSamples {
+        self.invalid_samples.lock().unwrap().clone()
+    }
 
-        print!("P3\n{} {}\n255\n", self.image_width, self.image_height);
-        for j in 0..self.image_height {
-            for i in 0..self.image_width {
-                let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                for _sample in 0..self.samples_per_pixel {
-                    let r = self.get_ray(i, j);
-                    pixel_color += self.ray_color(&r, self.max_depth, world)
+    /// Feeds every setting that changes the rendered image into `state`. The number of samples
+    /// is left out, since more of them can be added to an image later on, unless the sampler
+    /// places the samples depending on it.
+    pub fn hash_settings(&self, state: &mut impl Hasher) {
+        let (width, height) = self.image_size();
+        state.write_u32(width);
+        state.write_u32(height);
+        state.write_i32(self.max_depth);
+        state.write_u64(self.seed);
+        state.write(self.sampler.name().as_bytes());
+        if self.sampler.depends_on_sample_count() {
+            state.write_i32(self.samples_per_pixel);
+        }
+        state.write(self.filter.kind().name().as_bytes());
+        let vectors = [self.lookfrom, self.lookat, self.vup];
+        let background = [self.background.r, self.background.g, self.background.b];
+        for value in vectors
+            .iter()
+            .flat_map(|v| [v.x, v.y, v.z])
+            .chain(background)
+            .chain([self.vfov, self.defocus_angle, self.focus_dist])
+            .chain([self.filter.radius(), self.indirect_clamp])
+        {
+            state.write_u64(value.to_bits());
+        }
+    }
+
+    /// Width and height of the rendered image in pixels
+    pub fn image_size(&self) -> (u32, u32) {
+        let image_height = (self.image_width as f64 / self.aspect_ratio) as i32;
+        (self.image_width as u32, image_height.max(1) as u32)
+    }
+
+    /// Samples the pixels that aren't finished yet can still take, which is zero once the image
+    /// is finished. Adaptive sampling may stop pixels before they take all of them.
+    fn remaining_samples(&self, accumulator: &Accumulator) -> u64 {
+        let (width, height) = self.image_size();
+        let target = self.samples_per_pixel.max(0) as u32;
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let count = accumulator.sample_count(x, y);
+                if self.is_pixel_finished(count, accumulator.error(x, y)) {
+                    0
+                } else {
+                    (target - count) as u64
                 }
-                write_color(self.pixel_sample_scale * pixel_color);
+            })
+            .sum()
+    }
+
+    /// Whether a pixel with `count` samples and the given estimated error needs no more samples
+    fn is_pixel_finished(&self, count: u32, error: f64) -> bool {
+        let adaptive = self.error_threshold > 0.0
+            && count >= self.min_samples_per_pixel.max(0) as u32
+            && error < self.error_threshold;
+        count >= self.samples_per_pixel.max(0) as u32 || adaptive
+    }
+
+    fn render_pass(
+        &self,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        accumulator: &mut Accumulator,
+        deadline: Option<Instant>,
+        progress: Option<&Mutex<Progress>>,
+    ) {
+        // Hand out scanlines to the worker threads one at a time, so that threads finishing cheap
+        // rows early keep picking up work until the whole image is done. Once the time is up the
+        // remaining scanlines are left for a later run.
+        let (rows, splat_target) = accumulator.pass_mut();
+        let scanlines = Mutex::new(rows.enumerate());
+        let splat_target = Mutex::new(splat_target);
+        thread::scope(|s| {
+            for _ in 0..self.thread_count() {
+                let render_thread = thread::Builder::new().stack_size(RENDER_STACK_SIZE);
+                render_thread
+                    .spawn_scoped(s, || {
+                        let started = stats::thread_cpu_time();
+                        set_thread_sampler(Some(self.sampler.clone()));
+                        let mut invalid_samples = InvalidSamples::default();
+                        loop {
+                            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                                break;
+                            }
+                            let next = scanlines.lock().unwrap().next();
+                            let Some((j, mut scanline)) = next else {
+                                break;
+                            };
+                            let splats = self.render_scanline(
+                                j as i32,
+                                &mut scanline,
+                                world,
+                                lights,
+                                &mut invalid_samples,
+                            );
+                            splat_target.lock().unwrap().add(j, splats);
+
+                            let mut stats = self.stats.lock().unwrap();
+                            stats.take_thread_counts();
+                            if let Some(progress) = progress {
+                                progress.lock().unwrap().update(&stats);
+                            }
+                        }
+                        self.invalid_samples.lock().unwrap().merge(invalid_samples);
+                        let finished = stats::thread_cpu_time();
+                        if let (Some(started), Some(finished)) = (started, finished) {
+                            let cpu_time = &mut self.stats.lock().unwrap().cpu_time;
+                            *cpu_time.get_or_insert_default() += finished - started;
+                        }
+                    })
+                    .expect("can't start a render thread");
             }
+        });
+    }
+
+    /// Adds samples to the pixels of scanline `j` that aren't finished yet, and returns them
+    /// weighted by the filter for the surrounding pixels. Samples that aren't finite are counted
+    /// in `invalid_samples` and added as black.
+    fn render_scanline(
+        &self,
+        j: i32,
+        scanline: &mut AccumulatorRow,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        invalid_samples: &mut InvalidSamples,
+    ) -> Splats {
+        let reach = self.filter.reach();
+        let mut splats = Splats::new(
+            self.image_width as u32,
+            (j - reach).max(0) as u32,
+            (j + reach).min(self.image_height - 1) as u32,
+        );
+        let target = self.samples_per_pixel.max(0) as u32;
+        for i in 0..self.image_width as usize {
+            let count = scanline.counts[i];
+            if self.is_pixel_finished(count, scanline.error(i)) {
+                continue;
+            }
+
+            // The number of samples per pass only depends on the samples the pixel already has,
+            // so adaptive sampling stops pixels at the same counts however the render is split.
+            let batch = count.clamp(1, MAX_SAMPLES_PER_PASS);
+            let end = u32::min(count + batch, target);
+            let pixel_index = j as u64 * self.image_width as u64 + i as u64;
+            stats::count(Counter::PrimaryRays, (end - count) as u64);
+            for sample in count..end {
+                // Every sample gets its own random sequence, so the image doesn't depend on which
+                // thread rendered which pixel in which pass.
+                seed_random(self.seed, (pixel_index << 32) | sample as u64);
+                start_sample(PixelSample {
+                    seed: self.seed,
+                    pixel: pixel_index,
+                    index: sample,
+                    samples_per_pixel: target,
+                });
+                let offset = sample_square();
+                let r = self.get_ray(i as i32, j, &offset);
+                let mut aovs = scanline.has_aovs().then(AovSample::default);
+                let mut radiance =
+                    self.path_color(&r, self.max_depth, world, lights, None, aovs.as_mut());
+                if !radiance.is_finite() {
+                    invalid_samples.add(i as u32, j as u32);
+                    radiance = Radiance::emitted(Color::black());
+                }
+                if self.indirect_clamp > 0.0 {
+                    radiance.clamp_indirect(self.indirect_clamp);
+                }
+                if let Some(aovs) = &mut aovs {
+                    aovs.direct = radiance.direct;
+                    aovs.indirect = radiance.indirect();
+                    scanline.add_aovs(i, aovs);
+                }
+                scanline.add(i, radiance.total);
+                self.splat(&mut splats, i as i32, j, &offset, radiance.total);
+            }
+            scanline.counts[i] = end;
         }
+        splats
     }
 
-    fn initialize(&mut self) {
-        self.image_height = (self.image_width as f64 / self.aspect_ratio) as i32;
-        self.image_height = if self.image_height < 1 {
-            1
-        } else {
-            self.image_height
-        };
+    /// Adds a sample taken at `offset` from the center of pixel `i`, `j` to the pixels within
+    /// the filter radius.
+    fn splat(&self, splats: &mut Splats, i: i32, j: i32, offset: &Vec3, color: Color) {
+        for (dy, weight_y) in self.filter.footprint(offset.y) {
+            let y = j + dy;
+            if !(0..self.image_height).contains(&y) {
+                continue;
+            }
+            for (dx, weight_x) in self.filter.footprint(offset.x) {
+                let x = i + dx;
+                if (0..self.image_width).contains(&x) {
+                    splats.add(x as u32, y as u32, color, weight_x * weight_y);
+                }
+            }
+        }
+    }
 
-        self.pixel_sample_scale = 1.0 / self.samples_per_pixel as f64;
+    fn thread_count(&self) -> usize {
+        if self.threads > 0 {
+            return self.threads;
+        }
+        thread::available_parallelism().map_or(1, |n| n.get())
+    }
 
-        self.center = self.lookfrom.clone();
+    fn initialize(&mut self) {
+        self.image_height = self.image_size().1 as i32;
+
+        self.center = self.lookfrom;
 
         // Determine viewport dimensions.
         let theta = degrees_to_radians(self.vfov);
@@ -102,46 +502,45 @@ impl Camera {
         let viewport_width = viewport_height * (self.image_width as f64 / self.image_height as f64);
 
         // Calculate the u, v, w unit basis vectors for the camera coordinate frame.
-        self.w = unit_vector(&(&self.lookfrom - &self.lookat));
+        self.w = unit_vector(&(self.lookfrom - self.lookat));
         self.u = unit_vector(&cross(&self.vup, &self.w));
         self.v = cross(&self.w, &self.u);
 
         // Calculate the vectors across the horizontal and down the vertical viewport edges.
-        let viewport_u = viewport_width * &self.u; // Vector across viewport horizontal edge
+        let viewport_u = viewport_width * self.u; // Vector across viewport horizontal edge
         let viewport_v = viewport_height * -&self.v; // Vector down viewport vertical edge
 
         // Calculate the horizontal and vertical delta vectors from pixel to pixel.
-        self.pixel_delta_u = &viewport_u / self.image_width;
-        self.pixel_delta_v = &viewport_v / self.image_height;
+        self.pixel_delta_u = viewport_u / self.image_width;
+        self.pixel_delta_v = viewport_v / self.image_height;
 
         // Calculate the location of the upper left pixel
         let viewport_upper_left =
-            &self.center - (self.focus_dist * &self.w) - viewport_u / 2 - viewport_v / 2;
-        self.pixel00_loc = viewport_upper_left + 0.5 * (&self.pixel_delta_u + &self.pixel_delta_v);
+            self.center - (self.focus_dist * self.w) - viewport_u / 2 - viewport_v / 2;
+        self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
 
         // Calculate the camera defocus disk basis vectors
         let defocus_radius =
             self.focus_dist * f64::tan(degrees_to_radians(self.defocus_angle / 2.0));
-        self.defocus_disk_u = &self.u * defocus_radius;
-        self.defocus_disk_v = &self.v * defocus_radius;
+        self.defocus_disk_u = self.u * defocus_radius;
+        self.defocus_disk_v = self.v * defocus_radius;
     }
 
-    fn get_ray(&self, i: i32, j: i32) -> Ray {
-        // Construct a camera ray originating from the defocus disk and directed at randomly sampled
-        // point around the pixel location i, j.
+    fn get_ray(&self, i: i32, j: i32, offset: &Vec3) -> Ray {
+        // Construct a camera ray originating from the defocus disk and directed at the point
+        // offset from the pixel location i, j.
 
-        let offset = sample_square();
-        let pixel_sample = self.pixel00_loc.clone()
-            + ((i as f64 + offset.x) * &self.pixel_delta_u)
-            + ((j as f64 + offset.y) * &self.pixel_delta_v);
+        let pixel_sample = self.pixel00_loc
+            + ((i as f64 + offset.x) * self.pixel_delta_u)
+            + ((j as f64 + offset.y) * self.pixel_delta_v);
 
         let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center.clone()
+            self.center
         } else {
             self.defocus_disk_sample()
         };
-        let ray_direction = &pixel_sample - &ray_origin;
-        let ray_time = random_double();
+        let ray_direction = pixel_sample - ray_origin;
+        let ray_time = sample_1d(Dimension::Time);
 
         ray_with_time(ray_origin, ray_direction, ray_time)
     }
@@ -149,37 +548,733 @@ impl Camera {
     fn defocus_disk_sample(&self) -> Vec3 {
         // Returns a random point in the camera defocus disk
         let p = random_in_unit_disk();
-        &self.center + (p.x * &self.defocus_disk_u) + (p.y * &self.defocus_disk_v)
+        self.center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
+    }
+
+    pub fn ray_color(
+        &self,
+        r: &Ray,
+        depth: i32,
+        world: &dyn Hittable,
+        lights: &HittableList,
+    ) -> Color {
+        self.path_color(r, depth, world, lights, None, None).total
     }
 
-    pub fn ray_color(&self, r: &Ray, depth: i32, world: &dyn Hittable) -> Color {
+    /// Gathers the light arriving along `r`.
+    ///
+    /// # Arguments
+    ///
+    /// * `r` - Ray to trace
+    /// * `depth` - Remaining number of bounces
+    /// * `world` - Objects to render
+    /// * `lights` - Light sources to sample directly
+    /// * `scattering_pdf` - Density with which the previous bounce picked the direction of `r`,
+    ///   `None` for camera rays and rays leaving specular surfaces
+    /// * `aovs` - Filled in from the surface the ray hits, for camera rays of renders with AOVs
+    fn path_color(
+        &self,
+        r: &Ray,
+        depth: i32,
+        world: &dyn Hittable,
+        lights: &HittableList,
+        scattering_pdf: Option<f64>,
+        aovs: Option<&mut AovSample>,
+    ) -> Radiance {
         // If we've exceeded the ray bounce limit, no more light is gathered
         if depth <= 0 {
-            return Color::new(0.0, 0.0, 0.0);
+            return Radiance::emitted(Color::new(0.0, 0.0, 0.0));
         }
+        start_bounce((self.max_depth - depth) as u32);
 
         let mut rec = HitRecord::default();
 
         // If the ray hits nothing, return the background color.
         if !world.hit(r, Interval::new(0.001, f64::INFINITY), &mut rec) {
-            return self.background;
+            if let Some(aovs) = aovs {
+                aovs.albedo = self.background;
+            }
+            return Radiance::emitted(self.background);
+        }
+
+        if let Some(aovs) = aovs {
+            aovs.albedo = rec.mat.albedo(&rec);
+            aovs.normal = rec.normal;
+            aovs.depth = rec.t * r.direction().length();
+            aovs.position = rec.p;
+            aovs.uv = (rec.u, rec.v);
+            aovs.material_id = self.material_ids.get(&rec.mat);
+        }
+
+        let mut srec = ScatterRecord::default();
+        let mut color_from_emmission = rec.mat.emitted(rec.u, rec.v, &rec.p);
+
+        // The previous bounce sampled the lights as well and may have found this emitter that
+        // way, so weight the emission against the density of the light sample.
+        if let Some(scattering_pdf) = scattering_pdf {
+            let light_pdf = lights.pdf_value(r.origin(), r.direction());
+            color_from_emmission =
+                power_heuristic(scattering_pdf, light_pdf) * color_from_emmission;
         }
 
-        let mut scattered = Ray::default();
-        let mut attenuation = Color::default();
-        let color_from_emmission = rec.mat.emmited(rec.u, rec.v, &rec.p);
+        if !rec.mat.scatter(r, &rec, &mut srec) {
+            return Radiance::emitted(color_from_emmission);
+        }
+
+        // Specular materials scatter into a single direction, which light samples can't hit.
+        let Some(pdf) = srec.pdf else {
+            stats::count(Counter::BounceRays, 1);
+            let scattered =
+                self.path_color(&srec.skip_pdf_ray, depth - 1, world, lights, None, None);
+            let color_from_scatter = srec.attenuation * scattered.total;
+            return Radiance {
+                total: color_from_emmission + color_from_scatter,
+                emitted: color_from_emmission,
+                direct: srec.attenuation * scattered.emitted,
+            };
+        };
+
+        // The last bounce can't sample the lights, since its scattered ray doesn't gather the
+        // emission that the light sample is weighted against.
+        let color_from_lights = if lights.objects.is_empty() || depth <= 1 {
+            Color::black()
+        } else {
+            self.sample_lights(r, &rec, pdf.as_ref(), world, lights)
+        };
+
+        let scattered = ray_with_time(rec.p, pdf.generate(), r.time());
+        let scattering_pdf = if scattered.direction().near_zero() {
+            0.0
+        } else {
+            pdf.value(scattered.direction())
+        };
+        if !is_usable_density(scattering_pdf) {
+            return Radiance {
+                total: color_from_emmission + color_from_lights,
+                emitted: color_from_emmission,
+                direct: color_from_lights,
+            };
+        }
+
+        let bsdf = rec.mat.bsdf(r, &rec, &scattered);
+        stats::count(Counter::BounceRays, 1);
+        let incoming = self.path_color(
+            &scattered,
+            depth - 1,
+            world,
+            lights,
+            Some(scattering_pdf),
+            None,
+        );
+        let color_from_scatter = (1.0 / scattering_pdf) * bsdf * incoming.total;
+
+        Radiance {
+            total: color_from_emmission + color_from_lights + color_from_scatter,
+            emitted: color_from_emmission,
+            direct: color_from_lights + (1.0 / scattering_pdf) * bsdf * incoming.emitted,
+        }
+    }
 
-        if !rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
-            return color_from_emmission;
+    /// Estimates the light arriving directly from the light sources at a diffuse hit point.
+    ///
+    /// # Arguments
+    ///
+    /// * `r_in` - Ray that hit the point
+    /// * `rec` - Hit record of the point
+    /// * `scattering_pdf` - Distribution the material samples scattered directions from
+    /// * `world` - Objects to render
+    /// * `lights` - Light sources to sample
+    fn sample_lights(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        scattering_pdf: &dyn Pdf,
+        world: &dyn Hittable,
+        lights: &HittableList,
+    ) -> Color {
+        // Pick a direction towards a random point on one of the lights.
+        let light_pdf = HittablePdf::new(lights, rec.p);
+        let to_light = ray_with_time(rec.p, light_pdf.generate(), r_in.time());
+        if to_light.direction().near_zero() {
+            return Color::black();
+        }
+        let light_density = light_pdf.value(to_light.direction());
+        if !is_usable_density(light_density) {
+            return Color::black();
         }
 
-        let color_from_scatter = attenuation * self.ray_color(&scattered, depth - 1, world);
+        let scattering_density = scattering_pdf.value(to_light.direction());
+        if !is_usable_density(scattering_density) {
+            return Color::black();
+        }
 
-        color_from_emmission + color_from_scatter
+        // Whatever is hit first along the direction is what the point sees, which also takes care
+        // of occluders between the point and the light.
+        stats::count(Counter::ShadowRays, 1);
+        let mut light_rec = HitRecord::default();
+        if !world.hit(
+            &to_light,
+            Interval::new(0.001, f64::INFINITY),
+            &mut light_rec,
+        ) {
+            return Color::black();
+        }
+        let emitted = light_rec
+            .mat
+            .emitted(light_rec.u, light_rec.v, &light_rec.p);
+
+        let bsdf = rec.mat.bsdf(r_in, rec, &to_light);
+        let weight = power_heuristic(light_density, scattering_density);
+        (weight / light_density) * bsdf * emitted
     }
 }
 
+/// Light arriving along a ray, with the parts of it the lighting AOVs are made of
+struct Radiance {
+    total: Color,
+    emitted: Color, // Emitted by the surface the ray hits, or the background it escapes to
+    direct: Color,  // Reflected by the surface straight from the emitters
+}
+
+impl Radiance {
+    /// Light that only comes from emission
+    fn emitted(color: Color) -> Radiance {
+        Radiance {
+            total: color,
+            emitted: color,
+            direct: Color::black(),
+        }
+    }
+
+    /// Light reflected by the surface that took more than one bounce to get there
+    fn indirect(&self) -> Color {
+        self.total - self.emitted - self.direct
+    }
+
+    fn is_finite(&self) -> bool {
+        self.total.is_finite() && self.emitted.is_finite() && self.direct.is_finite()
+    }
+
+    /// Scales the indirect light down to a luminance of at most `max_luminance`, which removes
+    /// fireflies from rare bright paths at the cost of darkening the image a little.
+    fn clamp_indirect(&mut self, max_luminance: f64) {
+        let indirect = self.indirect();
+        let luminance = indirect.luminance();
+        if luminance > max_luminance {
+            self.total = self.emitted + self.direct + (max_luminance / luminance) * indirect;
+        }
+    }
+}
+
+/// Progress of a render, reported on stderr
+struct Progress {
+    start: Instant,
+    last_report: Instant,
+    deadline: Option<Instant>, // End of the time limit of the render
+    initial_samples: u64,      // Samples the accumulator had before the render
+    total_samples: u64,        // Samples taken so far plus the ones still scheduled
+    terminal: bool,            // Whether stderr is a terminal, where reports overwrite each other
+}
+
+impl Progress {
+    fn new(start: Instant, deadline: Option<Instant>, initial_samples: u64) -> Progress {
+        Progress {
+            start,
+            last_report: start,
+            deadline,
+            initial_samples,
+            total_samples: initial_samples,
+            terminal: io::stderr().is_terminal(),
+        }
+    }
+
+    /// Sets the budget before a pass: the render took `taken` samples so far and the pixels that
+    /// aren't finished can take `remaining` more. Adaptive sampling lowers the budget from one
+    /// pass to the next as pixels stop early.
+    fn schedule(&mut self, taken: u64, remaining: u64) {
+        self.total_samples = self.initial_samples + taken + remaining;
+    }
+
+    /// Reports the progress if the last report is long enough ago, twice a second on terminals
+    /// and every ten seconds in logs.
+    fn update(&mut self, stats: &RenderStats) {
+        let interval = if self.terminal {
+            Duration::from_millis(500)
+        } else {
+            Duration::from_secs(10)
+        };
+        if self.last_report.elapsed() >= interval {
+            self.last_report = Instant::now();
+            let done = (self.initial_samples + stats.primary_rays).min(self.total_samples);
+            self.report(stats, done);
+        }
+    }
+
+    /// Reports the final progress and the statistics of the render.
+    fn finish(&self, stats: &RenderStats, finished: bool) {
+        let done = if finished {
+            self.total_samples
+        } else {
+            (self.initial_samples + stats.primary_rays).min(self.total_samples)
+        };
+        self.report(stats, done);
+        if self.terminal {
+            eprintln!();
+        }
+        eprintln!("{stats}");
+    }
+
+    /// Prints the percentage of `done` samples, the samples per second and the expected time
+    /// left.
+    fn report(&self, stats: &RenderStats, done: u64) {
+        let seconds = self.start.elapsed().as_secs_f64();
+        let rate = stats.primary_rays as f64 / seconds.max(1e-9);
+        // The time limit may stop the render before it takes the remaining samples.
+        let until_deadline = self.deadline.map_or(f64::INFINITY, |deadline| {
+            deadline
+                .saturating_duration_since(Instant::now())
+                .as_secs_f64()
+        });
+        let eta = match ((self.total_samples - done) as f64 / rate).min(until_deadline) {
+            left if left.is_finite() => format_seconds(left),
+            _ => "unknown".to_string(),
+        };
+        let line = format!(
+            "Rendering {:5.1}%, {:.0} samples/s, ETA {eta}",
+            100.0 * done as f64 / self.total_samples.max(1) as f64,
+            rate
+        );
+        if self.terminal {
+            // Trailing spaces cover the end of a longer previous line.
+            eprint!("\r{line}    ");
+        } else {
+            eprintln!("{line}");
+        }
+    }
+}
+
+/// Formats a duration as hours, minutes and seconds.
+fn format_seconds(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+/// Whether a sample can be divided by its density, which rules out the zero, infinite or NaN
+/// densities of degenerate directions
+fn is_usable_density(pdf: f64) -> bool {
+    pdf > 0.0 && pdf.is_finite()
+}
+
+/// Multiple importance sampling weight of a sample taken with density `pdf`, when the same
+/// direction could also have been sampled with `other_pdf` by the other strategy.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
 fn sample_square() -> Vec3 {
     // Returns the vector to a random point in the [-.5,-.5]-[+.5,+.5] unit square.
-    vec3(random_double() - 0.5, random_double() - 0.5, 0.0)
+    let (x, y) = sample_2d(Dimension::Pixel);
+    vec3(x - 0.5, y - 0.5, 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        accumulator::SceneHasher,
+        aov::Aov,
+        bvh::BvhNode,
+        constant_medium::ConstantMedium,
+        filter::FilterKind,
+        material::{Dielectric, DiffuseLight, Lambertian, Material},
+        quad::Quad,
+        ray::ray,
+        sphere::Sphere,
+    };
+
+    fn test_world() -> HittableList {
+        let mut world = HittableList::default();
+        let ground = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        world.add(Arc::new(Sphere::new(
+            vec3(0.0, -100.5, -1.0),
+            100.0,
+            ground,
+        )));
+        let center = Arc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
+        world.add(Arc::new(Sphere::new(vec3(0.0, 0.0, -1.0), 0.5, center)));
+        world
+    }
+
+    fn test_camera() -> Camera {
+        Camera {
+            aspect_ratio: 2.0,
+            image_width: 16,
+            samples_per_pixel: 5,
+            background: Color::new(0.7, 0.8, 1.0),
+            threads: 2,
+            ..Default::default()
+        }
+    }
+
+    /// Light whose emission is broken
+    struct NanLight;
+
+    impl Material for NanLight {
+        fn emitted(&self, _u: f64, _v: f64, _p: &Vec3) -> Color {
+            Color::new(f64::NAN, 1.0, 1.0)
+        }
+    }
+
+    fn channels(image: &Framebuffer) -> Vec<[f64; 3]> {
+        image.pixels().iter().map(|c| [c.r, c.g, c.b]).collect()
+    }
+
+    #[test]
+    fn passes_match_single_render() {
+        let world = test_world();
+        let lights = HittableList::default();
+        let expected = test_camera().render(&world, &lights);
+
+        // Stop halfway and continue, like a resumed render.
+        let mut camera = test_camera();
+        let (width, height) = camera.image_size();
+        let mut accumulator = Accumulator::new(width, height);
+        camera.samples_per_pixel = 2;
+        camera.render_into(&world, &lights, &mut accumulator, |_| {});
+        assert_eq!(accumulator.min_sample_count(), 2);
+
+        let mut passes = 0;
+        camera.samples_per_pixel = 5;
+        camera.render_into(&world, &lights, &mut accumulator, |_| passes += 1);
+        assert_eq!(passes, 2);
+        assert_eq!(
+            accumulator.total_sample_count(),
+            5 * (width * height) as u64
+        );
+        assert_eq!(channels(&accumulator.average()), channels(&expected));
+    }
+
+    #[test]
+    fn filtered_render_is_independent_of_threads() {
+        let world = test_world();
+        let lights = HittableList::default();
+        let filtered_camera = |threads| Camera {
+            filter: Filter::new(FilterKind::Mitchell),
+            threads,
+            ..test_camera()
+        };
+        let image = filtered_camera(1).render(&world, &lights);
+        assert_eq!(
+            channels(&image),
+            channels(&filtered_camera(3).render(&world, &lights))
+        );
+
+        // The filter weights are normalized, so the plain background keeps its color.
+        let corner = image.pixel(0, 0);
+        assert!((corner.b - 1.0).abs() < 1e-9, "{}", corner.b);
+    }
+
+    #[test]
+    fn seeded_render_is_independent_of_threads() {
+        // Glass, a light and a medium use random numbers beyond the diffuse bounces.
+        let mut world = test_world();
+        let glass = Arc::new(Dielectric::new(1.5));
+        world.add(Arc::new(Sphere::new(vec3(1.0, 0.0, -1.0), 0.5, glass)));
+        let fog = Arc::new(Sphere::new(
+            vec3(-1.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Lambertian::new(Color::white())),
+        ));
+        world.add(Arc::new(ConstantMedium::new(fog, 2.0, Color::white())));
+        let light = Arc::new(Quad::new(
+            vec3(-1.0, 2.0, -2.0),
+            vec3(2.0, 0.0, 0.0),
+            vec3(0.0, 0.0, 2.0),
+            Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+        ));
+        world.add(light.clone());
+        let mut lights = HittableList::default();
+        lights.add(light);
+
+        let render = |seed, threads| {
+            let mut camera = Camera {
+                seed,
+                threads,
+                ..test_camera()
+            };
+            let image = camera.render(&world, &lights);
+            image
+                .pixels()
+                .iter()
+                .flat_map(|c| [c.r.to_bits(), c.g.to_bits(), c.b.to_bits()])
+                .collect::<Vec<_>>()
+        };
+        let image = render(42, 1);
+        assert_eq!(render(42, 1), image);
+        assert_eq!(render(42, 4), image);
+        assert_ne!(render(43, 4), image);
+    }
+
+    #[test]
+    fn hash_includes_sample_count_only_for_stratified() {
+        let hash = |sampler: &str, samples_per_pixel| {
+            let camera = Camera {
+                sampler: crate::sampler::by_name(sampler).unwrap(),
+                samples_per_pixel,
+                ..test_camera()
+            };
+            let mut hasher = SceneHasher::default();
+            camera.hash_settings(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash("sobol", 16), hash("sobol", 64));
+        assert_ne!(hash("stratified", 16), hash("stratified", 64));
+    }
+
+    #[test]
+    fn lighting_aovs_add_up_to_the_image() {
+        let world = test_world();
+        let mut camera = test_camera();
+        let (width, height) = camera.image_size();
+        let mut accumulator = Accumulator::with_aovs(width, height);
+        camera.render_into(&world, &HittableList::default(), &mut accumulator, |_| {});
+
+        let image = accumulator.average();
+        let direct = accumulator.aov(Aov::Direct).unwrap();
+        let indirect = accumulator.aov(Aov::Indirect).unwrap();
+        let material_ids = accumulator.aov(Aov::MaterialId).unwrap();
+
+        // The sky is all emission, the ground and the sphere only reflect light.
+        assert_eq!(material_ids.pixel(0, 0).r, 0.0);
+        assert_eq!(direct.pixel(0, 0).b, 0.0);
+        assert_eq!(material_ids.pixel(width / 2, height - 1).r, 1.0);
+        assert_eq!(material_ids.pixel(width / 2, height / 2).r, 2.0);
+        for (x, y) in [(width / 2, height - 1), (width / 2, height / 2)] {
+            let sum = direct.pixel(x, y) + indirect.pixel(x, y);
+            assert!((sum.g - image.pixel(x, y).g).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn time_limit_stops_early() {
+        let world = test_world();
+        let mut camera = Camera {
+            samples_per_pixel: 1_000_000,
+            time_limit: Some(Duration::from_millis(50)),
+            ..test_camera()
+        };
+        let (width, height) = camera.image_size();
+        let mut accumulator = Accumulator::new(width, height);
+        camera.render_into(&world, &HittableList::default(), &mut accumulator, |_| {});
+        assert!(accumulator.min_sample_count() < 1_000_000);
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_converged_pixels() {
+        let world = test_world();
+        let adaptive_camera = || Camera {
+            samples_per_pixel: 64,
+            min_samples_per_pixel: 4,
+            error_threshold: 0.01,
+            ..test_camera()
+        };
+        let (mut camera, budget) = (adaptive_camera(), adaptive_camera());
+        let (width, height) = camera.image_size();
+        let mut accumulator = Accumulator::new(width, height);
+        let pixels = (width * height) as u64;
+        assert_eq!(budget.remaining_samples(&accumulator), pixels * 64);
+
+        // Once the background pixels stop at 4 samples, the budget only covers the pixels still
+        // sampling.
+        let mut budgets = Vec::new();
+        let on_pass =
+            |accumulator: &Accumulator| budgets.push(budget.remaining_samples(accumulator));
+        assert!(camera.render_into(&world, &HittableList::default(), &mut accumulator, on_pass));
+        assert_eq!(budgets.last(), Some(&0));
+        assert!(budgets[2] < pixels * (64 - 4));
+
+        // The background is the same for every sample, the edge of the sphere isn't.
+        assert_eq!(accumulator.sample_count(0, 0), 4);
+        assert_eq!(accumulator.error(0, 0), 0.0);
+        let most_samples = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| accumulator.sample_count(x, y))
+            .max();
+        assert_eq!(most_samples, Some(64));
+    }
+
+    #[test]
+    fn invalid_samples_are_counted_as_black() {
+        let mut camera = test_camera();
+        camera.render(&test_world(), &HittableList::default());
+        assert_eq!(camera.invalid_samples(), InvalidSamples::default());
+
+        // Swap the center sphere for a broken light.
+        let mut world = test_world();
+        world.objects.pop();
+        let broken = Arc::new(NanLight);
+        world.add(Arc::new(Sphere::new(vec3(0.0, 0.0, -1.0), 0.5, broken)));
+        let image = camera.render(&world, &HittableList::default());
+        let invalid = camera.invalid_samples();
+        assert!(image.pixels().iter().all(|pixel| pixel.is_finite()));
+        assert!(invalid.count > 0);
+        assert_eq!(invalid.pixels.len(), MAX_INVALID_PIXELS);
+        assert!(invalid.pixels.is_sorted_by_key(|&(x, y)| (y, x)));
+
+        // The center pixel only sees the broken light and turns black.
+        let (width, height) = camera.image_size();
+        assert_eq!(image.pixel(width / 2, height / 2).g, 0.0);
+
+        let mut threaded = Camera {
+            threads: 3,
+            ..test_camera()
+        };
+        threaded.render(&world, &HittableList::default());
+        assert_eq!(threaded.invalid_samples(), invalid);
+    }
+
+    #[test]
+    fn degenerate_quad_light_is_ignored() {
+        let mut world = test_world();
+        let light = Arc::new(Quad::new(
+            vec3(-1.0, 2.0, -1.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(2.0, 0.0, 0.0),
+            Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+        ));
+        let through_line = ray(vec3(0.5, 3.0, -1.0), vec3(0.0, -1.0, 0.0));
+        let mut rec = HitRecord::default();
+        assert!(!light.hit(&through_line, Interval::new(0.0, 10.0), &mut rec));
+        let density = light.pdf_value(through_line.origin(), through_line.direction());
+        assert_eq!(density, 0.0);
+
+        world.add(light.clone());
+        let mut lights = HittableList::default();
+        lights.add(light);
+
+        let mut camera = test_camera();
+        let image = camera.render(&world, &lights);
+        assert_eq!(camera.invalid_samples().count, 0);
+        assert!(image.pixels().iter().all(|pixel| pixel.is_finite()));
+    }
+
+    #[test]
+    fn light_sampling_matches_bsdf_sampling() {
+        // A square light of side 2 and emission 4, one unit above a gray floor.
+        let mut world = HittableList::default();
+        world.add(Arc::new(Quad::new(
+            vec3(-10.0, 0.0, -10.0),
+            vec3(0.0, 0.0, 20.0),
+            vec3(20.0, 0.0, 0.0),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+        let light = Arc::new(Quad::new(
+            vec3(-1.0, 1.0, -1.0),
+            vec3(2.0, 0.0, 0.0),
+            vec3(0.0, 0.0, 2.0),
+            Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+        ));
+        world.add(light.clone());
+        let mut lights = HittableList::default();
+        lights.add(light);
+
+        // Two bounces only gather the light reflected straight from the floor.
+        let camera = Camera {
+            background: Color::black(),
+            ..test_camera()
+        };
+        let down = ray(vec3(0.0, 0.5, 0.0), vec3(0.0, -1.0, 0.0));
+        let estimate = |lights: &HittableList| {
+            seed_random(3, 0);
+            let samples = 100_000;
+            let sum: f64 = (0..samples)
+                .map(|_| camera.ray_color(&down, 2, &world, lights).g)
+                .sum();
+            sum / samples as f64
+        };
+
+        // The form factor of the light seen from the point below its center is
+        // 4 · 1/(2π) · 2 · atan(1/√2)/√2.
+        let form_factor = 4.0 * f64::atan(f64::sqrt(0.5)) / (f64::sqrt(2.0) * f64::consts::PI);
+        let expected = 0.5 * 4.0 * form_factor;
+        let bsdf_only = estimate(&HittableList::default());
+        let with_lights = estimate(&lights);
+        assert!(
+            (bsdf_only - expected).abs() < 0.02,
+            "{bsdf_only} {expected}"
+        );
+        assert!(
+            (with_lights - expected).abs() < 0.01,
+            "{with_lights} {expected}"
+        );
+    }
+
+    #[test]
+    fn indirect_clamp_limits_indirect_light() {
+        let world = test_world();
+        let render = |indirect_clamp| {
+            let mut camera = Camera {
+                indirect_clamp,
+                ..test_camera()
+            };
+            let (width, height) = camera.image_size();
+            let mut accumulator = Accumulator::with_aovs(width, height);
+            camera.render_into(&world, &HittableList::default(), &mut accumulator, |_| {});
+            accumulator
+        };
+        let unclamped = render(0.0);
+        let clamped = render(0.05);
+
+        let indirect = clamped.aov(Aov::Indirect).unwrap();
+        assert!(indirect
+            .pixels()
+            .iter()
+            .all(|c| c.luminance() <= 0.05 + 1e-9));
+        let brightness =
+            |image: Framebuffer| -> f64 { image.pixels().iter().map(|c| c.luminance()).sum() };
+        assert!(brightness(clamped.average()) < brightness(unclamped.average()));
+    }
+
+    #[test]
+    fn stats_count_the_work_of_the_render() {
+        let mut objects = test_world();
+        let world = HittableList::new(Arc::new(BvhNode::from_list(&mut objects)));
+        let render = |threads| {
+            let mut camera = Camera {
+                threads,
+                ..test_camera()
+            };
+            camera.render(&world, &HittableList::default());
+            camera.stats()
+        };
+        let stats = render(1);
+
+        let (width, height) = test_camera().image_size();
+        assert_eq!(stats.primary_rays, 5 * (width * height) as u64);
+        assert!(stats.bounce_rays > 0 && stats.shadow_rays == 0);
+        assert!(stats.bvh_node_visits >= stats.primary_rays + stats.bounce_rays);
+        assert!(stats.primitive_tests > 0);
+        assert!(stats.average_path_length() > 1.0);
+        assert!(stats.wall_time > Duration::ZERO);
+        assert_eq!(stats.cpu_time.is_some(), cfg!(unix));
+
+        // Every sample traces the same paths whichever thread takes it.
+        let threaded = render(3);
+        assert_eq!(
+            (threaded.secondary_rays(), threaded.bvh_node_visits),
+            (stats.secondary_rays(), stats.bvh_node_visits)
+        );
+        assert_eq!(threaded.primitive_tests, stats.primitive_tests);
+    }
 }
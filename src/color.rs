@@ -1,4 +1,4 @@
-use crate::interval::Interval;
+use std::io::{self, Write};
 
 #[derive(Clone, Default, Copy)]
 pub struct Color {
@@ -8,7 +8,7 @@ pub struct Color {
 }
 
 impl Color {
-    pub fn new(r: f64, g: f64, b: f64) -> Self {
+    pub const fn new(r: f64, g: f64, b: f64) -> Self {
         Color { r, g, b }
     }
     pub fn white() -> Self {
@@ -17,33 +17,72 @@ impl Color {
     pub fn black() -> Self {
         Color::new(0.0, 0.0, 0.0)
     }
+
+    /// Whether no component is NaN or infinite
+    pub fn is_finite(&self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite()
+    }
+
+    /// Perceived brightness of the linear color, weighted with the Rec. 709 coefficients
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
 }
 
-fn linear_to_gamma(linear_component: f64) -> f64 {
-    if linear_component > 0.0 {
-        return f64::sqrt(linear_component);
+/// Encodes a linear component with the sRGB transfer function.
+pub fn linear_to_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear.max(0.0)
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
     }
-    0.0
 }
 
-pub fn write_color(color: Color) {
-    let mut r = color.r;
-    let mut g = color.g;
-    let mut b = color.b;
+/// Slope of [`linear_to_srgb`] at a linear component, by how much small changes of it show in
+/// the encoded value.
+pub fn srgb_slope(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92
+    } else {
+        1.055 / 2.4 * linear.powf(1.0 / 2.4 - 1.0)
+    }
+}
 
-    // Apply a linear to gamma transform for gamma 2
-    r = linear_to_gamma(r);
-    g = linear_to_gamma(g);
-    b = linear_to_gamma(b);
+/// Decodes an sRGB encoded component back to linear, the inverse of [`linear_to_srgb`].
+pub fn srgb_to_linear(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
 
-    // Translate the [0,1] component values to the byte range [0,255]
-    let intensity = Interval::new(0.000, 0.999);
-    let ir = (255.999 * intensity.clamp(r)) as i32;
-    let ig = (255.999 * intensity.clamp(g)) as i32;
-    let ib = (255.999 * intensity.clamp(b)) as i32;
+/// Encodes a linear color between 0 and 1 as 8 bit sRGB.
+///
+/// # Arguments
+///
+/// * `color` - Linear color, components outside [0, 1] are clamped
+/// * `dither` - Noise in steps of the 8 bit values added to every channel before rounding,
+///   channels that are exactly black or white aren't dithered
+pub fn to_rgb8(color: Color, dither: [f64; 3]) -> [u8; 3] {
+    let quantize = |linear: f64, dither: f64| {
+        let encoded = linear_to_srgb(linear).clamp(0.0, 1.0);
+        if encoded == 0.0 || encoded == 1.0 {
+            return (255.0 * encoded) as u8;
+        }
+        (255.0 * encoded + dither).round().clamp(0.0, 255.0) as u8
+    };
+
+    [
+        quantize(color.r, dither[0]),
+        quantize(color.g, dither[1]),
+        quantize(color.b, dither[2]),
+    ]
+}
 
+pub fn write_color(out: &mut impl Write, [ir, ig, ib]: [u8; 3]) -> io::Result<()> {
     // Write out the pixel color components.
-    print!("{} {} {}\n", ir, ig, ib);
+    writeln!(out, "{} {} {}", ir, ig, ib)
 }
 
 impl std::ops::Add<Color> for Color {
@@ -58,6 +97,18 @@ impl std::ops::Add<Color> for Color {
     }
 }
 
+impl std::ops::Sub<Color> for Color {
+    type Output = Color;
+
+    fn sub(self, rhs: Color) -> Self::Output {
+        Color {
+            r: self.r - rhs.r,
+            g: self.g - rhs.g,
+            b: self.b - rhs.b,
+        }
+    }
+}
+
 impl std::ops::Mul<f64> for Color {
     type Output = Color;
 
@@ -1,39 +1,60 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::{
     color::Color,
     interval::Interval,
-    material::Material,
-    rtweekend::random_double,
-    sphere::{HitRecord, Hittable},
+    material::{Isotropic, Material},
+    sampler::{sample_1d, Dimension},
+    sphere::{debug_assert_hit_in_bounds, HitRecord, Hittable},
     texture::Texture,
     vec3,
 };
 
 pub struct ConstantMedium {
-    boundary: Rc<dyn Hittable>,
+    boundary: Arc<dyn Hittable>,
     neg_inv_density: f64,
-    phase_function: Material,
+    phase_function: Arc<dyn Material>,
 }
 
 impl ConstantMedium {
     pub fn from_tex(
-        boundary: Rc<dyn Hittable>,
+        boundary: Arc<dyn Hittable>,
         density: f64,
-        tex: Rc<dyn Texture>,
+        tex: Arc<dyn Texture>,
     ) -> ConstantMedium {
-        ConstantMedium {
-            boundary,
-            neg_inv_density: (-1.0 / density),
-            phase_function: Material::isotropic_from_tex(tex),
-        }
+        ConstantMedium::with_phase_function(boundary, density, Arc::new(Isotropic::from_tex(tex)))
+    }
+
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, albedo: Color) -> ConstantMedium {
+        ConstantMedium::with_phase_function(boundary, density, Arc::new(Isotropic::new(albedo)))
     }
 
-    pub fn new(boundary: Rc<dyn Hittable>, density: f64, albedo: Color) -> ConstantMedium {
+    /// Convex object enclosing the medium
+    pub fn boundary(&self) -> &dyn Hittable {
+        &*self.boundary
+    }
+
+    /// Material deciding how light scatters inside the medium
+    pub fn phase_function(&self) -> &Arc<dyn Material> {
+        &self.phase_function
+    }
+
+    /// Creates a medium of constant density
+    ///
+    /// # Arguments
+    ///
+    /// * `boundary` - Convex object enclosing the medium
+    /// * `density` - Density of the medium
+    /// * `phase_function` - Material deciding how light scatters inside the medium
+    pub fn with_phase_function(
+        boundary: Arc<dyn Hittable>,
+        density: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> ConstantMedium {
         ConstantMedium {
             boundary,
             neg_inv_density: (-1.0 / density),
-            phase_function: Material::isotropic(albedo),
+            phase_function,
         }
     }
 }
@@ -72,7 +93,7 @@ impl Hittable for ConstantMedium {
 
         let ray_length = r.direction().length();
         let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * f64::ln(random_double());
+        let hit_distance = self.neg_inv_density * f64::ln(sample_1d(Dimension::Medium));
 
         if hit_distance > distance_inside_boundary {
             return false;
@@ -84,6 +105,7 @@ impl Hittable for ConstantMedium {
         rec.normal = vec3(1.0, 0.0, 0.0); // arbitrary
         rec.front_face = true; // also arbitrary
         rec.mat = self.phase_function.clone();
+        debug_assert_hit_in_bounds(self, rec);
 
         true
     }
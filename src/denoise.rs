@@ -0,0 +1,381 @@
+use std::thread;
+
+use crate::{
+    accumulator::Accumulator,
+    aov::Aov,
+    color::Color,
+    framebuffer::Framebuffer,
+    vec3::{dot, vec3, Vec3},
+};
+
+/// Taps of the B3 spline the filter kernel is made of
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Exponent sharpening the falloff of the weights with the angle between normals
+const NORMAL_SHARPNESS: i32 = 128;
+
+/// Albedo below which a color channel isn't divided by its albedo
+const MIN_ALBEDO: f64 = 0.01;
+
+/// Largest number of filter passes, the last one spreads its taps 2^16 pixels apart
+pub const MAX_PASSES: u32 = 16;
+
+/// Largest variance used, so that pixels with unknown variance don't turn weights into NaN
+const MAX_VARIANCE: f64 = 1e10;
+
+/// Post-process removing noise from a rendered image with edge-avoiding à-trous wavelet filtering,
+/// the spatial part of SVGF (Schied et al., "Spatiotemporal Variance-Guided Filtering").
+///
+/// Every pass blurs the image with a 5x5 kernel whose taps are spread twice as far apart as in the
+/// pass before, so a few passes cover a wide area. Neighbors only count for a pixel as far as they
+/// show the same material facing the same way at a similar depth, and have a brightness within
+/// the noise the pixel's variance predicts. The lighting is filtered with the albedo divided out
+/// and multiplied back in afterwards, which keeps textures sharp.
+///
+/// The result only depends on the accumulated samples, not on the number of threads filtering
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denoiser {
+    /// Number of filter passes, the filtered area is 4 · (2^passes - 1) + 1 pixels wide
+    pub passes: u32,
+    /// Brightness differences between neighbors that still get blurred, in standard deviations
+    /// of the pixel's noise
+    pub strength: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            passes: 5,
+            strength: 4.0,
+        }
+    }
+}
+
+/// Values of a pixel guiding the filter
+#[derive(Clone, Copy)]
+struct Features {
+    normal: Vec3,
+    depth: f64,
+    depth_gradient: f64, // Largest depth difference to a direct neighbor
+    material_id: u32,
+}
+
+/// Features of all pixels of an image
+struct FeatureImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<Features>,
+}
+
+impl Denoiser {
+    /// Returns the denoised average of the accumulated samples, filtered by up to `threads`
+    /// threads in parallel.
+    ///
+    /// # Panics
+    ///
+    /// If the accumulator doesn't keep AOVs, see [`Accumulator::with_aovs`].
+    pub fn denoise(&self, accumulator: &Accumulator, threads: usize) -> Framebuffer {
+        let aov = |aov| {
+            accumulator
+                .aov(aov)
+                .expect("denoising needs an accumulator with AOVs")
+        };
+        let (width, height) = (accumulator.width(), accumulator.height());
+        if width == 0 || height == 0 {
+            return Framebuffer::new(width, height);
+        }
+        let image = accumulator.average();
+        let albedo = aov(Aov::Albedo);
+        let normals = aov(Aov::Normal);
+        let depths = aov(Aov::Depth);
+        let material_ids = aov(Aov::MaterialId);
+
+        // Filter the light falling onto the surfaces instead of their color, with the albedo
+        // divided out where it isn't too dark to do that.
+        let albedo: Vec<Color> = albedo
+            .pixels()
+            .iter()
+            .map(|a| {
+                let channel = |value: f64| if value > MIN_ALBEDO { value } else { 1.0 };
+                Color::new(channel(a.r), channel(a.g), channel(a.b))
+            })
+            .collect();
+        let mut irradiance: Vec<Color> = image
+            .pixels()
+            .iter()
+            .zip(&albedo)
+            .map(|(color, albedo)| {
+                Color::new(color.r / albedo.r, color.g / albedo.g, color.b / albedo.b)
+            })
+            .collect();
+        let mut variance: Vec<f64> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .zip(&albedo)
+            .map(|((x, y), albedo)| {
+                let scale = albedo.luminance();
+                f64::min(accumulator.variance(x, y) / (scale * scale), MAX_VARIANCE)
+            })
+            .collect();
+
+        let depth = |x: u32, y: u32| depths.pixel(x, y).r;
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let neighbors = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                let depth_gradient = neighbors
+                    .into_iter()
+                    .filter(|&(nx, ny)| nx < width && ny < height)
+                    .map(|(nx, ny)| f64::abs(depth(nx, ny) - depth(x, y)))
+                    .fold(0.0, f64::max);
+                let normal = normals.pixel(x, y);
+                Features {
+                    normal: vec3(normal.r, normal.g, normal.b),
+                    depth: depth(x, y),
+                    depth_gradient,
+                    material_id: material_ids.pixel(x, y).r as u32,
+                }
+            })
+            .collect();
+
+        let features = FeatureImage {
+            width,
+            height,
+            pixels,
+        };
+        for pass in 0..self.passes {
+            let step = 1 << pass.min(MAX_PASSES);
+            (irradiance, variance) =
+                self.filter_pass(step, threads, &features, &irradiance, &variance);
+        }
+
+        let mut denoised = Framebuffer::new(width, height);
+        for ((pixel, irradiance), albedo) in denoised
+            .pixels_mut()
+            .iter_mut()
+            .zip(&irradiance)
+            .zip(&albedo)
+        {
+            *pixel = *irradiance * *albedo;
+        }
+        denoised
+    }
+
+    /// Runs one pass of the filter with the taps `step` pixels apart on up to `threads` threads,
+    /// and returns the filtered colors together with their variances.
+    fn filter_pass(
+        &self,
+        step: i64,
+        threads: usize,
+        features: &FeatureImage,
+        colors: &[Color],
+        variances: &[f64],
+    ) -> (Vec<Color>, Vec<f64>) {
+        let (width, height) = (features.width, features.height);
+        let mut filtered = vec![(Color::black(), 0.0); colors.len()];
+
+        // Every pixel only reads the previous pass, so bands of rows can be filtered in parallel.
+        let rows_per_band = (height as usize).div_ceil(threads.max(1)).max(1);
+        thread::scope(|s| {
+            for (band, pixels) in filtered
+                .chunks_mut(rows_per_band * width as usize)
+                .enumerate()
+            {
+                s.spawn(move || {
+                    let first_pixel = band * rows_per_band * width as usize;
+                    for (offset, pixel) in pixels.iter_mut().enumerate() {
+                        let index = first_pixel + offset;
+                        let (x, y) = (
+                            (index % width as usize) as i64,
+                            (index / width as usize) as i64,
+                        );
+                        *pixel = self.filter_pixel(step, x, y, features, colors, variances);
+                    }
+                });
+            }
+        });
+
+        filtered.into_iter().unzip()
+    }
+
+    /// Filters pixel `x`, `y`, see [`Denoiser::filter_pass`].
+    fn filter_pixel(
+        &self,
+        step: i64,
+        x: i64,
+        y: i64,
+        features: &FeatureImage,
+        colors: &[Color],
+        variances: &[f64],
+    ) -> (Color, f64) {
+        let (width, height) = (features.width as i64, features.height as i64);
+        let index = (y * width + x) as usize;
+        let (color, center) = (colors[index], &features.pixels[index]);
+        let luminance = color.luminance();
+        let luminance_tolerance =
+            self.strength * f64::sqrt(local_variance(x, y, width, height, variances)) + 1e-10;
+
+        let mut color_sum = Color::black();
+        let mut variance_sum = 0.0;
+        let mut weight_sum = 0.0;
+        for (j, kernel_y) in KERNEL.iter().enumerate() {
+            for (i, kernel_x) in KERNEL.iter().enumerate() {
+                let (dx, dy) = (i as i64 - 2, j as i64 - 2);
+                let (qx, qy) = (x + dx * step, y + dy * step);
+                if !(0..width).contains(&qx) || !(0..height).contains(&qy) {
+                    continue;
+                }
+                let q = (qy * width + qx) as usize;
+
+                let mut weight = kernel_x * kernel_y;
+                if q != index {
+                    let other = &features.pixels[q];
+                    if other.material_id != center.material_id {
+                        continue;
+                    }
+                    let distance = step as f64 * f64::hypot(dx as f64, dy as f64);
+                    let depth_tolerance = center.depth_gradient * distance + 1e-6;
+                    let normal_weight =
+                        f64::max(dot(&center.normal, &other.normal), 0.0).powi(NORMAL_SHARPNESS);
+                    let depth_weight = f64::abs(center.depth - other.depth) / depth_tolerance;
+                    let luminance_weight =
+                        f64::abs(luminance - colors[q].luminance()) / luminance_tolerance;
+                    weight *= normal_weight * f64::exp(-depth_weight - luminance_weight);
+                }
+
+                color_sum += weight * colors[q];
+                variance_sum += weight * weight * variances[q];
+                weight_sum += weight;
+            }
+        }
+
+        // The center tap always counts, so the weights never sum up to zero.
+        (
+            (1.0 / weight_sum) * color_sum,
+            variance_sum / (weight_sum * weight_sum),
+        )
+    }
+}
+
+/// Variance of pixel `x`, `y` blurred with its direct neighbors, so that the noise of a pixel
+/// without outliers isn't underestimated next to pixels with them
+fn local_variance(x: i64, y: i64, width: i64, height: i64, variances: &[f64]) -> f64 {
+    const WEIGHTS: [f64; 3] = [0.25, 0.5, 0.25];
+    let mut sum = 0.0;
+    let mut weight_sum = 0.0;
+    for (dy, weight_y) in (-1..=1).zip(WEIGHTS) {
+        for (dx, weight_x) in (-1..=1).zip(WEIGHTS) {
+            let (qx, qy) = (x + dx, y + dy);
+            if (0..width).contains(&qx) && (0..height).contains(&qy) {
+                sum += weight_x * weight_y * variances[(qy * width + qx) as usize];
+                weight_sum += weight_x * weight_y;
+            }
+        }
+    }
+    sum / weight_sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aov::AovSample;
+
+    /// Image whose left half shows material 1 with a brightness of 0.2 and whose right half shows
+    /// material 2 with a brightness of 0.8, both with noise of ±0.1.
+    fn noisy_halves(width: u32, height: u32) -> Accumulator {
+        let mut accumulator = Accumulator::with_aovs(width, height);
+        let mut noise = 12345u32;
+        for mut row in accumulator.pass_mut().0 {
+            for x in 0..width as usize {
+                let left = x < width as usize / 2;
+                for _ in 0..4 {
+                    noise = noise.wrapping_mul(1664525).wrapping_add(1013904223);
+                    let offset = (noise >> 8) as f64 / (1 << 24) as f64 * 0.2 - 0.1;
+                    let value = if left { 0.2 } else { 0.8 } + offset;
+                    row.add(x, Color::new(value, value, value));
+                    row.add_aovs(
+                        x,
+                        &AovSample {
+                            albedo: Color::new(0.5, 0.5, 0.5),
+                            normal: vec3(0.0, 0.0, 1.0),
+                            depth: 1.0,
+                            material_id: if left { 1 } else { 2 },
+                            ..AovSample::default()
+                        },
+                    );
+                    row.counts[x] += 1;
+                }
+            }
+        }
+        accumulator
+    }
+
+    fn spread(image: &Framebuffer, columns: std::ops::Range<u32>) -> (f64, f64) {
+        let values: Vec<f64> = (0..image.height())
+            .flat_map(|y| columns.clone().map(move |x| (x, y)))
+            .map(|(x, y)| image.pixel(x, y).g)
+            .collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance =
+            values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / values.len() as f64;
+        (mean, variance)
+    }
+
+    #[test]
+    fn denoising_smooths_without_crossing_edges() {
+        let accumulator = noisy_halves(16, 8);
+        let noisy = accumulator.average();
+        let denoised = Denoiser::default().denoise(&accumulator, 3);
+
+        for (columns, expected) in [(0..8, 0.2), (8..16, 0.8)] {
+            let (_, noisy_variance) = spread(&noisy, columns.clone());
+            let (mean, variance) = spread(&denoised, columns.clone());
+            assert!(
+                variance < noisy_variance / 4.0,
+                "{variance} {noisy_variance}"
+            );
+            assert!((mean - expected).abs() < 0.02, "{mean}");
+        }
+        // The columns next to the edge don't pick up the other side.
+        assert!((spread(&denoised, 7..8).0 - 0.2).abs() < 0.02);
+        assert!((spread(&denoised, 8..9).0 - 0.8).abs() < 0.02);
+    }
+
+    #[test]
+    fn no_passes_keep_the_image() {
+        let accumulator = noisy_halves(6, 3);
+        let denoised = Denoiser {
+            passes: 0,
+            ..Denoiser::default()
+        }
+        .denoise(&accumulator, 1);
+        for (a, b) in denoised.pixels().iter().zip(accumulator.average().pixels()) {
+            assert!((a.g - b.g).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn result_is_independent_of_threads() {
+        let accumulator = noisy_halves(16, 8);
+        let single = Denoiser::default().denoise(&accumulator, 1);
+        for threads in [0, 2, 5, 64] {
+            let denoised = Denoiser::default().denoise(&accumulator, threads);
+            for (a, b) in denoised.pixels().iter().zip(single.pixels()) {
+                assert_eq!([a.r, a.g, a.b], [b.r, b.g, b.b]);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_image_stays_empty() {
+        for (width, height) in [(0, 0), (0, 4), (4, 0)] {
+            let denoised = Denoiser::default().denoise(&Accumulator::with_aovs(width, height), 4);
+            assert_eq!((denoised.width(), denoised.height()), (width, height));
+        }
+    }
+}
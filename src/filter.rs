@@ -0,0 +1,260 @@
+use std::{f64::consts::PI, str::FromStr};
+
+/// Shape of a reconstruction filter
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    /// Constant weight, with the default radius every sample only counts for its own pixel.
+    #[default]
+    Box,
+    /// Weight falling off linearly towards the radius
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius, shifted to reach zero at the
+    /// radius
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3, a compromise between blurring and ringing
+    Mitchell,
+    /// Sinc windowed by a wider sinc, which keeps the most detail but rings around hard edges
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::Lanczos => "lanczos",
+        }
+    }
+
+    /// Radius in pixels the filter is usually used with
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "box" => Ok(FilterKind::Box),
+            "tent" | "triangle" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            "lanczos" => Ok(FilterKind::Lanczos),
+            _ => Err(format!(
+                "unknown filter `{s}`, expected one of box, tent, gaussian, mitchell, lanczos"
+            )),
+        }
+    }
+}
+
+/// Largest filter radius in pixels
+pub const MAX_RADIUS: f64 = 16.0;
+
+/// Reconstruction filter deciding how much a sample counts for the pixels around it. Every pixel
+/// is the weighted average of the samples within the radius of its center, weighted by the
+/// filter value of their horizontal and vertical offsets multiplied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filter {
+    kind: FilterKind,
+    radius: f64,
+}
+
+impl Filter {
+    /// Creates a filter with the default radius of its kind
+    pub fn new(kind: FilterKind) -> Filter {
+        Filter::with_radius(kind, kind.default_radius())
+    }
+
+    /// Creates a filter
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Shape of the filter
+    /// * `radius` - Offset in pixels beyond which samples don't count
+    ///
+    /// # Panics
+    ///
+    /// If the radius isn't positive or is larger than [`MAX_RADIUS`].
+    pub fn with_radius(kind: FilterKind, radius: f64) -> Filter {
+        assert!(
+            radius > 0.0 && radius <= MAX_RADIUS,
+            "filter radius must be positive and at most {MAX_RADIUS}"
+        );
+        Filter { kind, radius }
+    }
+
+    pub fn kind(&self) -> FilterKind {
+        self.kind
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
+
+    /// Weight of a sample at offset `x` from a pixel center along one axis. Mitchell and Lanczos
+    /// filters are negative in places.
+    ///
+    /// The radius itself still belongs to the filter, so that a box filter with radius 0.5 counts
+    /// every sample for exactly one pixel.
+    pub fn evaluate(&self, x: f64) -> f64 {
+        let x = x.abs();
+        if x > self.radius {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => 1.0 - x / self.radius,
+            FilterKind::Gaussian => {
+                let sigma = self.radius / 3.0;
+                let gaussian = |x: f64| f64::exp(-x * x / (2.0 * sigma * sigma));
+                gaussian(x) - gaussian(self.radius)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / self.radius),
+            FilterKind::Lanczos => sinc(x) * sinc(x / self.radius),
+        }
+    }
+
+    /// Number of pixels on either side of its own pixel that a sample can count for
+    pub fn reach(&self) -> i32 {
+        (self.radius + 0.5).ceil() as i32 - 1
+    }
+
+    /// Pixels along one axis that a sample counts for, as offsets from the pixel it was taken
+    /// for, with their nonzero weights.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - Position of the sample relative to the center of its pixel, within ±0.5
+    pub fn footprint(&self, offset: f64) -> impl Iterator<Item = (i32, f64)> + '_ {
+        let first = (offset - self.radius).floor() as i32 + 1;
+        let last = (offset + self.radius).floor() as i32;
+        (first..=last)
+            .map(move |pixel| (pixel, self.evaluate(pixel as f64 - offset)))
+            .filter(|&(_, weight)| weight != 0.0)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(FilterKind::Box)
+    }
+}
+
+/// Mitchell-Netravali cubic with B = C = 1/3, which is zero from 2 on
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+    let value = if x >= 2.0 {
+        0.0
+    } else if x < 1.0 {
+        (12.0 - 9.0 * B - 6.0 * C) * x * x * x
+            + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+            + (6.0 - 2.0 * B)
+    } else {
+        (-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C)
+    };
+    value / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    f64::sin(PI * x) / (PI * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [FilterKind; 5] = [
+        FilterKind::Box,
+        FilterKind::Tent,
+        FilterKind::Gaussian,
+        FilterKind::Mitchell,
+        FilterKind::Lanczos,
+    ];
+
+    #[test]
+    fn filters_vanish_at_the_radius() {
+        for kind in KINDS {
+            let filter = Filter::new(kind);
+            let radius = filter.radius();
+            assert!(filter.evaluate(0.0) > 0.0, "{kind:?}");
+            assert_eq!(filter.evaluate(-radius - 0.1), 0.0, "{kind:?}");
+            if kind != FilterKind::Box {
+                assert!(filter.evaluate(radius).abs() < 1e-12, "{kind:?}");
+                assert!(filter.evaluate(radius - 1e-3).abs() < 0.01, "{kind:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn filters_are_symmetric_and_continuous() {
+        for kind in KINDS {
+            let filter = Filter::with_radius(kind, 2.5);
+            for step in 0..250 {
+                let x = step as f64 / 100.0;
+                assert_eq!(filter.evaluate(x), filter.evaluate(-x), "{kind:?}");
+                if kind != FilterKind::Box {
+                    let jump = filter.evaluate(x + 1e-6) - filter.evaluate(x);
+                    assert!(jump.abs() < 1e-4, "{kind:?} jumps at {x}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mitchell_reproduces_constants() {
+        // The Mitchell-Netravali family sums to one over integer offsets.
+        for shift in [0.0, 0.25, 0.5] {
+            let sum: f64 = (-3..=3).map(|i| mitchell(f64::abs(i as f64 + shift))).sum();
+            assert!((sum - 1.0).abs() < 1e-12, "{sum}");
+        }
+    }
+
+    #[test]
+    fn box_footprint_is_its_own_pixel() {
+        let filter = Filter::default();
+        assert_eq!(filter.reach(), 0);
+        for offset in [-0.5, -0.25, 0.0, 0.49] {
+            assert_eq!(filter.footprint(offset).collect::<Vec<_>>(), [(0, 1.0)]);
+        }
+    }
+
+    #[test]
+    fn footprint_stays_within_reach() {
+        for kind in KINDS {
+            for radius in [0.5, 0.7, 1.0, 1.5, 2.0, 3.2] {
+                let filter = Filter::with_radius(kind, radius);
+                for step in 0..100 {
+                    let offset = step as f64 / 100.0 - 0.5;
+                    for (pixel, _) in filter.footprint(offset) {
+                        assert!(pixel.abs() <= filter.reach(), "{kind:?} {radius} {offset}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for kind in KINDS {
+            assert_eq!(kind.name().parse::<FilterKind>(), Ok(kind));
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use image::{
+    codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding},
+    error::ImageFormatHint,
+    ImageError, ImageFormat, ImageResult, Rgb32FImage, RgbImage,
+};
+
+use crate::{
+    color::{write_color, Color},
+    tonemap::DisplayTransform,
+};
+
+/// File formats a rendered image can be written as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    PpmAscii,
+    PpmBinary,
+    Hdr,
+    Exr,
+}
+
+impl OutputFormat {
+    /// Picks the output format from the extension of a file name.
+    ///
+    /// `.ppm` files are written as binary P6 pixmaps, the ASCII P3 variant has to be requested
+    /// explicitly through [`Framebuffer::save_with_format`].
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "ppm" => Some(OutputFormat::PpmBinary),
+            "hdr" => Some(OutputFormat::Hdr),
+            "exr" => Some(OutputFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "jpg" | "jpeg" => Ok(OutputFormat::Jpeg),
+            "ppm" | "p6" => Ok(OutputFormat::PpmBinary),
+            "ppm-ascii" | "p3" => Ok(OutputFormat::PpmAscii),
+            "hdr" => Ok(OutputFormat::Hdr),
+            "exr" => Ok(OutputFormat::Exr),
+            _ => Err(format!(
+                "unknown output format `{s}`, expected one of png, jpeg, ppm, ppm-ascii, hdr, exr"
+            )),
+        }
+    }
+}
+
+/// Rendered image held in memory as linear color values, one per pixel in row-major order,
+/// together with the transform that turns them into display values for 8 bit formats.
+#[derive(Clone)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    display: DisplayTransform,
+}
+
+impl Framebuffer {
+    /// Creates a black framebuffer of the given dimensions
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::black(); width as usize * height as usize],
+            display: DisplayTransform::default(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[y as usize * self.width as usize + x as usize]
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Color] {
+        &mut self.pixels
+    }
+
+    pub fn display(&self) -> &DisplayTransform {
+        &self.display
+    }
+
+    /// Sets the exposure, tone mapping and dithering used for 8 bit formats.
+    pub fn set_display(&mut self, display: DisplayTransform) {
+        self.display = display;
+    }
+
+    /// Converts the image to 8 bit sRGB-encoded values ready for display formats, through its
+    /// display transform.
+    pub fn to_rgb8(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            image::Rgb(self.display.to_rgb8(self.pixel(x, y), x, y))
+        })
+    }
+
+    /// Converts the image to linear 32 bit float values for HDR formats, which leave exposure and
+    /// tone mapping to the viewer.
+    pub fn to_rgb32f(&self) -> Rgb32FImage {
+        Rgb32FImage::from_fn(self.width, self.height, |x, y| {
+            let color = self.pixel(x, y);
+            image::Rgb([color.r as f32, color.g as f32, color.b as f32])
+        })
+    }
+
+    /// Saves the image, picking the file format from the extension of `path`.
+    ///
+    /// The special path `-` writes an ASCII P3 pixmap to stdout.
+    pub fn save(&self, path: impl AsRef<Path>) -> ImageResult<()> {
+        let path = path.as_ref();
+        if path.as_os_str() == "-" {
+            let stdout = BufWriter::new(io::stdout().lock());
+            return self.write_ppm(stdout).map_err(ImageError::IoError);
+        }
+
+        let format = OutputFormat::from_path(path).ok_or_else(|| {
+            let hint = match path.extension() {
+                Some(extension) => ImageFormatHint::PathExtension(extension.into()),
+                None => ImageFormatHint::Unknown,
+            };
+            ImageError::Unsupported(hint.into())
+        })?;
+        self.save_with_format(path, format)
+    }
+
+    /// Saves the image to `path` in the given format, regardless of the file extension.
+    pub fn save_with_format(
+        &self,
+        path: impl AsRef<Path>,
+        format: OutputFormat,
+    ) -> ImageResult<()> {
+        let path = path.as_ref();
+        match format {
+            OutputFormat::Png => self.to_rgb8().save_with_format(path, ImageFormat::Png),
+            OutputFormat::Jpeg => self.to_rgb8().save_with_format(path, ImageFormat::Jpeg),
+            OutputFormat::PpmAscii => {
+                let file = BufWriter::new(File::create(path)?);
+                self.write_ppm(file).map_err(ImageError::IoError)
+            }
+            OutputFormat::PpmBinary => {
+                let file = BufWriter::new(File::create(path)?);
+                let encoder =
+                    PnmEncoder::new(file).with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary));
+                self.to_rgb8().write_with_encoder(encoder)
+            }
+            OutputFormat::Hdr => self.to_rgb32f().save_with_format(path, ImageFormat::Hdr),
+            OutputFormat::Exr => self
+                .to_rgb32f()
+                .save_with_format(path, ImageFormat::OpenExr),
+        }
+    }
+
+    /// Writes the image as an ASCII P3 pixmap.
+    pub fn write_ppm(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "P3\n{} {}\n255", self.width, self.height)?;
+        let width = self.width as usize;
+        for (i, pixel_color) in self.pixels.iter().enumerate() {
+            let (x, y) = ((i % width) as u32, (i / width) as u32);
+            write_color(&mut out, self.display.to_rgb8(*pixel_color, x, y))?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_format_from_path_uses_the_extension() {
+        let format = |path: &str| OutputFormat::from_path(Path::new(path));
+        assert_eq!(format("out.png"), Some(OutputFormat::Png));
+        assert_eq!(format("out.JPG"), Some(OutputFormat::Jpeg));
+        assert_eq!(format("dir.d/out.jpeg"), Some(OutputFormat::Jpeg));
+        assert_eq!(format("out.ppm"), Some(OutputFormat::PpmBinary));
+        assert_eq!(format("out.hdr"), Some(OutputFormat::Hdr));
+        assert_eq!(format("out.exr"), Some(OutputFormat::Exr));
+        assert_eq!(format("out.bmp"), None);
+        assert_eq!(format("out"), None);
+    }
+
+    #[test]
+    fn output_format_from_str_accepts_the_aliases() {
+        assert_eq!("PNG".parse(), Ok(OutputFormat::Png));
+        assert_eq!("jpg".parse(), Ok(OutputFormat::Jpeg));
+        assert_eq!("p6".parse(), Ok(OutputFormat::PpmBinary));
+        assert_eq!("p3".parse(), Ok(OutputFormat::PpmAscii));
+        assert_eq!("ppm-ascii".parse(), Ok(OutputFormat::PpmAscii));
+        assert_eq!("exr".parse(), Ok(OutputFormat::Exr));
+        assert!("tiff".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn write_ppm_writes_rows_top_to_bottom() {
+        let mut framebuffer = Framebuffer::new(2, 2);
+        framebuffer.set_display(DisplayTransform {
+            dither: false,
+            ..DisplayTransform::default()
+        });
+        framebuffer.pixels_mut()[1] = Color::white();
+        framebuffer.pixels_mut()[2] = Color::new(1.0, 0.0, 0.0);
+        framebuffer.pixels_mut()[3] = Color::new(0.0, 0.0, 2.0);
+
+        let mut out = Vec::new();
+        framebuffer.write_ppm(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "P3\n2 2\n255\n0 0 0\n255 255 255\n255 0 0\n0 0 255\n"
+        );
+        assert_eq!(framebuffer.pixel(0, 1).r, 1.0);
+    }
+}
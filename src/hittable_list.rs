@@ -1,24 +1,26 @@
 use core::f64;
-use std::{i32, rc::Rc};
+use std::{any::Any, sync::Arc};
 
 use crate::{
     aabb::Aabb,
     interval::Interval,
-    ray::{ray, ray_with_time, Ray},
+    mat4::Mat4,
+    ray::{ray_with_time, Ray},
     rtweekend::degrees_to_radians,
-    sphere::{HitRecord, Hittable},
-    vec3::{vec3, Vec3},
+    sampler::{sample_index, Dimension},
+    sphere::{debug_assert_hit_in_bounds, HitRecord, Hittable},
+    vec3::{unit_vector, vec3, Vec3},
 };
 
 #[derive(Default)]
 pub struct HittableList {
-    pub objects: Vec<Rc<dyn Hittable>>,
+    pub objects: Vec<Arc<dyn Hittable>>,
 
     bbox: Aabb,
 }
 
 impl HittableList {
-    pub fn new(object: Rc<dyn Hittable>) -> HittableList {
+    pub fn new(object: Arc<dyn Hittable>) -> HittableList {
         let mut hittable_list = HittableList {
             objects: Default::default(),
             bbox: Default::default(),
@@ -27,7 +29,7 @@ impl HittableList {
         hittable_list
     }
 
-    pub fn add(&mut self, object: Rc<dyn Hittable>) {
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.bbox = Aabb::from_aabb(&self.bbox, object.bounding_box());
         self.objects.push(object);
     }
@@ -43,7 +45,7 @@ impl Hittable for HittableList {
             let is_hit = object.hit(r, Interval::new(ray_t.min, closest_so_far), &mut temp_rec);
             if is_hit {
                 hit_anything = true;
-                closest_so_far = temp_rec.t.clone();
+                closest_so_far = temp_rec.t;
                 *rec = temp_rec.clone();
             }
         }
@@ -54,16 +56,38 @@ impl Hittable for HittableList {
     fn bounding_box(&self) -> &Aabb {
         &self.bbox
     }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+
+        // Every object is picked with the same probability by `random`.
+        let weight = 1.0 / self.objects.len() as f64;
+        self.objects
+            .iter()
+            .map(|object| weight * object.pdf_value(origin, direction))
+            .sum()
+    }
+
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        if self.objects.is_empty() {
+            return vec3(1.0, 0.0, 0.0);
+        }
+
+        let index = sample_index(Dimension::LightSelect, self.objects.len());
+        self.objects[index].random(origin)
+    }
 }
 
 pub struct Translate {
-    object: Rc<dyn Hittable>,
+    object: Arc<dyn Hittable>,
     offset: Vec3,
     bbox: Aabb,
 }
 
 impl Translate {
-    pub fn new(object: Rc<dyn Hittable>, offset: Vec3) -> Self {
+    pub fn new(object: Arc<dyn Hittable>, offset: Vec3) -> Self {
         let bbox = object.bounding_box() + offset;
         Translate {
             object,
@@ -73,6 +97,13 @@ impl Translate {
     }
 }
 
+impl Translate {
+    /// The object being moved
+    pub fn object(&self) -> &dyn Hittable {
+        &*self.object
+    }
+}
+
 impl Hittable for Translate {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         // Move the ray backwards by the offset
@@ -85,6 +116,7 @@ impl Hittable for Translate {
 
         // Move the intersection point forwards by the offset
         rec.p = rec.p + self.offset;
+        debug_assert_hit_in_bounds(self, rec);
 
         true
     }
@@ -92,17 +124,25 @@ impl Hittable for Translate {
     fn bounding_box(&self) -> &Aabb {
         &self.bbox
     }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        self.object.pdf_value(&(origin - self.offset), direction)
+    }
+
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        self.object.random(&(origin - self.offset))
+    }
 }
 
 pub struct RotateY {
-    object: Rc<dyn Hittable>,
+    object: Arc<dyn Hittable>,
     sin_theta: f64,
     cos_theta: f64,
     bbox: Aabb,
 }
 
 impl RotateY {
-    pub fn new(object: Rc<dyn Hittable>, angle: f64) -> Self {
+    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> Self {
         let radians = degrees_to_radians(angle);
         let sin_theta = f64::sin(radians);
         let cos_theta = f64::cos(radians);
@@ -128,17 +168,17 @@ impl RotateY {
                         match c {
                             0 => {
                                 min.x = f64::min(min.x, tester.x);
-                                max.x = f64::min(max.x, tester.x)
+                                max.x = f64::max(max.x, tester.x)
                             }
                             1 => {
                                 min.y = f64::min(min.y, tester.y);
-                                max.y = f64::min(max.y, tester.y)
+                                max.y = f64::max(max.y, tester.y)
                             },
                             2 => {
                                 min.z = f64::min(min.z, tester.z);
-                                max.z = f64::min(max.z, tester.z)
+                                max.z = f64::max(max.z, tester.z)
                             }
-                            i32::MIN..=i32::MAX => !panic!("Unreachable")
+                            _ => unreachable!(),
                         }
                     }
                 }
@@ -157,19 +197,36 @@ impl RotateY {
     }
 }
 
+impl RotateY {
+    /// The object being rotated
+    pub fn object(&self) -> &dyn Hittable {
+        &*self.object
+    }
+
+    /// Rotates a point or direction from world space into object space.
+    fn to_object(&self, v: &Vec3) -> Vec3 {
+        vec3(
+            (self.cos_theta * v.x) - (self.sin_theta * v.z),
+            v.y,
+            (self.sin_theta * v.x) + (self.cos_theta * v.z),
+        )
+    }
+
+    /// Rotates a point or direction from object space back into world space.
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        vec3(
+            (self.cos_theta * v.x) + (self.sin_theta * v.z),
+            v.y,
+            (-self.sin_theta * v.x) + (self.cos_theta * v.z),
+        )
+    }
+}
+
 impl Hittable for RotateY {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         // Transform the ray from world space to object space.
-        let origin = vec3(
-            (self.cos_theta * r.origin().x) - (self.sin_theta * r.origin().z),
-            r.origin().y,
-            (self.sin_theta * r.origin().x) + (self.cos_theta * r.origin().z),
-        );
-        let direction = vec3(
-            (self.cos_theta * r.direction().x) - (self.sin_theta * r.direction().z),
-            r.direction().y,
-            (self.sin_theta * r.direction().x) + (self.cos_theta * r.direction().z),
-        );
+        let origin = self.to_object(r.origin());
+        let direction = self.to_object(r.direction());
         let rotated_r = &ray_with_time(origin, direction, r.time());
 
         // Determine whether an intersection exists in object space (and if so, where).
@@ -178,16 +235,9 @@ impl Hittable for RotateY {
         }
 
         // Transform the intersection from object space back to world space.
-        rec.p = vec3(
-            (self.cos_theta * rec.p.x) - (self.sin_theta * rec.p.z),
-            rec.p.y,
-            (-self.sin_theta * rec.p.x) + (self.cos_theta * rec.p.z),
-        );
-        rec.normal = vec3(
-            (self.cos_theta * rec.normal.x) - (self.sin_theta * rec.normal.z),
-            rec.normal.y,
-            (-self.sin_theta * rec.normal.x) + (self.cos_theta * rec.normal.z),
-        );
+        rec.p = self.to_world(&rec.p);
+        rec.normal = self.to_world(&rec.normal);
+        debug_assert_hit_in_bounds(self, rec);
 
         true
     }
@@ -195,4 +245,389 @@ impl Hittable for RotateY {
     fn bounding_box(&self) -> &Aabb {
         &self.bbox
     }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        self.object
+            .pdf_value(&self.to_object(origin), &self.to_object(direction))
+    }
+
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        self.to_world(&self.object.random(&self.to_object(origin)))
+    }
+}
+
+/// Instance of an object under an arbitrary affine transformation, such as a rotation around any
+/// axis or a non-uniform scale
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    /// Object space to world space
+    matrix: Mat4,
+    /// World space to object space
+    inverse: Mat4,
+    /// Object space normals to world space normals, the transposed inverse
+    normal_matrix: Mat4,
+    /// Whether solid angles seen from a point are preserved, so the object can still be sampled
+    preserves_angles: bool,
+    bbox: Aabb,
+}
+
+impl Transform {
+    /// Creates an instance of `object`, transformed by `matrix`. Transforming another `Transform`
+    /// collapses both into a single matrix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the matrix is singular, as it would flatten the object. Use
+    /// [`Transform::try_new`] for matrices that aren't known to be invertible.
+    pub fn new(object: Arc<dyn Hittable>, matrix: Mat4) -> Self {
+        Transform::try_new(object, matrix).expect("transformation matrix must be invertible")
+    }
+
+    /// Like [`Transform::new`], but returns `None` if the combined matrix is singular or nearly
+    /// so, see [`Mat4::inverse`].
+    pub fn try_new(object: Arc<dyn Hittable>, matrix: Mat4) -> Option<Self> {
+        let inner = &*object as &dyn Any;
+        let (object, matrix) = match inner.downcast_ref::<Transform>() {
+            Some(transform) => (transform.object.clone(), matrix * transform.matrix),
+            None => (object, matrix),
+        };
+
+        let inverse = matrix.inverse()?;
+
+        // Transform all corners of the object's box and bound them again.
+        let bbox = object.bounding_box();
+        let mut min = vec3(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = vec3(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let x = if i == 0 { bbox.x.min } else { bbox.x.max };
+                    let y = if j == 0 { bbox.y.min } else { bbox.y.max };
+                    let z = if k == 0 { bbox.z.min } else { bbox.z.max };
+                    let corner = matrix.transform_point(&vec3(x, y, z));
+
+                    min = vec3(
+                        f64::min(min.x, corner.x),
+                        f64::min(min.y, corner.y),
+                        f64::min(min.z, corner.z),
+                    );
+                    max = vec3(
+                        f64::max(max.x, corner.x),
+                        f64::max(max.y, corner.y),
+                        f64::max(max.z, corner.z),
+                    );
+                }
+            }
+        }
+
+        Some(Transform {
+            object,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose_linear(),
+            preserves_angles: matrix.preserves_angles(),
+            bbox: Aabb::from_point(&min, &max),
+        })
+    }
+
+    /// The object being transformed
+    pub fn object(&self) -> &dyn Hittable {
+        &*self.object
+    }
+
+    /// Matrix taking the object from object space into world space
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Transform the ray into object space. The direction isn't normalized, so distances
+        // along the ray stay the same in both spaces.
+        let origin = self.inverse.transform_point(r.origin());
+        let direction = self.inverse.transform_vector(r.direction());
+        let object_r = ray_with_time(origin, direction, r.time());
+
+        if !self.object.hit(&object_r, ray_t, rec) {
+            return false;
+        }
+
+        // Normals stay perpendicular to the surface only when transformed by the transposed
+        // inverse. The side of the surface the ray came from doesn't change.
+        rec.p = self.matrix.transform_point(&rec.p);
+        rec.normal = unit_vector(&self.normal_matrix.transform_vector(&rec.normal));
+        debug_assert_hit_in_bounds(self, rec);
+
+        true
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        // Other transformations distort the solid angles, which the object's density doesn't
+        // account for.
+        if !self.preserves_angles {
+            return 0.0;
+        }
+        self.object.pdf_value(
+            &self.inverse.transform_point(origin),
+            &self.inverse.transform_vector(direction),
+        )
+    }
+
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        if !self.preserves_angles {
+            return vec3(1.0, 0.0, 0.0);
+        }
+        let direction = self.object.random(&self.inverse.transform_point(origin));
+        self.matrix.transform_vector(&direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::Color,
+        material::Lambertian,
+        quad::bx,
+        ray::ray,
+        rtweekend::{random_double, seed_random},
+        sphere::Sphere,
+        vec3::random_unit_vector,
+    };
+
+    fn test_box() -> Arc<dyn Hittable> {
+        let white = Arc::new(Lambertian::new(Color::white()));
+        bx(&vec3(-1.0, 0.0, 2.0), &vec3(3.0, 2.0, 5.0), white)
+    }
+
+    fn corners(bbox: &Aabb) -> Vec<Vec3> {
+        let mut corners = Vec::new();
+        for x in [bbox.x.min, bbox.x.max] {
+            for y in [bbox.y.min, bbox.y.max] {
+                for z in [bbox.z.min, bbox.z.max] {
+                    corners.push(vec3(x, y, z));
+                }
+            }
+        }
+        corners
+    }
+
+    /// Checks that `bbox` is the tightest box around `points`.
+    fn assert_bounds(bbox: &Aabb, points: &[Vec3]) {
+        for (axis, interval) in [&bbox.x, &bbox.y, &bbox.z].into_iter().enumerate() {
+            let values = points.iter().map(|p| p.axis(axis as i32));
+            let min = values.clone().fold(f64::INFINITY, f64::min);
+            let max = values.fold(-f64::INFINITY, f64::max);
+            assert!(
+                (interval.min - min).abs() < 1e-9 && (interval.max - max).abs() < 1e-9,
+                "axis {axis}: expected {min}..{max}, found {interval:?}"
+            );
+        }
+    }
+
+    /// Shoots random rays at the object's bounding box and checks that every hit lies inside it.
+    fn assert_hits_in_bounds(object: &dyn Hittable) {
+        seed_random(0, 0);
+        let bbox = object.bounding_box();
+        let center = bbox.centroid();
+        let radius = 2.0 * (bbox.x.size() + bbox.y.size() + bbox.z.size());
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = center + radius * random_unit_vector();
+            let target = vec3(
+                bbox.x.min + random_double() * bbox.x.size(),
+                bbox.y.min + random_double() * bbox.y.size(),
+                bbox.z.min + random_double() * bbox.z.size(),
+            );
+            let mut rec = HitRecord::default();
+            if object.hit(
+                &ray(origin, target - origin),
+                Interval::new(0.001, f64::INFINITY),
+                &mut rec,
+            ) {
+                hits += 1;
+                assert!(
+                    bbox.expand(1e-9).contains(&rec.p),
+                    "{:?} outside of {bbox:?}",
+                    rec.p
+                );
+            }
+        }
+        assert!(hits > 0, "no ray hit the object");
+    }
+
+    #[test]
+    fn rotate_y_bounds_match_rotated_corners() {
+        let object = test_box();
+        let object_corners = corners(object.bounding_box());
+        for angle in [0.0, 15.0, 30.0, 45.0, 90.0, 135.0, 180.0, 270.0, -60.0] {
+            let rotated = RotateY::new(object.clone(), angle);
+
+            let radians = degrees_to_radians(angle);
+            let (sin_theta, cos_theta) = (f64::sin(radians), f64::cos(radians));
+            let expected: Vec<Vec3> = object_corners
+                .iter()
+                .map(|p| {
+                    vec3(
+                        cos_theta * p.x + sin_theta * p.z,
+                        p.y,
+                        -sin_theta * p.x + cos_theta * p.z,
+                    )
+                })
+                .collect();
+            assert_bounds(rotated.bounding_box(), &expected);
+            assert_hits_in_bounds(&rotated);
+        }
+    }
+
+    #[test]
+    fn translate_bounds_match_moved_corners() {
+        let object = test_box();
+        let offset = vec3(265.0, -3.5, 0.25);
+        let translated = Translate::new(object.clone(), offset);
+
+        let expected: Vec<Vec3> = corners(object.bounding_box())
+            .iter()
+            .map(|p| p + offset)
+            .collect();
+        assert_bounds(translated.bounding_box(), &expected);
+        assert_hits_in_bounds(&translated);
+    }
+
+    #[test]
+    fn nested_instances_stay_in_bounds() {
+        let rotated = Arc::new(RotateY::new(test_box(), 15.0));
+        let translated = Translate::new(rotated, vec3(265.0, 0.0, 295.0));
+        assert_hits_in_bounds(&translated);
+    }
+
+    #[test]
+    fn transform_bounds_match_transformed_corners() {
+        let object = test_box();
+        let matrix = Mat4::translation(&vec3(1.0, 2.0, 3.0))
+            * Mat4::rotation(&vec3(1.0, 1.0, 0.5), 40.0)
+            * Mat4::scaling(&vec3(2.0, 0.5, 1.0));
+        let transformed = Transform::new(object.clone(), matrix);
+
+        let expected: Vec<Vec3> = corners(object.bounding_box())
+            .iter()
+            .map(|p| matrix.transform_point(p))
+            .collect();
+        assert_bounds(transformed.bounding_box(), &expected);
+        assert_hits_in_bounds(&transformed);
+    }
+
+    #[test]
+    fn nested_transforms_collapse() {
+        let inner = Mat4::rotation(&vec3(0.0, 0.0, 1.0), 30.0);
+        let outer = Mat4::translation(&vec3(0.0, 5.0, 0.0));
+        let nested = Transform::new(Arc::new(Transform::new(test_box(), inner)), outer);
+        let combined = Transform::new(test_box(), outer * inner);
+
+        assert_eq!(nested.matrix(), combined.matrix());
+        assert_bounds(nested.bounding_box(), &corners(combined.bounding_box()));
+    }
+
+    #[test]
+    fn transform_hits_the_rotated_and_scaled_object() {
+        // Stretching the unit cube along x and turning it a quarter around y puts it at
+        // 0..1 x 0..1 x -2..0.
+        let white = Arc::new(Lambertian::new(Color::white()));
+        let cube = bx(&vec3(0.0, 0.0, 0.0), &vec3(1.0, 1.0, 1.0), white);
+        let matrix =
+            Mat4::rotation(&vec3(0.0, 1.0, 0.0), 90.0) * Mat4::scaling(&vec3(2.0, 1.0, 1.0));
+        let transformed = Transform::new(cube, matrix);
+        // The boxes of the sides of the cube are padded a little.
+        let bbox = transformed.bounding_box();
+        for (interval, min, max) in [
+            (&bbox.x, 0.0, 1.0),
+            (&bbox.y, 0.0, 1.0),
+            (&bbox.z, -2.0, 0.0),
+        ] {
+            assert!(
+                interval.min <= min && (interval.min - min).abs() < 1e-3,
+                "{interval:?}"
+            );
+            assert!(
+                interval.max >= max && (interval.max - max).abs() < 1e-3,
+                "{interval:?}"
+            );
+        }
+
+        let hit = |origin: Vec3, direction: Vec3| {
+            let mut rec = HitRecord::default();
+            let r = ray(origin, direction);
+            transformed
+                .hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec)
+                .then_some(rec)
+        };
+
+        // Distances are measured along the unnormalized direction, as for any other object.
+        let rec = hit(vec3(0.5, 0.5, 5.0), vec3(0.0, 0.0, -2.0)).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-9);
+        assert!((rec.p - vec3(0.5, 0.5, 0.0)).length() < 1e-9);
+        assert!((rec.normal - vec3(0.0, 0.0, 1.0)).length() < 1e-9);
+
+        let rec = hit(vec3(5.0, 0.5, -1.5), vec3(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert!((rec.p - vec3(1.0, 0.5, -1.5)).length() < 1e-9);
+        assert!((rec.normal - vec3(1.0, 0.0, 0.0)).length() < 1e-9);
+
+        // The untransformed cube would have been here.
+        assert!(hit(vec3(0.5, 0.5, 5.0), vec3(0.0, 0.0, -1.0)).is_some());
+        assert!(hit(vec3(1.5, 0.5, 5.0), vec3(0.0, 0.0, -1.0)).is_none());
+        assert!(hit(vec3(0.5, 0.5, -2.5), vec3(0.0, 0.0, -1.0)).is_none());
+    }
+
+    #[test]
+    fn transform_samples_only_undistorted_objects() {
+        let white = Arc::new(Lambertian::new(Color::white()));
+        let sphere = Arc::new(Sphere::new(vec3(0.0, 0.0, 0.0), 1.0, white));
+        let moved = Mat4::translation(&vec3(0.0, 0.0, -4.0));
+        let expected = sphere.pdf_value(&vec3(0.0, 0.0, 4.0), &vec3(0.0, 0.0, -1.0));
+
+        let rotated = Transform::new(
+            sphere.clone(),
+            moved * Mat4::rotation(&vec3(1.0, 0.0, 0.0), 60.0),
+        );
+        let density = rotated.pdf_value(&Vec3::default(), &vec3(0.0, 0.0, -1.0));
+        assert!((density - expected).abs() < 1e-9);
+        seed_random(5, 0);
+        for _ in 0..100 {
+            let direction = rotated.random(&Vec3::default());
+            assert!(rotated.pdf_value(&Vec3::default(), &direction) > 0.0);
+        }
+
+        let stretched = Transform::new(sphere, moved * Mat4::scaling(&vec3(1.0, 2.0, 1.0)));
+        assert_eq!(
+            stretched.pdf_value(&Vec3::default(), &vec3(0.0, 0.0, -1.0)),
+            0.0
+        );
+    }
+
+    #[test]
+    fn transform_normals_stay_perpendicular() {
+        // Stretching a unit sphere gives an ellipsoid x²/4 + y² + z² = 1, whose normals follow
+        // the gradient (x/4, y, z).
+        let white = Arc::new(Lambertian::new(Color::white()));
+        let sphere = Arc::new(Sphere::new(vec3(0.0, 0.0, 0.0), 1.0, white));
+        let ellipsoid = Transform::new(sphere, Mat4::scaling(&vec3(2.0, 1.0, 1.0)));
+
+        let r = ray(vec3(5.0, 4.0, 3.0), vec3(-1.0, -0.7, -0.6));
+        let mut rec = HitRecord::default();
+        assert!(ellipsoid.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+
+        let p = rec.p;
+        assert!((p.x * p.x / 4.0 + p.y * p.y + p.z * p.z - 1.0).abs() < 1e-9);
+        assert!((r.at(rec.t) - p).length() < 1e-9);
+        let expected = unit_vector(&vec3(p.x / 4.0, p.y, p.z));
+        assert!((rec.normal - expected).length() < 1e-9);
+        assert!(rec.front_face);
+    }
 }
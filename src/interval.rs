@@ -1,4 +1,4 @@
-#[derive(Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
@@ -0,0 +1,79 @@
+//! Ray tracer following the "Ray Tracing in One Weekend" series.
+//!
+//! A [`Scene`] combines a world of [`Hittable`] objects with the [`Camera`] looking at it and
+//! renders into a [`Framebuffer`] that can be saved in various image formats:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use edu_ray_tracing::{vec3, Camera, Color, HittableList, Lambertian, Scene, Sphere};
+//!
+//! let mut world = HittableList::default();
+//! let ground = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+//! world.add(Arc::new(Sphere::new(vec3(0.0, -100.5, -1.0), 100.0, ground)));
+//! let center = Arc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
+//! world.add(Arc::new(Sphere::new(vec3(0.0, 0.0, -1.0), 0.5, center)));
+//!
+//! let mut camera = Camera::default();
+//! camera.image_width = 400;
+//! camera.background = Color::new(0.7, 0.8, 1.0);
+//!
+//! let mut scene = Scene::new(world, camera);
+//! scene.render().save("spheres.png").unwrap();
+//! ```
+//!
+//! Scenes can also be loaded from text files through [`scene_file::load`].
+
+pub mod aabb;
+pub mod accumulator;
+pub mod aov;
+pub mod bvh;
+pub mod camera;
+pub mod color;
+pub mod constant_medium;
+pub mod denoise;
+pub mod filter;
+pub mod framebuffer;
+pub mod hittable_list;
+pub mod interval;
+pub mod mat4;
+pub mod material;
+pub mod obj;
+pub mod onb;
+pub mod pdf;
+pub mod perlin;
+pub mod quad;
+pub mod ray;
+pub mod rtweekend;
+pub mod sampler;
+pub mod scene;
+pub mod scene_file;
+pub mod sphere;
+pub mod stats;
+pub mod texture;
+pub mod tonemap;
+pub mod triangle;
+pub mod vec3;
+
+pub use accumulator::{Accumulator, Checkpoint};
+pub use aov::Aov;
+pub use bvh::{BvhNode, BvhStats, SplitMethod};
+pub use camera::Camera;
+pub use color::Color;
+pub use constant_medium::ConstantMedium;
+pub use denoise::Denoiser;
+pub use filter::{Filter, FilterKind};
+pub use framebuffer::{Framebuffer, OutputFormat};
+pub use hittable_list::{HittableList, RotateY, Transform, Translate};
+pub use mat4::Mat4;
+pub use material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
+pub use quad::{bx, Quad};
+pub use ray::Ray;
+pub use sampler::Sampler;
+pub use scene::Scene;
+pub use sphere::{HitRecord, Hittable, Sphere};
+pub use stats::RenderStats;
+pub use texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture};
+pub use tonemap::{DisplayTransform, ToneMapper};
+pub use triangle::{Triangle, TriangleMesh};
+pub use vec3::{vec3, Vec3};
@@ -1,46 +1,52 @@
-use std::{i32, rc::Rc};
-
-use bvh::BvhNode;
-use camera::Camera;
-use color::Color;
-use constant_medium::ConstantMedium;
-use hittable_list::{HittableList, RotateY, Translate};
-use material::Material;
-use quad::{bx, Quad};
-use rtweekend::{random_double, random_double_from, REFRACTION_GLASS};
-use sphere::{Hittable, Sphere};
-use texture::{CheckerTexture, ImageTexture, NoiseTexture};
-use vec3::{random, random_from, vec3};
-
-mod aabb;
-mod bvh;
-mod camera;
-mod color;
-mod constant_medium;
-mod hittable_list;
-mod interval;
-mod material;
-mod perlin;
-mod quad;
-mod ray;
-mod rtweekend;
-mod sphere;
-mod texture;
-mod vec3;
-
-fn bouncing_spheres() {
+use std::{
+    fs,
+    hash::Hasher,
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use image::{ImageError, ImageResult};
+
+use edu_ray_tracing::{
+    accumulator::{Accumulator, Checkpoint, SceneHasher},
+    aov::{self, Aov},
+    bvh::{self, BvhNode, SplitMethod},
+    camera::{Camera, MAX_DEPTH, MAX_IMAGE_SIZE},
+    color::Color,
+    constant_medium::ConstantMedium,
+    denoise::MAX_PASSES,
+    filter::{self, Filter, FilterKind},
+    framebuffer::{Framebuffer, OutputFormat},
+    hittable_list::{HittableList, RotateY, Translate},
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    quad::{bx, Quad},
+    rtweekend::{self, random_double, random_double_from, seed_random, REFRACTION_GLASS},
+    sampler::{self, Sampler},
+    scene::Scene,
+    scene_file,
+    sphere::{Hittable, Sphere},
+    texture::{CheckerTexture, ImageTexture, NoiseTexture},
+    tonemap::ToneMapper,
+    vec3::{random, random_from, vec3},
+};
+
+fn bouncing_spheres(split: SplitMethod) -> Scene {
     let mut world = HittableList::default();
 
-    let checker = Rc::new(CheckerTexture::from_colors(
+    let checker = Arc::new(CheckerTexture::from_colors(
         0.32,
         Color::new(0.2, 0.3, 0.1),
         Color::new(0.9, 0.9, 0.9),
     ));
-    //let ground_material = Material::lambertian(Color::new(0.5, 0.5, 0.5));
-    world.add(Rc::new(Sphere::new(
+    //let ground_material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    world.add(Arc::new(Sphere::new(
         vec3(0.0, -1000.0, 0.0),
         1000.0,
-        Material::lambertian_from_tex(checker),
+        Arc::new(Lambertian::from_tex(checker)),
     )));
 
     for a in -11..11 {
@@ -51,15 +57,15 @@ fn bouncing_spheres() {
                 0.2,
                 b as f64 + 0.9 * random_double(),
             );
-            let sphere_material;
+            let sphere_material: Arc<dyn Material>;
 
-            if (&center - vec3(4.0, 0.2, 0.0)).length() > 0.9 {
+            if (center - vec3(4.0, 0.2, 0.0)).length() > 0.9 {
                 if choose_mat < 0.8 {
                     // diffuse
                     let albedo = random() * random();
-                    sphere_material = Material::lambertian(albedo.to_color());
-                    let center2 = center.clone() + vec3(0.0, random_double_from(0.0, 0.5), 0.0);
-                    world.add(Rc::new(Sphere::moving(
+                    sphere_material = Arc::new(Lambertian::new(albedo.to_color()));
+                    let center2 = center + vec3(0.0, random_double_from(0.0, 0.5), 0.0);
+                    world.add(Arc::new(Sphere::moving(
                         center,
                         center2,
                         0.2,
@@ -69,28 +75,28 @@ fn bouncing_spheres() {
                     // metal
                     let albedo = random_from(0.5, 1.0);
                     let fuzz = random_double_from(0.0, 0.5);
-                    sphere_material = Material::metal(albedo.to_color(), fuzz);
-                    world.add(Rc::new(Sphere::new(center, 0.2, sphere_material)));
+                    sphere_material = Arc::new(Metal::new(albedo.to_color(), fuzz));
+                    world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
                 } else {
                     // glass
-                    sphere_material = Material::dielectric(rtweekend::REFRACTION_GLASS);
-                    world.add(Rc::new(Sphere::new(center, 0.2, sphere_material)));
+                    sphere_material = Arc::new(Dielectric::new(rtweekend::REFRACTION_GLASS));
+                    world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
                 }
             }
         }
     }
 
-    let material1 = Material::dielectric(rtweekend::REFRACTION_GLASS);
-    world.add(Rc::new(Sphere::new(vec3(0.0, 1.0, 0.0), 1.0, material1)));
+    let material1 = Arc::new(Dielectric::new(rtweekend::REFRACTION_GLASS));
+    world.add(Arc::new(Sphere::new(vec3(0.0, 1.0, 0.0), 1.0, material1)));
 
-    let material2 = Material::lambertian(Color::new(0.4, 0.2, 0.1));
-    world.add(Rc::new(Sphere::new(vec3(-4.0, 1.0, 0.0), 1.0, material2)));
+    let material2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    world.add(Arc::new(Sphere::new(vec3(-4.0, 1.0, 0.0), 1.0, material2)));
 
-    let material3 = Material::metal(Color::new(0.7, 0.6, 0.5), 0.0);
-    world.add(Rc::new(Sphere::new(vec3(4.0, 1.0, 0.0), 1.0, material3)));
+    let material3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+    world.add(Arc::new(Sphere::new(vec3(4.0, 1.0, 0.0), 1.0, material3)));
 
-    let node = BvhNode::from_list(&mut world);
-    world = HittableList::new(Rc::new(node));
+    let node = BvhNode::with_split(&world.objects, split);
+    world = HittableList::new(Arc::new(node));
 
     let mut cam: Camera = Default::default();
     cam.aspect_ratio = 16.0 / 9.0;
@@ -107,26 +113,26 @@ fn bouncing_spheres() {
     cam.defocus_angle = 0.6;
     cam.focus_dist = 10.0;
 
-    cam.render(&world);
+    Scene::new(world, cam)
 }
 
-fn checkered_spheres() {
+fn checkered_spheres() -> Scene {
     let mut world = HittableList::default();
 
-    let checker = Rc::new(CheckerTexture::from_colors(
+    let checker = Arc::new(CheckerTexture::from_colors(
         0.32,
         Color::new(0.2, 0.3, 0.1),
         Color::new(0.9, 0.9, 0.9),
     ));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         vec3(0.0, -10.0, 0.0),
         10.0,
-        Material::lambertian_from_tex(checker.clone()),
+        Arc::new(Lambertian::from_tex(checker.clone())),
     )));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         vec3(0.0, 10.0, 0.0),
         10.0,
-        Material::lambertian_from_tex(checker),
+        Arc::new(Lambertian::from_tex(checker)),
     )));
 
     let mut cam: Camera = Default::default();
@@ -143,13 +149,13 @@ fn checkered_spheres() {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&world);
+    Scene::new(world, cam)
 }
 
-fn earth() {
-    let earth_texture = Rc::new(ImageTexture::new("misc\\earthmap.jpg"));
-    let earth_surface = Material::lambertian_from_tex(earth_texture);
-    let globe = Rc::new(Sphere::new(vec3(0.0, 0.0, 0.0), 2.0, earth_surface));
+fn earth() -> Scene {
+    let earth_texture = Arc::new(ImageTexture::new("misc/earthmap.jpg"));
+    let earth_surface = Arc::new(Lambertian::from_tex(earth_texture));
+    let globe = Arc::new(Sphere::new(vec3(0.0, 0.0, 0.0), 2.0, earth_surface));
 
     let mut cam: Camera = Default::default();
     cam.aspect_ratio = 16.0 / 9.0;
@@ -165,22 +171,22 @@ fn earth() {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&HittableList::new(globe));
+    Scene::new(HittableList::new(globe), cam)
 }
 
-fn perlin_spheres() {
+fn perlin_spheres() -> Scene {
     let mut world = HittableList::default();
 
-    let pertext = Rc::new(NoiseTexture::new(4.0));
-    world.add(Rc::new(Sphere::new(
+    let pertext = Arc::new(NoiseTexture::new(4.0));
+    world.add(Arc::new(Sphere::new(
         vec3(0.0, -1000.0, 0.0),
         1000.0,
-        Material::lambertian_from_tex(pertext.clone()),
+        Arc::new(Lambertian::from_tex(pertext.clone())),
     )));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         vec3(0.0, 2.0, 0.0),
         2.0,
-        Material::lambertian_from_tex(pertext),
+        Arc::new(Lambertian::from_tex(pertext)),
     )));
 
     let mut cam: Camera = Default::default();
@@ -197,45 +203,45 @@ fn perlin_spheres() {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&world);
+    Scene::new(world, cam)
 }
 
-fn quads() {
+fn quads() -> Scene {
     let mut world = HittableList::default();
 
     // Materials
-    let left_red = Material::lambertian(Color::new(1.0, 0.2, 0.2));
-    let back_green = Material::lambertian(Color::new(0.2, 1.0, 0.2));
-    let right_blue = Material::lambertian(Color::new(0.2, 0.2, 1.0));
-    let upper_orange = Material::lambertian(Color::new(1.0, 0.5, 0.0));
-    let lower_teal = Material::lambertian(Color::new(0.2, 0.8, 0.8));
+    let left_red = Arc::new(Lambertian::new(Color::new(1.0, 0.2, 0.2)));
+    let back_green = Arc::new(Lambertian::new(Color::new(0.2, 1.0, 0.2)));
+    let right_blue = Arc::new(Lambertian::new(Color::new(0.2, 0.2, 1.0)));
+    let upper_orange = Arc::new(Lambertian::new(Color::new(1.0, 0.5, 0.0)));
+    let lower_teal = Arc::new(Lambertian::new(Color::new(0.2, 0.8, 0.8)));
 
     // Quads
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(-3.0, -2.0, 5.0),
         vec3(0.0, 0.0, -4.0),
         vec3(0.0, 4.0, 0.0),
         left_red,
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(-2.0, -2.0, 0.0),
         vec3(4.0, 0.0, 0.0),
         vec3(0.0, 4.0, 0.0),
         back_green,
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(3.0, -2.0, 1.0),
         vec3(0.0, 0.0, 4.0),
         vec3(0.0, 4.0, 0.0),
         right_blue,
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(-2.0, 3.0, 1.0),
         vec3(4.0, 0.0, 0.0),
         vec3(0.0, 0.0, 4.0),
         upper_orange,
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(-2.0, -3.0, 5.0),
         vec3(4.0, 0.0, 0.0),
         vec3(0.0, 0.0, -4.0),
@@ -257,36 +263,41 @@ fn quads() {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&world);
+    Scene::new(world, cam)
 }
 
-fn simple_light() {
+fn simple_light() -> Scene {
     let mut world = HittableList::default();
+    let mut lights = HittableList::default();
 
-    let pertext = Rc::new(NoiseTexture::new(4.0));
-    world.add(Rc::new(Sphere::new(
+    let pertext = Arc::new(NoiseTexture::new(4.0));
+    world.add(Arc::new(Sphere::new(
         vec3(0.0, -1000.0, 0.0),
         1000.0,
-        Material::lambertian_from_tex(pertext.clone()),
+        Arc::new(Lambertian::from_tex(pertext.clone())),
     )));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         vec3(0.0, 2.0, 0.0),
         2.0,
-        Material::lambertian_from_tex(pertext),
+        Arc::new(Lambertian::from_tex(pertext)),
     )));
 
-    let difflight = Material::diffuse_light(Color::new(4.0, 4.0, 4.0));
-    world.add(Rc::new(Sphere::new(
+    let difflight = Arc::new(DiffuseLight::new(Color::new(4.0, 4.0, 4.0)));
+    let light_sphere = Arc::new(Sphere::new(
         vec3(0.0, 7.0, 0.0),
         2.0,
         difflight.clone(),
-    )));
-    world.add(Rc::new(Quad::new(
+    ));
+    world.add(light_sphere.clone());
+    lights.add(light_sphere);
+    let light_quad = Arc::new(Quad::new(
         vec3(3.0, 1.0, -2.0),
         vec3(2.0, 0.0, 0.0),
         vec3(0.0, 2.0, 0.0),
         difflight,
-    )));
+    ));
+    world.add(light_quad.clone());
+    lights.add(light_quad);
 
     let mut cam = Camera::default();
 
@@ -303,66 +314,69 @@ fn simple_light() {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&world);
+    Scene::with_lights(world, lights, cam)
 }
 
-fn cornell_box() {
+fn cornell_box() -> Scene {
     let mut world = HittableList::default();
+    let mut lights = HittableList::default();
 
-    let red = Material::lambertian(Color::new(0.65, 0.05, 0.05));
-    let white = Material::lambertian(Color::new(0.73, 0.73, 0.73));
-    let green = Material::lambertian(Color::new(0.12, 0.45, 0.15));
-    let light = Material::diffuse_light(Color::new(15.0, 15.0, 15.0));
+    let red = Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
+    let light = Arc::new(DiffuseLight::new(Color::new(15.0, 15.0, 15.0)));
 
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(555.0, 0.0, 0.0),
         vec3(0.0, 555.0, 0.0),
         vec3(0.0, 0.0, 555.0),
         green,
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(0.0, 0.0, 0.0),
         vec3(0.0, 555.0, 0.0),
         vec3(0.0, 0.0, 555.0),
         red,
     )));
-    world.add(Rc::new(Quad::new(
+    let light_quad = Arc::new(Quad::new(
         vec3(343.0, 554.0, 332.0),
         vec3(-130.0, 0.0, 0.0),
         vec3(0.0, 0.0, -105.0),
         light,
-    )));
-    world.add(Rc::new(Quad::new(
+    ));
+    world.add(light_quad.clone());
+    lights.add(light_quad);
+    world.add(Arc::new(Quad::new(
         vec3(0.0, 0.0, 0.0),
         vec3(555.0, 0.0, 0.0),
         vec3(0.0, 0.0, 555.0),
         white.clone(),
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(555.0, 555.0, 555.0),
         vec3(-555.0, 0.0, 0.0),
         vec3(0.0, 0.0, -555.0),
         white.clone(),
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(0.0, 0.0, 555.0),
         vec3(555.0, 0.0, 0.0),
         vec3(0.0, 555.0, 0.0),
         white.clone(),
     )));
 
-    let mut box1: Rc<dyn Hittable> = bx(
+    let mut box1: Arc<dyn Hittable> = bx(
         &vec3(0.0, 0.0, 0.0),
         &vec3(165.0, 330.0, 165.0),
         white.clone(),
     );
-    box1 = Rc::new(RotateY::new(box1, 15.0));
-    box1 = Rc::new(Translate::new(box1, vec3(265.0, 0.0, 295.0)));
+    box1 = Arc::new(RotateY::new(box1, 15.0));
+    box1 = Arc::new(Translate::new(box1, vec3(265.0, 0.0, 295.0)));
     world.add(box1);
 
-    let mut box2: Rc<dyn Hittable> = bx(&vec3(0.0, 0.0, 0.0), &vec3(165.0, 165.0, 165.0), white);
-    box2 = Rc::new(RotateY::new(box2, -18.0));
-    box2 = Rc::new(Translate::new(box2, vec3(130.0, 0.0, 65.0)));
+    let mut box2: Arc<dyn Hittable> = bx(&vec3(0.0, 0.0, 0.0), &vec3(165.0, 165.0, 165.0), white);
+    box2 = Arc::new(RotateY::new(box2, -18.0));
+    box2 = Arc::new(Translate::new(box2, vec3(130.0, 0.0, 65.0)));
     world.add(box2);
 
     let mut cam = Camera::default();
@@ -380,72 +394,75 @@ fn cornell_box() {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&world);
+    Scene::with_lights(world, lights, cam)
 }
 
-fn cornell_smoke() {
+fn cornell_smoke() -> Scene {
     let mut world = HittableList::default();
+    let mut lights = HittableList::default();
 
-    let red = Material::lambertian(Color::new(0.65, 0.05, 0.05));
-    let white = Material::lambertian(Color::new(0.73, 0.73, 0.73));
-    let green = Material::lambertian(Color::new(0.12, 0.45, 0.15));
-    let light = Material::diffuse_light(Color::new(7.0, 7.0, 7.0));
+    let red = Arc::new(Lambertian::new(Color::new(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::new(Color::new(0.12, 0.45, 0.15)));
+    let light = Arc::new(DiffuseLight::new(Color::new(7.0, 7.0, 7.0)));
 
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(555.0, 0.0, 0.0),
         vec3(0.0, 555.0, 0.0),
         vec3(0.0, 0.0, 555.0),
         green,
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(0.0, 0.0, 0.0),
         vec3(0.0, 555.0, 0.0),
         vec3(0.0, 0.0, 555.0),
         red,
     )));
-    world.add(Rc::new(Quad::new(
+    let light_quad = Arc::new(Quad::new(
         vec3(113.0, 554.0, 127.0),
         vec3(330.0, 0.0, 0.0),
         vec3(0.0, 0.0, 305.0),
         light,
-    )));
-    world.add(Rc::new(Quad::new(
+    ));
+    world.add(light_quad.clone());
+    lights.add(light_quad);
+    world.add(Arc::new(Quad::new(
         vec3(0.0, 0.0, 0.0),
         vec3(555.0, 0.0, 0.0),
         vec3(0.0, 0.0, 555.0),
         white.clone(),
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(555.0, 555.0, 555.0),
         vec3(-555.0, 0.0, 0.0),
         vec3(0.0, 0.0, -555.0),
         white.clone(),
     )));
-    world.add(Rc::new(Quad::new(
+    world.add(Arc::new(Quad::new(
         vec3(0.0, 0.0, 555.0),
         vec3(555.0, 0.0, 0.0),
         vec3(0.0, 555.0, 0.0),
         white.clone(),
     )));
 
-    let mut box1: Rc<dyn Hittable> = bx(
+    let mut box1: Arc<dyn Hittable> = bx(
         &vec3(0.0, 0.0, 0.0),
         &vec3(165.0, 330.0, 165.0),
         white.clone(),
     );
-    box1 = Rc::new(RotateY::new(box1, 15.0));
-    box1 = Rc::new(Translate::new(box1, vec3(265.0, 0.0, 295.0)));
+    box1 = Arc::new(RotateY::new(box1, 15.0));
+    box1 = Arc::new(Translate::new(box1, vec3(265.0, 0.0, 295.0)));
 
-    let mut box2: Rc<dyn Hittable> = bx(&vec3(0.0, 0.0, 0.0), &vec3(165.0, 165.0, 165.0), white);
-    box2 = Rc::new(RotateY::new(box2, -18.0));
-    box2 = Rc::new(Translate::new(box2, vec3(130.0, 0.0, 65.0)));
+    let mut box2: Arc<dyn Hittable> = bx(&vec3(0.0, 0.0, 0.0), &vec3(165.0, 165.0, 165.0), white);
+    box2 = Arc::new(RotateY::new(box2, -18.0));
+    box2 = Arc::new(Translate::new(box2, vec3(130.0, 0.0, 65.0)));
 
-    world.add(Rc::new(ConstantMedium::new(
+    world.add(Arc::new(ConstantMedium::new(
         box1,
         0.01,
         Color::new(0.0, 0.0, 0.0),
     )));
-    world.add(Rc::new(ConstantMedium::new(
+    world.add(Arc::new(ConstantMedium::new(
         box2,
         0.01,
         Color::new(1.0, 1.0, 1.0),
@@ -466,13 +483,18 @@ fn cornell_smoke() {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&world);
+    Scene::with_lights(world, lights, cam)
 }
 
-fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
+fn final_scene(
+    image_width: i32,
+    samples_per_pixel: i32,
+    max_depth: i32,
+    split: SplitMethod,
+) -> Scene {
     // Create a grid of 20x20 boxes of different height as floor
     let mut boxes1 = HittableList::default();
-    let ground = Material::lambertian(Color::new(0.48, 0.83, 0.53));
+    let ground = Arc::new(Lambertian::new(Color::new(0.48, 0.83, 0.53)));
 
     let boxes_per_side = 20;
     for i in 0..boxes_per_side {
@@ -490,24 +512,27 @@ fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
     }
 
     let mut world = HittableList::default();
+    let mut lights = HittableList::default();
 
-    world.add(Rc::new(BvhNode::from_list(&mut boxes1)));
+    world.add(Arc::new(BvhNode::with_split(&boxes1.objects, split)));
 
     // Create a rectangular light source above all other components
-    let light = Material::diffuse_light(Color::new(7.0, 7.0, 7.0));
-    world.add(Rc::new(Quad::new(
+    let light = Arc::new(DiffuseLight::new(Color::new(7.0, 7.0, 7.0)));
+    let light_quad = Arc::new(Quad::new(
         vec3(123.0, 554.0, 147.0),
         vec3(300.0, 0.0, 0.0),
         vec3(0.0, 0.0, 265.0),
         light,
-    )));
+    ));
+    world.add(light_quad.clone());
+    lights.add(light_quad);
 
     // Create a moving sphere
     let center1 = vec3(400.0, 400.0, 200.0);
     let center2 = center1 + vec3(30.0, 0.0, 0.0);
 
-    let sphere_material = Material::lambertian(Color::new(0.7, 0.3, 0.1));
-    world.add(Rc::new(Sphere::moving(
+    let sphere_material = Arc::new(Lambertian::new(Color::new(0.7, 0.3, 0.1)));
+    world.add(Arc::new(Sphere::moving(
         center1,
         center2,
         50.0,
@@ -515,67 +540,70 @@ fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
     )));
 
     // Create a stationary glass and a stationary metal sphere
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         vec3(260.0, 150.0, 45.0),
         50.0,
-        Material::dielectric(REFRACTION_GLASS),
+        Arc::new(Dielectric::new(REFRACTION_GLASS)),
     )));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         vec3(0.0, 150.0, 145.0),
         50.0,
-        Material::metal(Color::new(0.8, 0.8, 0.9), 1.0),
+        Arc::new(Metal::new(Color::new(0.8, 0.8, 0.9), 1.0)),
     )));
 
     // Create a glass sphere filled with smoke
-    let mut boundary = Rc::new(Sphere::new(
+    let mut boundary = Arc::new(Sphere::new(
         vec3(360.0, 150.0, 145.0),
         70.0,
-        Material::dielectric(REFRACTION_GLASS),
+        Arc::new(Dielectric::new(REFRACTION_GLASS)),
     ));
     world.add(boundary.clone());
-    world.add(Rc::new(ConstantMedium::new(
+    world.add(Arc::new(ConstantMedium::new(
         boundary,
         0.2,
         Color::new(0.2, 0.4, 0.9),
     )));
 
     // Create fog across the whole scene
-    boundary = Rc::new(Sphere::new(
+    boundary = Arc::new(Sphere::new(
         vec3(0.0, 0.0, 0.0),
         5000.0,
-        Material::dielectric(REFRACTION_GLASS),
+        Arc::new(Dielectric::new(REFRACTION_GLASS)),
     ));
-    world.add(Rc::new(ConstantMedium::new(
+    world.add(Arc::new(ConstantMedium::new(
         boundary,
         0.0001,
         Color::white(),
     )));
 
     // Create a globe
-    let emat = Material::lambertian_from_tex(Rc::new(ImageTexture::new("misc\\earthmap.jpg")));
-    world.add(Rc::new(Sphere::new(vec3(400.0, 200.0, 400.0), 100.0, emat)));
+    let emat = Arc::new(Lambertian::from_tex(Arc::new(ImageTexture::new("misc/earthmap.jpg"))));
+    world.add(Arc::new(Sphere::new(vec3(400.0, 200.0, 400.0), 100.0, emat)));
 
     // Create a sphere with noise
-    let pertext = Rc::new(NoiseTexture::new(0.2));
-    world.add(Rc::new(Sphere::new(
+    let pertext = Arc::new(NoiseTexture::new(0.2));
+    world.add(Arc::new(Sphere::new(
         vec3(220.0, 280.0, 300.0),
         80.0,
-        Material::lambertian_from_tex(pertext),
+        Arc::new(Lambertian::from_tex(pertext)),
     )));
 
     // Create cube of spheres and move in scene
     let mut boxes2 = HittableList::default();
-    let white = Material::lambertian(Color::new(0.73, 0.73, 0.73));
-    for j in 0..1000 {
-        boxes2.add(Rc::new(Sphere::new(
+    let white = Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)));
+    for _ in 0..1000 {
+        boxes2.add(Arc::new(Sphere::new(
             random_from(0.0, 165.0),
             10.0,
             white.clone(),
         )));
     }
 
-    world.add(Rc::new(Translate::new(
-        Rc::new(RotateY::new(Rc::new(boxes2), 15.0)),
+    world.add(Arc::new(Translate::new(
+        Arc::new(RotateY::new(
+            Arc::new(BvhNode::with_split(&boxes2.objects, split)),
+            15.0,
+        )),
         vec3(-100.0, 270.0, 395.0),
     )));
 
@@ -595,20 +623,618 @@ fn final_scene(image_width: i32, samples_per_pixel: i32, max_depth: i32) {
 
     cam.defocus_angle = 0.0;
 
-    cam.render(&world);
+    Scene::with_lights(world, lights, cam)
+}
+
+/// Built-in scene: name, description and constructor, which splits its hierarchies with the
+/// given method
+type SceneEntry = (&'static str, &'static str, fn(SplitMethod) -> Scene);
+
+/// Built-in scenes selectable from the command line
+const SCENES: [SceneEntry; 10] = [
+    (
+        "bouncing-spheres",
+        "Random field of moving, metal and glass spheres",
+        bouncing_spheres,
+    ),
+    (
+        "checkered-spheres",
+        "Two spheres with a checker texture",
+        |_| checkered_spheres(),
+    ),
+    ("earth", "Globe with an image texture", |_| earth()),
+    ("perlin-spheres", "Spheres with Perlin noise", |_| perlin_spheres()),
+    ("quads", "Five colored quadrilaterals", |_| quads()),
+    ("simple-light", "Noise spheres lit by an area light", |_| simple_light()),
+    ("cornell-box", "Cornell box with two rotated boxes", |_| cornell_box()),
+    ("cornell-smoke", "Cornell box with smoke boxes", |_| cornell_smoke()),
+    (
+        "final-scene",
+        "Final scene of The Next Week at full quality",
+        |split| final_scene(800, 10000, 40, split),
+    ),
+    (
+        "final-scene-draft",
+        "Final scene of The Next Week at draft quality",
+        |split| final_scene(400, 250, 4, split),
+    ),
+];
+
+/// Render one of the built-in scenes to an image file
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Name of the built-in scene to render
+    #[arg(short, long, default_value = "final-scene")]
+    scene: String,
+
+    /// Scene file to render instead of a built-in scene
+    #[arg(short, long, conflicts_with = "scene")]
+    file: Option<PathBuf>,
+
+    /// List the built-in scenes and exit
+    #[arg(long)]
+    list_scenes: bool,
+
+    /// Output image, the format is picked from the extension ("-" writes a P3 pixmap to stdout)
+    #[arg(short, long, default_value = "image.png")]
+    output: PathBuf,
+
+    /// Output format overriding the file extension (png, jpeg, ppm, ppm-ascii, hdr, exr)
+    #[arg(long)]
+    format: Option<OutputFormat>,
+
+    /// Rendered image width in pixel count
+    #[arg(short, long, value_parser = clap::value_parser!(i32).range(1..=MAX_IMAGE_SIZE as i64))]
+    width: Option<i32>,
+
+    /// Count of random samples for each pixel, the maximum with adaptive sampling
+    #[arg(long = "spp", value_parser = clap::value_parser!(i32).range(1..))]
+    samples_per_pixel: Option<i32>,
+
+    /// Stop sampling pixels once their estimated error is below this, e.g. 0.005, 0 disables it
+    #[arg(long, value_parser = parse_error_threshold)]
+    error_threshold: Option<f64>,
+
+    /// Samples every pixel takes before adaptive sampling may stop it
+    #[arg(long = "min-spp", value_parser = clap::value_parser!(i32).range(1..))]
+    min_samples_per_pixel: Option<i32>,
+
+    /// Maximum number of ray bounces into scene
+    #[arg(short = 'd', long, value_parser = clap::value_parser!(i32).range(1..=MAX_DEPTH as i64))]
+    max_depth: Option<i32>,
+
+    /// Ratio of image width over height, either as a number or as "16:9"
+    #[arg(short, long, value_parser = parse_aspect_ratio)]
+    aspect_ratio: Option<f64>,
+
+    /// Number of render threads, 0 uses all available cores
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+
+    /// Seed of the random numbers, renders with the same seed are identical. Also changes the
+    /// random layout of the built-in scenes.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Placement of the samples within pixels and along paths (independent, stratified, halton,
+    /// sobol)
+    #[arg(long, value_parser = sampler::by_name)]
+    sampler: Option<Arc<dyn Sampler>>,
+
+    /// Pixel reconstruction filter weighting samples for the pixels around them (box, tent,
+    /// gaussian, mitchell, lanczos)
+    #[arg(long)]
+    filter: Option<FilterKind>,
+
+    /// Radius of the reconstruction filter in pixels, by default the usual one for the filter
+    #[arg(long, value_parser = parse_filter_radius)]
+    filter_radius: Option<f64>,
+
+    /// Largest luminance of the light a sample gathers over more than one bounce, suppresses
+    /// fireflies at the cost of some brightness
+    #[arg(long, value_name = "LUMINANCE", value_parser = parse_indirect_clamp)]
+    indirect_clamp: Option<f64>,
+
+    /// Remove the noise from the finished image with this many passes of a filter guided by the
+    /// albedo, normals and depth
+    #[arg(
+        long,
+        value_name = "PASSES",
+        num_args = 0..=1,
+        default_missing_value = "5",
+        value_parser = clap::value_parser!(u32).range(0..=MAX_PASSES as i64)
+    )]
+    denoise: Option<u32>,
+
+    /// Brightness differences the denoiser smooths over, in standard deviations of the noise
+    #[arg(long, value_parser = parse_denoise_strength)]
+    denoise_strength: Option<f64>,
+
+    /// Exposure adjustment in stops for 8 bit outputs, every stop doubles the brightness
+    #[arg(long, value_name = "EV", allow_hyphen_values = true, value_parser = parse_exposure)]
+    exposure: Option<f64>,
+
+    /// Operator compressing bright colors for 8 bit outputs (clamp, reinhard, extended-reinhard,
+    /// aces, agx)
+    #[arg(long)]
+    tone_map: Option<ToneMapper>,
+
+    /// Brightness the extended Reinhard operator maps to white
+    #[arg(long, value_parser = parse_white_point)]
+    white_point: Option<f64>,
+
+    /// Quantize 8 bit outputs without dithering
+    #[arg(long)]
+    no_dither: bool,
+
+    /// How bounding volume hierarchies divide their objects (median, sah)
+    #[arg(long, default_value = "sah")]
+    bvh: SplitMethod,
+
+    /// Print the shape and estimated cost of the scene's bounding volume hierarchies
+    #[arg(long)]
+    bvh_stats: bool,
+
+    /// Don't report the progress and the statistics of the render on stderr
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Stop rendering after this many seconds, even if not all samples are taken yet
+    #[arg(long, value_parser = parse_time_limit)]
+    time_limit: Option<Duration>,
+
+    /// Seconds between writes of the partially rendered image and the checkpoint, 0 disables
+    /// them
+    #[arg(long, default_value = "30", value_parser = parse_seconds)]
+    preview_interval: Duration,
+
+    /// File keeping the samples taken so far. If it exists the render continues from it, and it's
+    /// updated while rendering, so an interrupted render or one with more samples can resume.
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Also write an image of the number of samples every pixel took
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+
+    /// Arbitrary output variables to render along with the image, separated by commas (albedo,
+    /// normal, depth, position, uv, material_id, direct, indirect). EXR outputs get them as
+    /// layers, other formats as separate images named like `image.albedo.png`.
+    #[arg(long = "aov", value_delimiter = ',')]
+    aovs: Vec<Aov>,
+
+    /// Write the AOVs of EXR outputs to separate images instead of layers of the output file
+    #[arg(long)]
+    separate_aovs: bool,
+}
+
+fn parse_error_threshold(s: &str) -> Result<f64, String> {
+    let threshold: f64 = s.parse().map_err(|_| format!("invalid error threshold `{s}`"))?;
+    if threshold.is_finite() && threshold >= 0.0 {
+        Ok(threshold)
+    } else {
+        Err(format!("error threshold `{s}` must be zero or positive"))
+    }
+}
+
+fn parse_aspect_ratio(s: &str) -> Result<f64, String> {
+    let ratio = match s.split_once([':', '/']) {
+        Some((width, height)) => {
+            let width: f64 = width.trim().parse().map_err(|_| format!("invalid width `{width}`"))?;
+            let height: f64 =
+                height.trim().parse().map_err(|_| format!("invalid height `{height}`"))?;
+            width / height
+        }
+        None => s.parse().map_err(|_| format!("invalid aspect ratio `{s}`"))?,
+    };
+
+    let max = MAX_IMAGE_SIZE as f64;
+    if (1.0 / max..=max).contains(&ratio) {
+        Ok(ratio)
+    } else {
+        Err(format!("aspect ratio `{s}` must be between 1/{max} and {max}"))
+    }
+}
+
+fn parse_filter_radius(s: &str) -> Result<f64, String> {
+    let radius: f64 = s.parse().map_err(|_| format!("invalid filter radius `{s}`"))?;
+    if radius > 0.0 && radius <= filter::MAX_RADIUS {
+        Ok(radius)
+    } else {
+        Err(format!(
+            "filter radius `{s}` must be positive and at most {}",
+            filter::MAX_RADIUS
+        ))
+    }
+}
+
+fn parse_indirect_clamp(s: &str) -> Result<f64, String> {
+    let clamp: f64 = s.parse().map_err(|_| format!("invalid luminance `{s}`"))?;
+    if clamp.is_finite() && clamp > 0.0 {
+        Ok(clamp)
+    } else {
+        Err(format!("indirect clamp `{s}` must be positive"))
+    }
+}
+
+fn parse_denoise_strength(s: &str) -> Result<f64, String> {
+    let strength: f64 = s.parse().map_err(|_| format!("invalid denoise strength `{s}`"))?;
+    if strength.is_finite() && strength > 0.0 {
+        Ok(strength)
+    } else {
+        Err(format!("denoise strength `{s}` must be positive"))
+    }
+}
+
+fn parse_exposure(s: &str) -> Result<f64, String> {
+    let exposure: f64 = s.parse().map_err(|_| format!("invalid exposure `{s}`"))?;
+    if exposure.is_finite() {
+        Ok(exposure)
+    } else {
+        Err(format!("exposure `{s}` must be a finite number of stops"))
+    }
+}
+
+fn parse_white_point(s: &str) -> Result<f64, String> {
+    let white_point: f64 = s.parse().map_err(|_| format!("invalid white point `{s}`"))?;
+    if white_point.is_finite() && white_point > 0.0 {
+        Ok(white_point)
+    } else {
+        Err(format!("white point `{s}` must be positive"))
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds: f64 = s.parse().map_err(|_| format!("invalid number of seconds `{s}`"))?;
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("`{s}` isn't a valid duration"))
+}
+
+fn parse_time_limit(s: &str) -> Result<Duration, String> {
+    let limit = parse_seconds(s)?;
+    if limit.is_zero() {
+        return Err("time limit must be positive".to_string());
+    }
+    Ok(limit)
+}
+
+/// Format of the output given on the command line, `None` for a pixmap on stdout
+fn output_format(args: &Args) -> Result<Option<OutputFormat>, String> {
+    if let Some(format) = args.format.or_else(|| OutputFormat::from_path(&args.output)) {
+        return Ok(Some(format));
+    }
+    if args.output == Path::new("-") {
+        return Ok(None);
+    }
+    Err(format!(
+        "Unknown output format of `{}`, use the extension png, jpg, ppm, hdr or exr, or --format",
+        args.output.display()
+    ))
+}
+
+/// Writes the image and the AOVs of its accumulator to the output given on the command line, in
+/// the format [`output_format`] picked for it.
+fn save_output(
+    image: &Framebuffer,
+    accumulator: &Accumulator,
+    args: &Args,
+    format: Option<OutputFormat>,
+) -> ImageResult<()> {
+    let path = &args.output;
+    let Some(format) = format else {
+        return image.save(path);
+    };
+
+    let aovs: Vec<(Aov, Framebuffer)> = args
+        .aovs
+        .iter()
+        .filter_map(|&aov| Some((aov, accumulator.aov(aov)?)))
+        .collect();
+    if format == OutputFormat::Exr && !args.separate_aovs && !aovs.is_empty() {
+        return save_replacing(path, |partial_path| {
+            aov::save_exr_layers(partial_path, image, &aovs)
+        });
+    }
+
+    save_replacing(path, |partial_path| {
+        image.save_with_format(partial_path, format)
+    })?;
+    for (aov, image) in &aovs {
+        let mut name = path.file_stem().unwrap_or_default().to_owned();
+        name.push(format!(".{}", aov.name()));
+        if let Some(extension) = path.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        save_replacing(&path.with_file_name(name), |partial_path| {
+            image.save_with_format(partial_path, format)
+        })?;
+    }
+    Ok(())
+}
+
+/// Writes a file under a temporary name first and then renames it, so that an interrupted write
+/// never destroys an earlier preview.
+fn save_replacing(path: &Path, save: impl FnOnce(&Path) -> ImageResult<()>) -> ImageResult<()> {
+    let mut partial_name = path.file_name().unwrap_or_default().to_owned();
+    partial_name.push(".partial");
+    let partial_path = path.with_file_name(partial_name);
+    save(&partial_path)?;
+    fs::rename(&partial_path, path).map_err(ImageError::IoError)
+}
+
+/// Whether the render needs the AOVs, for writing them or for the denoiser
+fn needs_aovs(camera: &Camera, args: &Args) -> bool {
+    !args.aovs.is_empty() || camera.denoiser.is_some()
+}
+
+/// Creates an accumulator for the image of the camera, keeping AOVs if they're needed.
+fn new_accumulator(camera: &Camera, args: &Args) -> Accumulator {
+    let (width, height) = camera.image_size();
+    if needs_aovs(camera, args) {
+        Accumulator::with_aovs(width, height)
+    } else {
+        Accumulator::new(width, height)
+    }
+}
+
+/// Identifies the scene and the settings affecting the image, so that a checkpoint isn't resumed
+/// for a different render. Scene files are hashed by content, but not the meshes and textures they
+/// reference.
+fn scene_hash(args: &Args, camera: &Camera) -> io::Result<u64> {
+    let mut hasher = SceneHasher::default();
+    match &args.file {
+        Some(path) => hasher.write(&fs::read(path)?),
+        None => hasher.write(args.scene.as_bytes()),
+    }
+    camera.hash_settings(&mut hasher);
+    Ok(hasher.finish())
+}
+
+/// Continues from the checkpoint file if it exists, or starts from scratch if it doesn't.
+fn resume(
+    path: &Path,
+    checkpoint: &Checkpoint,
+    camera: &Camera,
+    args: &Args,
+) -> Result<Accumulator, String> {
+    let (saved, accumulator) = match Checkpoint::load(path) {
+        Ok(loaded) => loaded,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(new_accumulator(camera, args));
+        }
+        Err(error) => return Err(error.to_string()),
+    };
+    if saved != *checkpoint {
+        return Err("checkpoint is for a different scene, seed or image size".to_string());
+    }
+    if needs_aovs(camera, args) && !accumulator.has_aovs() {
+        return Err("checkpoint was saved without the AOVs this render needs".to_string());
+    }
+
+    eprintln!(
+        "Resuming from {} with {} samples per pixel",
+        path.display(),
+        accumulator.min_sample_count()
+    );
+    Ok(accumulator)
+}
+
+fn list_scenes() {
+    println!("Available scenes:");
+    for (name, description, _) in SCENES {
+        println!("  {name:<20} {description}");
+    }
 }
 
 fn main() {
-    match 9 {
-        1 => bouncing_spheres(),
-        2 => checkered_spheres(),
-        3 => earth(),
-        4 => perlin_spheres(),
-        5 => quads(),
-        6 => simple_light(),
-        7 => cornell_box(),
-        8 => cornell_smoke(),
-        9 => final_scene(800, 10000, 40),
-        i32::MIN..=i32::MAX => final_scene(400, 250, 4),
+    let args = Args::parse();
+
+    if args.list_scenes {
+        list_scenes();
+        return;
+    }
+
+    if !args.aovs.is_empty() && args.output == Path::new("-") {
+        eprintln!("AOVs can't be written to stdout");
+        process::exit(2);
+    }
+    // Check the output before rendering, so that a typo doesn't throw away a long render.
+    let format = output_format(&args).unwrap_or_else(|error| {
+        eprintln!("{error}");
+        process::exit(2);
+    });
+
+    let mut scene = match &args.file {
+        Some(path) => match scene_file::load_with_split(path, args.bvh) {
+            Ok(scene) => scene,
+            Err(error) => {
+                eprintln!("Error loading {}: {error}", path.display());
+                process::exit(1);
+            }
+        },
+        None => {
+            let Some((_, _, build_scene)) = SCENES.iter().find(|(name, _, _)| *name == args.scene)
+            else {
+                eprintln!("Unknown scene `{}`", args.scene);
+                list_scenes();
+                process::exit(2);
+            };
+            // Randomly generated scenes are laid out the same way for the same seed.
+            seed_random(args.seed.unwrap_or(0), u64::MAX);
+            build_scene(args.bvh)
+        }
+    };
+    let cam = &mut scene.camera;
+    if let Some(image_width) = args.width {
+        cam.image_width = image_width;
+    }
+    if let Some(samples_per_pixel) = args.samples_per_pixel {
+        cam.samples_per_pixel = samples_per_pixel;
+    }
+    if let Some(error_threshold) = args.error_threshold {
+        cam.error_threshold = error_threshold;
+    }
+    if let Some(min_samples_per_pixel) = args.min_samples_per_pixel {
+        cam.min_samples_per_pixel = min_samples_per_pixel;
+    }
+    if let Some(max_depth) = args.max_depth {
+        cam.max_depth = max_depth;
+    }
+    if let Some(aspect_ratio) = args.aspect_ratio {
+        cam.aspect_ratio = aspect_ratio;
+    }
+    if let Some(threads) = args.threads {
+        cam.threads = threads;
+    }
+    if let Some(seed) = args.seed {
+        cam.seed = seed;
+    }
+    if let Some(sampler) = &args.sampler {
+        cam.sampler = sampler.clone();
+    }
+    if args.filter.is_some() || args.filter_radius.is_some() {
+        let kind = args.filter.unwrap_or(cam.filter.kind());
+        let radius = args.filter_radius.unwrap_or(kind.default_radius());
+        cam.filter = Filter::with_radius(kind, radius);
+    }
+    if let Some(time_limit) = args.time_limit {
+        cam.time_limit = Some(time_limit);
+    }
+    cam.progress = !args.quiet;
+    if let Some(indirect_clamp) = args.indirect_clamp {
+        cam.indirect_clamp = indirect_clamp;
+    }
+    if args.denoise.is_some() || args.denoise_strength.is_some() {
+        let mut denoiser = cam.denoiser.unwrap_or_default();
+        if let Some(passes) = args.denoise {
+            denoiser.passes = passes;
+        }
+        if let Some(strength) = args.denoise_strength {
+            denoiser.strength = strength;
+        }
+        cam.denoiser = (denoiser.passes > 0).then_some(denoiser);
+    }
+    if let Some(exposure) = args.exposure {
+        cam.display.exposure = exposure;
+    }
+    if let Some(tone_mapper) = args.tone_map {
+        cam.display.tone_mapper = tone_mapper;
+    }
+    if let Some(white_point) = args.white_point {
+        cam.display.white_point = white_point;
+    }
+    if args.no_dither {
+        cam.display.dither = false;
+    }
+
+    let (_, image_height) = cam.image_size();
+    if image_height > MAX_IMAGE_SIZE as u32 {
+        eprintln!(
+            "Image height {image_height} is larger than the maximum of {MAX_IMAGE_SIZE} pixels"
+        );
+        process::exit(2);
+    }
+
+    if args.bvh_stats {
+        let hierarchies = bvh::hierarchies(&scene.world);
+        if hierarchies.is_empty() {
+            eprintln!("No bounding volume hierarchies in the scene");
+        }
+        for (index, node) in hierarchies.iter().enumerate() {
+            eprintln!("BVH {}: {}", index + 1, node.stats());
+        }
+    }
+
+    let checkpoint = args.checkpoint.as_ref().map(|path| {
+        let checkpoint = match scene_hash(&args, &scene.camera) {
+            Ok(scene_hash) => Checkpoint {
+                seed: scene.camera.seed,
+                scene_hash,
+            },
+            Err(error) => {
+                eprintln!("Error hashing the scene: {error}");
+                process::exit(1);
+            }
+        };
+        (path, checkpoint)
+    });
+    let mut accumulator = match &checkpoint {
+        Some((path, checkpoint)) => match resume(path, checkpoint, &scene.camera, &args) {
+            Ok(accumulator) => accumulator,
+            Err(error) => {
+                eprintln!("Error resuming from {}: {error}", path.display());
+                process::exit(1);
+            }
+        },
+        None => new_accumulator(&scene.camera, &args),
+    };
+    let save_checkpoint = |accumulator: &Accumulator| {
+        if let Some((path, checkpoint)) = &checkpoint {
+            if let Err(error) = checkpoint.save(path, accumulator) {
+                eprintln!("Error writing checkpoint {}: {error}", path.display());
+            }
+        }
+    };
+
+    // Long renders periodically write what they have so far, so there's something to look at
+    // and something left if the render gets killed.
+    let previews = args.output != Path::new("-");
+    let display = scene.camera.display;
+    let mut last_save = Instant::now();
+    let finished = scene.render_into(&mut accumulator, |accumulator| {
+        if args.preview_interval.is_zero() || last_save.elapsed() < args.preview_interval {
+            return;
+        }
+        if previews {
+            let mut preview = accumulator.average();
+            preview.set_display(display);
+            if let Err(error) = save_output(&preview, accumulator, &args, format) {
+                eprintln!("Error writing preview {}: {error}", args.output.display());
+            }
+        }
+        save_checkpoint(accumulator);
+        last_save = Instant::now();
+    });
+    save_checkpoint(&accumulator);
+
+    if !finished {
+        eprintln!(
+            "Time limit reached after {} of {} samples per pixel",
+            accumulator.min_sample_count(),
+            scene.camera.samples_per_pixel
+        );
+    }
+    let invalid_samples = scene.camera.invalid_samples();
+    if invalid_samples.count > 0 {
+        let pixels: Vec<String> = invalid_samples
+            .pixels
+            .iter()
+            .map(|(x, y)| format!("({x}, {y})"))
+            .collect();
+        eprintln!(
+            "Warning: {} samples were NaN or infinite and counted as black, first in pixels {}",
+            invalid_samples.count,
+            pixels.join(", ")
+        );
+    }
+    if scene.camera.error_threshold > 0.0 {
+        let pixel_count = (accumulator.width() * accumulator.height()) as f64;
+        eprintln!(
+            "Adaptive sampling took {:.1} samples per pixel on average",
+            accumulator.total_sample_count() as f64 / pixel_count
+        );
+    }
+
+    if let Some(path) = &args.heatmap {
+        if let Err(error) = accumulator.sample_heatmap().save(path) {
+            eprintln!("Error writing {}: {error}", path.display());
+        }
+    }
+
+    let image = scene.camera.finish(&accumulator);
+    if let Err(error) = save_output(&image, &accumulator, &args, format) {
+        eprintln!("Error writing {}: {error}", args.output.display());
+        process::exit(1);
     }
 }
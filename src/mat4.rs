@@ -0,0 +1,361 @@
+use std::ops;
+
+use crate::{
+    rtweekend::degrees_to_radians,
+    vec3::{cross, unit_vector, vec3, Vec3},
+};
+
+/// Affine transformation stored as a 4x4 matrix acting on column vectors. The last row is always
+/// (0, 0, 0, 1).
+///
+/// Matrices compose like functions: `a * b` applies `b` first and `a` second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    /// Creates a transformation from its rows
+    ///
+    /// # Panics
+    ///
+    /// Panics if the last row isn't (0, 0, 0, 1), since only affine transformations are supported.
+    pub fn from_rows(m: [[f64; 4]; 4]) -> Mat4 {
+        assert!(
+            m[3] == [0.0, 0.0, 0.0, 1.0],
+            "last row of an affine transformation must be (0, 0, 0, 1)"
+        );
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// Returns the matrix of the linear part and the translation as columns.
+    fn from_columns(x: &Vec3, y: &Vec3, z: &Vec3, offset: &Vec3) -> Mat4 {
+        Mat4 {
+            m: [
+                [x.x, y.x, z.x, offset.x],
+                [x.y, y.y, z.y, offset.y],
+                [x.z, y.z, z.z, offset.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// Moves points by `offset`.
+    pub fn translation(offset: &Vec3) -> Mat4 {
+        Mat4::from_columns(
+            &vec3(1.0, 0.0, 0.0),
+            &vec3(0.0, 1.0, 0.0),
+            &vec3(0.0, 0.0, 1.0),
+            offset,
+        )
+    }
+
+    /// Scales each axis by the matching component of `factors`.
+    pub fn scaling(factors: &Vec3) -> Mat4 {
+        Mat4::from_columns(
+            &vec3(factors.x, 0.0, 0.0),
+            &vec3(0.0, factors.y, 0.0),
+            &vec3(0.0, 0.0, factors.z),
+            &Vec3::default(),
+        )
+    }
+
+    /// Rotates counterclockwise, looking against the axis, around an axis through the origin
+    ///
+    /// # Arguments
+    ///
+    /// * `axis` - Direction of the rotation axis, doesn't need to be normalized
+    /// * `angle` - Rotation angle in degrees
+    pub fn rotation(axis: &Vec3, angle: f64) -> Mat4 {
+        // Rodrigues' rotation formula written out as a matrix.
+        let a = unit_vector(axis);
+        let radians = degrees_to_radians(angle);
+        let (sin_theta, cos_theta) = (f64::sin(radians), f64::cos(radians));
+        let t = 1.0 - cos_theta;
+
+        Mat4 {
+            m: [
+                [
+                    t * a.x * a.x + cos_theta,
+                    t * a.x * a.y - sin_theta * a.z,
+                    t * a.x * a.z + sin_theta * a.y,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.y + sin_theta * a.z,
+                    t * a.y * a.y + cos_theta,
+                    t * a.y * a.z - sin_theta * a.x,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.z - sin_theta * a.y,
+                    t * a.y * a.z + sin_theta * a.x,
+                    t * a.z * a.z + cos_theta,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// Places an object at `from`, turned so that its +z axis points at `to` and its +y axis is
+    /// as close to `up` as possible.
+    pub fn look_at(from: &Vec3, to: &Vec3, up: &Vec3) -> Mat4 {
+        let z = unit_vector(&(to - from));
+        let x = unit_vector(&cross(up, &z));
+        let y = cross(&z, &x);
+        Mat4::from_columns(&x, &y, &z, from)
+    }
+
+    /// Transforms a point, applying both the linear part and the translation.
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.m;
+        vec3(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    /// Transforms a direction, which isn't affected by the translation.
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        vec3(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Returns the inverse transformation, or `None` if the matrix is singular or so close to it
+    /// that the inverse would be mostly rounding error. The test is relative to the scale of the
+    /// matrix, so uniformly tiny or huge scales still invert.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let m = &self.m;
+
+        // Invert the linear part through its cofactors.
+        let c00 = m[1][1] * m[2][2] - m[1][2] * m[2][1];
+        let c01 = m[1][2] * m[2][0] - m[1][0] * m[2][2];
+        let c02 = m[1][0] * m[2][1] - m[1][1] * m[2][0];
+        let det = m[0][0] * c00 + m[0][1] * c01 + m[0][2] * c02;
+
+        // Compare the determinant to the volume of a cube as long as the longest row, so that a
+        // matrix only counts as singular if it squashes some direction much more than it scales
+        // the others.
+        let row_length = |row: &[f64; 4]| vec3(row[0], row[1], row[2]).length();
+        let scale = m[..3].iter().map(row_length).fold(0.0, f64::max);
+        let inv_det = 1.0 / det;
+        if det.abs() <= 1e-12 * scale.powi(3) || !inv_det.is_finite() {
+            return None;
+        }
+
+        let x = vec3(c00, c01, c02) * inv_det;
+        let y = vec3(
+            m[0][2] * m[2][1] - m[0][1] * m[2][2],
+            m[0][0] * m[2][2] - m[0][2] * m[2][0],
+            m[0][1] * m[2][0] - m[0][0] * m[2][1],
+        ) * inv_det;
+        let z = vec3(
+            m[0][1] * m[1][2] - m[0][2] * m[1][1],
+            m[0][2] * m[1][0] - m[0][0] * m[1][2],
+            m[0][0] * m[1][1] - m[0][1] * m[1][0],
+        ) * inv_det;
+        let mut inverse = Mat4::from_columns(&x, &y, &z, &Vec3::default());
+
+        // Undo the translation after the linear part has been undone.
+        let offset = -inverse.transform_vector(&vec3(m[0][3], m[1][3], m[2][3]));
+        inverse.m[0][3] = offset.x;
+        inverse.m[1][3] = offset.y;
+        inverse.m[2][3] = offset.z;
+
+        Some(inverse)
+    }
+
+    /// Returns the matrix with the linear part transposed and without translation, which turns
+    /// the inverse of a transformation into the one for its normals.
+    pub fn transpose_linear(&self) -> Mat4 {
+        let m = &self.m;
+        Mat4::from_columns(
+            &vec3(m[0][0], m[0][1], m[0][2]),
+            &vec3(m[1][0], m[1][1], m[1][2]),
+            &vec3(m[2][0], m[2][1], m[2][2]),
+            &Vec3::default(),
+        )
+    }
+
+    /// Whether the transformation only rotates, mirrors, scales uniformly and translates. Such
+    /// transformations preserve angles, so directions and solid angles map one to one.
+    pub fn preserves_angles(&self) -> bool {
+        // The columns of the linear part have to be orthogonal and of the same length.
+        let gram = self.transpose_linear() * *self;
+        let scale = gram.m[0][0];
+        (0..3).all(|i| {
+            (0..3).all(|j| {
+                let expected = if i == j { scale } else { 0.0 };
+                f64::abs(gram.m[i][j] - expected) <= 1e-9 * scale
+            })
+        })
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::identity()
+    }
+}
+
+impl ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec3::dot;
+
+    fn assert_close(a: &Vec3, b: &Vec3) {
+        assert!((a - b).length() < 1e-9, "{a:?} != {b:?}");
+    }
+
+    fn assert_identity(m: &Mat4) {
+        for (i, row) in m.m.iter().enumerate() {
+            for (j, &value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-9, "{m:?}");
+            }
+        }
+    }
+
+    fn skewed() -> Mat4 {
+        Mat4::translation(&vec3(1.0, -2.0, 3.0))
+            * Mat4::rotation(&vec3(1.0, 2.0, -0.5), 70.0)
+            * Mat4::scaling(&vec3(2.0, 0.5, 3.0))
+            * Mat4::rotation(&vec3(0.0, 1.0, 1.0), -25.0)
+    }
+
+    #[test]
+    fn rotation_is_counterclockwise() {
+        let quarter = Mat4::rotation(&vec3(0.0, 0.0, 2.0), 90.0);
+        assert_close(
+            &quarter.transform_vector(&vec3(1.0, 0.0, 0.0)),
+            &vec3(0.0, 1.0, 0.0),
+        );
+        assert_close(
+            &quarter.transform_point(&vec3(0.0, 1.0, 5.0)),
+            &vec3(-1.0, 0.0, 5.0),
+        );
+    }
+
+    #[test]
+    fn translation_moves_points_but_not_vectors() {
+        let m = Mat4::translation(&vec3(1.0, 2.0, 3.0));
+        assert_close(
+            &m.transform_point(&vec3(1.0, 1.0, 1.0)),
+            &vec3(2.0, 3.0, 4.0),
+        );
+        assert_close(
+            &m.transform_vector(&vec3(1.0, 1.0, 1.0)),
+            &vec3(1.0, 1.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn inverse_undoes_the_transformation() {
+        let m = skewed();
+        let inverse = m.inverse().unwrap();
+        assert_identity(&(m * inverse));
+        assert_identity(&(inverse * m));
+
+        let p = vec3(0.3, -7.0, 2.5);
+        assert_close(&inverse.transform_point(&m.transform_point(&p)), &p);
+        assert_close(&inverse.transform_vector(&m.transform_vector(&p)), &p);
+    }
+
+    #[test]
+    fn singular_matrix_has_no_inverse() {
+        assert!(Mat4::scaling(&vec3(1.0, 0.0, 1.0)).inverse().is_none());
+        let flat = Mat4::from_rows([
+            [1.0, 2.0, 3.0, 4.0],
+            [2.0, 4.0, 6.0, 5.0],
+            [0.0, 1.0, 1.0, 6.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        assert!(flat.inverse().is_none());
+    }
+
+    #[test]
+    fn singularity_is_relative_to_the_scale() {
+        let tiny = Mat4::scaling(&vec3(1e-5, 1e-5, 1e-5));
+        let product = tiny * tiny.inverse().unwrap();
+        let p = vec3(1.0, -2.0, 3.0);
+        assert!((product.transform_point(&p) - p).length() < 1e-9);
+
+        assert!(Mat4::scaling(&vec3(1.0, 1e-14, 1.0)).inverse().is_none());
+        assert!(Mat4::scaling(&vec3(1e-200, 1e-200, 1e-200))
+            .inverse()
+            .is_none());
+    }
+
+    #[test]
+    fn normal_matrix_keeps_normals_perpendicular() {
+        let m = skewed();
+        let normal_matrix = m.inverse().unwrap().transpose_linear();
+
+        // Two tangents of a surface and its normal, transformed into world space.
+        let (s, t) = (vec3(1.0, 2.0, 0.5), vec3(-0.5, 0.0, 3.0));
+        let normal = normal_matrix.transform_vector(&cross(&s, &t));
+        for tangent in [s, t] {
+            let tangent = m.transform_vector(&tangent);
+            assert!(dot(&normal, &tangent).abs() < 1e-9);
+        }
+        // Transforming the tangents directly would not have kept the normal perpendicular.
+        assert!(dot(&m.transform_vector(&cross(&s, &t)), &m.transform_vector(&s)).abs() > 0.1);
+    }
+
+    #[test]
+    fn only_similarities_preserve_angles() {
+        let similar = Mat4::translation(&vec3(4.0, 0.0, 0.0))
+            * Mat4::rotation(&vec3(1.0, 1.0, 1.0), 33.0)
+            * Mat4::scaling(&vec3(-2.0, 2.0, 2.0));
+        assert!(similar.preserves_angles());
+        assert!(!skewed().preserves_angles());
+        assert!(!Mat4::scaling(&vec3(1.0, 1.0, 1.01)).preserves_angles());
+    }
+
+    #[test]
+    fn look_at_points_z_at_the_target() {
+        let from = vec3(1.0, 2.0, 3.0);
+        let m = Mat4::look_at(&from, &vec3(1.0, 2.0, -7.0), &vec3(0.0, 1.0, 0.0));
+        assert_close(&m.transform_point(&Vec3::default()), &from);
+        assert_close(
+            &m.transform_vector(&vec3(0.0, 0.0, 1.0)),
+            &vec3(0.0, 0.0, -1.0),
+        );
+        assert_close(
+            &m.transform_vector(&vec3(0.0, 1.0, 0.0)),
+            &vec3(0.0, 1.0, 0.0),
+        );
+        assert!(m.preserves_angles());
+    }
+}
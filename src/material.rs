@@ -1,184 +1,166 @@
-use std::rc::Rc;
+use std::{
+    f64::consts::PI,
+    sync::{Arc, LazyLock},
+};
 
 use crate::{
     color::Color,
+    pdf::{CosinePdf, Pdf, SpherePdf},
     ray::{ray_with_time, Ray},
-    rtweekend::random_double,
+    sampler::{sample_1d, Dimension},
     sphere::HitRecord,
     texture::{SolidColor, Texture},
     vec3::{dot, random_unit_vector, reflect, refract, unit_vector, Vec3},
 };
 
-#[derive(Clone, Default)]
-pub enum Mat {
-    #[default]
-    Lambertian,
-    Metal,
-    Dielectric,
-    DiffuseLight,
-    Isotropic,
+/// Outcome of scattering a ray off a material
+#[derive(Default)]
+pub struct ScatterRecord {
+    /// Fraction of the light that is scattered
+    pub attenuation: Color,
+    /// Distribution of the scattered directions, `None` for specular materials which scatter
+    /// into the single direction of `skip_pdf_ray`
+    pub pdf: Option<Box<dyn Pdf>>,
+    /// Ray that specular materials scatter into
+    pub skip_pdf_ray: Ray,
 }
 
-#[derive(Clone)]
-pub struct Material {
-    material: Mat,
-    albedo: Color,
-    fuzz: f64,
-    refraction_index: f64,
-    tex: Rc<dyn Texture>,
-}
-
-impl Material {
-    fn new(
-        material: Mat,
-        albedo: Color,
-        fuzz: f64,
-        refraction_index: f64,
-        tex: Rc<dyn Texture>,
-    ) -> Material {
-        Material {
-            material,
-            albedo,
-            fuzz,
-            refraction_index,
-            tex,
-        }
+/// Describes how light interacts with a surface or a volume.
+///
+/// All methods have defaults for a material that neither scatters nor emits light, so a material
+/// only implements the parts it needs.
+pub trait Material: Send + Sync {
+    /// Scatters an incoming ray off the material. Returns false if the ray is absorbed.
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _srec: &mut ScatterRecord) -> bool {
+        false
     }
-    pub fn lambertian(albedo: Color) -> Material {
-        Self::new(
-            Mat::Lambertian,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            Rc::new(SolidColor::from_color(albedo)),
-        )
-    }
-
-    pub fn lambertian_from_tex(tex: Rc<dyn Texture>) -> Material {
-        Self::new(
-            Mat::Lambertian,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            tex,
-        )
-    }
-
-    pub fn isotropic(albedo: Color) -> Material {
-        Self::new(
-            Mat::Isotropic,
-            Color::default(),
-            f64::default(),
-            f64::default(),
-            Rc::new(SolidColor::from_color(albedo)),
-        )
-    }
-
-    pub fn isotropic_from_tex(tex: Rc<dyn Texture>) -> Material {
-        Self::new(
-            Mat::Isotropic,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            tex,
-        )
-    }
-
-    pub fn diffuse_light(emit: Color) -> Material {
-        Self::new(
-            Mat::DiffuseLight,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            Rc::new(SolidColor::from_color(emit)),
-        )
-    }
-
-    pub fn diffuse_light_from_tex(tex: Rc<dyn Texture>) -> Self {
-        Self::new(
-            Mat::DiffuseLight,
-            Default::default(),
-            Default::default(),
-            Default::default(),
-            tex,
-        )
-    }
-
-    pub fn dielectric(refraction_index: f64) -> Material {
-        Self::new(
-            Mat::Dielectric,
-            Default::default(),
-            Default::default(),
-            refraction_index,
-            Rc::new(SolidColor::from_color(Color::default())),
-        )
-    }
-
-    pub fn metal(albedo: Color, fuzz: f64) -> Material {
-        Self::new(
-            Mat::Metal,
-            albedo,
-            fuzz,
-            Default::default(),
-            Rc::new(SolidColor::from_color(Color::default())),
-        )
-    }
-
-    pub fn scatter(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        match self.material {
-            Mat::Lambertian => self.scatter_lambertian(r_in, rec, attenuation, scattered),
-            Mat::Metal => self.scatter_metal(r_in, rec, attenuation, scattered),
-            Mat::Dielectric => self.scatter_dielectic(r_in, rec, attenuation, scattered),
-            Mat::DiffuseLight => false,
-            Mat::Isotropic => self.scatter_isotropic(r_in, rec, attenuation, scattered),
-        }
+
+    /// Light emitted by the material at the given surface coordinates and point.
+    fn emitted(&self, _u: f64, _v: f64, _p: &Vec3) -> Color {
+        Color::black()
     }
 
-    fn scatter_lambertian(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction = &random_unit_vector() + &rec.normal;
-        // Catch degenerate scatter direction
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal.clone();
-        }
-        *scattered = ray_with_time(rec.p.clone(), scatter_direction, r_in.time());
-        *attenuation = self.tex.value(rec.u, rec.v, &rec.p);
+    /// Probability density, with respect to solid angle, of the material's scattering
+    /// distribution producing the direction of `scattered`. Specular materials, whose scattering
+    /// is a delta lobe, return zero.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    /// Evaluates the BSDF, multiplied by the cosine of the scattered direction with the normal,
+    /// for light leaving along `scattered`. For volumes this is the phase function. Specular
+    /// materials return black, their delta lobes can't be evaluated for a given direction.
+    fn bsdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> Color {
+        Color::black()
+    }
+
+    /// Color of the surface at a hit point without any lighting, as used for the albedo AOV.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color::black()
+    }
+}
+
+/// Material of hit records that haven't been filled in by a hit yet
+pub fn default_material() -> Arc<dyn Material> {
+    static DEFAULT: LazyLock<Arc<dyn Material>> =
+        LazyLock::new(|| Arc::new(Lambertian::new(Color::black())));
+    DEFAULT.clone()
+}
+
+/// Ideal diffuse surface
+pub struct Lambertian {
+    tex: Arc<dyn Texture>,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Color) -> Lambertian {
+        Lambertian::from_tex(Arc::new(SolidColor::from_color(albedo)))
+    }
+
+    pub fn from_tex(tex: Arc<dyn Texture>) -> Lambertian {
+        Lambertian { tex }
+    }
+}
+
+impl Material for Lambertian {
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.attenuation = self.tex.value(rec.u, rec.v, &rec.p);
+        srec.pdf = Some(Box::new(CosinePdf::new(&rec.normal)));
         true
     }
 
-    fn scatter_metal(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cos_theta = dot(&rec.normal, &unit_vector(scattered.direction()));
+        f64::max(cos_theta, 0.0) / PI
+    }
+
+    fn bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        // The albedo is distributed exactly like the sampling density.
+        self.scattering_pdf(r_in, rec, scattered) * self.tex.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.tex.value(rec.u, rec.v, &rec.p)
+    }
+}
+
+/// Reflective surface, blurred by a fuzz factor
+pub struct Metal {
+    albedo: Color,
+    fuzz: f64,
+}
+
+impl Metal {
+    /// Creates a metal
+    ///
+    /// # Arguments
+    ///
+    /// * `albedo` - Color of the reflections
+    /// * `fuzz` - Radius of the sphere the reflected direction is randomized in, 0 is a mirror
+    pub fn new(albedo: Color, fuzz: f64) -> Metal {
+        Metal { albedo, fuzz }
+    }
+}
+
+impl Material for Metal {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
         let mut reflected = reflect(r_in.direction(), &rec.normal);
         reflected = unit_vector(&reflected) + (self.fuzz * random_unit_vector());
-        *scattered = ray_with_time(rec.p.clone(), reflected, r_in.time());
-        *attenuation = self.albedo.clone();
-        dot(scattered.direction(), &rec.normal) > 0.0
-    }
-
-    fn scatter_dielectic(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = Color::white();
+        srec.attenuation = self.albedo;
+        srec.pdf = None;
+        srec.skip_pdf_ray = ray_with_time(rec.p, reflected, r_in.time());
+        dot(srec.skip_pdf_ray.direction(), &rec.normal) > 0.0
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+/// Clear material that both reflects and refracts, like glass or water
+pub struct Dielectric {
+    // Refractive index in vacuum or air, or the ratio of the material's refractive index over
+    // the refractive index of the enclosing media
+    refraction_index: f64,
+}
+
+impl Dielectric {
+    pub fn new(refraction_index: f64) -> Dielectric {
+        Dielectric { refraction_index }
+    }
+
+    fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
+        // Use Schlick's approximation for reflectance.
+        let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
+        r0 = r0 * r0;
+        r0 + (1.0 - r0) * f64::powf(1.0 - cosine, 5.0)
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.attenuation = Color::white();
+        srec.pdf = None;
         let ri = if rec.front_face {
             1.0 / self.refraction_index
         } else {
@@ -190,48 +172,194 @@ impl Material {
         let sin_theta = f64::sqrt(1.0 - cos_theta * cos_theta);
 
         let cannot_refract = ri * sin_theta > 1.0;
-        let direction = if cannot_refract || self.reflectance(cos_theta, ri) > random_double() {
-            reflect(&unit_direction, &rec.normal)
-        } else {
-            refract(&unit_direction, &rec.normal, ri)
-        };
+        let direction =
+            if cannot_refract || Dielectric::reflectance(cos_theta, ri) > sample_1d(Dimension::Choice) {
+                reflect(&unit_direction, &rec.normal)
+            } else {
+                refract(&unit_direction, &rec.normal, ri)
+            };
 
-        *scattered = ray_with_time(rec.p.clone(), direction, r_in.time());
+        srec.skip_pdf_ray = ray_with_time(rec.p, direction, r_in.time());
         true
     }
 
-    fn scatter_isotropic(
-        &self,
-        r_in: &Ray,
-        rec: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        *scattered = ray_with_time(rec.p, random_unit_vector(), r_in.time());
-        *attenuation = self.tex.value(rec.u, rec.v, &rec.p);
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        // Glass passes all light on, denoisers expect white for it.
+        Color::white()
+    }
+}
+
+/// Surface emitting light, without scattering any
+pub struct DiffuseLight {
+    tex: Arc<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> DiffuseLight {
+        DiffuseLight::from_tex(Arc::new(SolidColor::from_color(emit)))
+    }
+
+    pub fn from_tex(tex: Arc<dyn Texture>) -> DiffuseLight {
+        DiffuseLight { tex }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Color {
+        self.tex.value(u, v, p)
+    }
+}
+
+/// Phase function scattering uniformly in all directions, used for participating media
+pub struct Isotropic {
+    tex: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Isotropic {
+        Isotropic::from_tex(Arc::new(SolidColor::from_color(albedo)))
+    }
+
+    pub fn from_tex(tex: Arc<dyn Texture>) -> Isotropic {
+        Isotropic { tex }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        srec.attenuation = self.tex.value(rec.u, rec.v, &rec.p);
+        srec.pdf = Some(Box::new(SpherePdf));
         true
     }
 
-    fn reflectance(&self, cosine: f64, refraction_index: f64) -> f64 {
-        // Use Schlick's approximation for reflectance.
-        let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
-        r0 = r0 * r0;
-        r0 + (1.0 - r0) * f64::powf(1.0 - cosine, 5.0)
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
     }
 
-    pub fn emmited(&self, u: f64, v: f64, p: &Vec3) -> Color {
-        match self.material {
-            Mat::Lambertian => Color::black(),
-            Mat::Metal => Color::black(),
-            Mat::Dielectric => Color::black(),
-            Mat::DiffuseLight => self.tex.value(u, v, p),
-            Mat::Isotropic => Color::black(),
-        }
+    fn bsdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> Color {
+        self.scattering_pdf(r_in, rec, scattered) * self.tex.value(rec.u, rec.v, &rec.p)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.tex.value(rec.u, rec.v, &rec.p)
     }
 }
 
-impl Default for Material {
-    fn default() -> Self {
-        Self::lambertian(Color::default())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ray::ray,
+        rtweekend::seed_random,
+        vec3::{random_unit_vector, vec3},
+    };
+
+    const SAMPLES: usize = 200_000;
+
+    /// Hit of a ray coming straight down onto a floor at the origin
+    fn floor_hit() -> (Ray, HitRecord) {
+        let r_in = ray(vec3(0.3, 1.0, 0.0), vec3(-0.3, -1.0, 0.0));
+        let mut rec = HitRecord::default();
+        rec.set_face_normal(&r_in, &vec3(0.0, 1.0, 0.0));
+        (r_in, rec)
+    }
+
+    fn scatter(material: &dyn Material, r_in: &Ray, rec: &HitRecord) -> ScatterRecord {
+        let mut srec = ScatterRecord::default();
+        assert!(material.scatter(r_in, rec, &mut srec));
+        srec
+    }
+
+    /// Checks that the density of the scattered directions integrates to 1 over all directions,
+    /// agrees with `scattering_pdf` and weights the BSDF to the attenuation.
+    fn assert_normalized(material: &dyn Material, albedo: Color) {
+        seed_random(4, 0);
+        let (r_in, rec) = floor_hit();
+        let srec = scatter(material, &r_in, &rec);
+        let pdf = srec.pdf.expect("material should scatter diffusely");
+        assert_eq!(srec.attenuation.g, albedo.g);
+
+        let mut integral = 0.0;
+        let mut reflected = 0.0;
+        for _ in 0..SAMPLES {
+            let uniform = random_unit_vector();
+            integral += pdf.value(&uniform) * 4.0 * PI;
+
+            let scattered = ray(rec.p, pdf.generate());
+            let density = pdf.value(scattered.direction());
+            let expected = material.scattering_pdf(&r_in, &rec, &scattered);
+            assert!((density - expected).abs() < 1e-9, "{density} {expected}");
+            reflected += material.bsdf(&r_in, &rec, &scattered).g / density;
+        }
+        let integral = integral / SAMPLES as f64;
+        assert!((integral - 1.0).abs() < 0.01, "{integral}");
+        let reflected = reflected / SAMPLES as f64;
+        assert!((reflected - albedo.g).abs() < 1e-9, "{reflected}");
+    }
+
+    /// Material outside the crate's own, which only overrides what it needs
+    struct Glow;
+
+    impl Material for Glow {
+        fn emitted(&self, u: f64, _v: f64, _p: &Vec3) -> Color {
+            Color::new(u, 0.0, 0.0)
+        }
+    }
+
+    #[test]
+    fn material_defaults_neither_scatter_nor_emit() {
+        let (r_in, mut rec) = floor_hit();
+        rec.mat = Arc::new(Glow);
+        let mut srec = ScatterRecord::default();
+        assert!(!rec.mat.scatter(&r_in, &rec, &mut srec));
+        assert_eq!(rec.mat.emitted(0.5, 0.0, &rec.p).r, 0.5);
+        assert_eq!(rec.mat.scattering_pdf(&r_in, &rec, &r_in), 0.0);
+        assert_eq!(rec.mat.bsdf(&r_in, &rec, &r_in).r, 0.0);
+        assert_eq!(rec.mat.albedo(&rec).r, 0.0);
+
+        // Only lights emit among the built-in materials.
+        let lambertian = Lambertian::new(Color::white());
+        assert_eq!(lambertian.emitted(0.5, 0.5, &rec.p).g, 0.0);
+        let light = DiffuseLight::new(Color::white());
+        assert!(!light.scatter(&r_in, &rec, &mut srec));
+        assert_eq!(light.emitted(0.5, 0.5, &rec.p).g, 1.0);
+    }
+
+    #[test]
+    fn lambertian_pdf_is_normalized() {
+        let albedo = Color::new(0.2, 0.6, 0.4);
+        assert_normalized(&Lambertian::new(albedo), albedo);
+
+        // Nothing is scattered below the surface.
+        let (r_in, rec) = floor_hit();
+        let below = ray(rec.p, vec3(0.2, -1.0, 0.0));
+        let density = Lambertian::new(albedo).scattering_pdf(&r_in, &rec, &below);
+        assert_eq!(density, 0.0);
+    }
+
+    #[test]
+    fn isotropic_pdf_is_normalized() {
+        let albedo = Color::new(0.7, 0.5, 0.1);
+        assert_normalized(&Isotropic::new(albedo), albedo);
+    }
+
+    #[test]
+    fn specular_materials_have_no_pdf() {
+        let (r_in, rec) = floor_hit();
+        let specular: [&dyn Material; 2] =
+            [&Metal::new(Color::white(), 0.0), &Dielectric::new(1.5)];
+        for material in specular {
+            let srec = scatter(material, &r_in, &rec);
+            assert!(srec.pdf.is_none());
+            let scattered = &srec.skip_pdf_ray;
+            assert_eq!(material.scattering_pdf(&r_in, &rec, scattered), 0.0);
+            assert_eq!(material.bsdf(&r_in, &rec, scattered).g, 0.0);
+        }
+
+        // A mirror reflects around the normal.
+        let srec = scatter(&Metal::new(Color::white(), 0.0), &r_in, &rec);
+        let direction = unit_vector(srec.skip_pdf_ray.direction());
+        let expected = unit_vector(&vec3(-0.3, 1.0, 0.0));
+        assert!((direction - expected).length() < 1e-12);
     }
 }
@@ -0,0 +1,705 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::SplitWhitespace,
+    sync::Arc,
+};
+
+use crate::{
+    bvh::SplitMethod,
+    color::Color,
+    hittable_list::HittableList,
+    material::{Dielectric, DiffuseLight, Lambertian, Material, Metal},
+    rtweekend::REFRACTION_GLASS,
+    texture::ImageTexture,
+    triangle::{TriangleMesh, Uv},
+    vec3::{vec3, Vec3},
+};
+
+/// Error loading a Wavefront OBJ file or one of its material libraries
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, io::Error),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(path, error) => write!(f, "{}: {error}", path.display()),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}, line {line}: {message}", path.display()),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io(_, error) => Some(error),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+/// Loads an OBJ file into one triangle mesh per group and material, using the materials from
+/// the referenced MTL libraries.
+pub fn load(path: impl AsRef<Path>) -> Result<HittableList, ObjError> {
+    load_with_split(path, None, SplitMethod::default())
+}
+
+/// Loads an OBJ file into one triangle mesh per group, using `mat` for all faces and ignoring
+/// any material libraries.
+pub fn load_with_material(
+    path: impl AsRef<Path>,
+    mat: Arc<dyn Material>,
+) -> Result<HittableList, ObjError> {
+    load_with_split(path, Some(mat), SplitMethod::default())
+}
+
+/// Loads an OBJ file like [`load`], or like [`load_with_material`] if `mat` is given, with
+/// `method` dividing the triangles of every mesh between the nodes of its hierarchy.
+pub fn load_with_split(
+    path: impl AsRef<Path>,
+    mat: Option<Arc<dyn Material>>,
+    method: SplitMethod,
+) -> Result<HittableList, ObjError> {
+    ObjParser::new(path.as_ref(), mat, method).parse()
+}
+
+/// Material used for faces without a `usemtl` statement
+fn default_material() -> Arc<dyn Material> {
+    Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73)))
+}
+
+/// Collects the faces of one group and material, merging identical corners into shared
+/// vertices.
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), u32>,
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Uv>,
+    triangles: Vec<[u32; 3]>,
+    all_have_normals: bool,
+    all_have_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn new() -> Self {
+        MeshBuilder {
+            all_have_normals: true,
+            all_have_uvs: true,
+            ..Default::default()
+        }
+    }
+
+    fn vertex(&mut self, corner: (usize, Option<usize>, Option<usize>), obj: &ObjParser) -> u32 {
+        if let Some(&index) = self.vertices.get(&corner) {
+            return index;
+        }
+
+        let (position, uv, normal) = corner;
+        let index = self.positions.len() as u32;
+        self.positions.push(obj.positions[position]);
+        self.uvs.push(uv.map_or((0.0, 0.0), |uv| obj.uvs[uv]));
+        self.normals
+            .push(normal.map_or(Vec3::default(), |normal| obj.normals[normal]));
+        self.all_have_uvs &= uv.is_some();
+        self.all_have_normals &= normal.is_some();
+        self.vertices.insert(corner, index);
+        index
+    }
+
+    fn build(mut self, mat: Arc<dyn Material>, method: SplitMethod) -> TriangleMesh {
+        // Attributes only make sense for the mesh if every vertex has them.
+        if !self.all_have_normals {
+            self.normals.clear();
+        }
+        if !self.all_have_uvs {
+            self.uvs.clear();
+        }
+        TriangleMesh::with_split(
+            self.positions,
+            self.normals,
+            self.uvs,
+            self.triangles,
+            mat,
+            method,
+        )
+    }
+}
+
+struct ObjParser<'a> {
+    path: &'a Path,
+    base_dir: &'a Path,
+    material_override: Option<Arc<dyn Material>>,
+    split_method: SplitMethod,
+
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Uv>,
+    materials: HashMap<String, Arc<dyn Material>>,
+
+    meshes: Vec<((String, String), MeshBuilder)>,
+    current_group: String,
+    current_material: String,
+    line: usize,
+}
+
+impl<'a> ObjParser<'a> {
+    fn new(
+        path: &'a Path,
+        material_override: Option<Arc<dyn Material>>,
+        split_method: SplitMethod,
+    ) -> Self {
+        ObjParser {
+            path,
+            base_dir: path.parent().unwrap_or(Path::new("")),
+            material_override,
+            split_method,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            materials: HashMap::new(),
+            meshes: Vec::new(),
+            current_group: String::new(),
+            current_material: String::new(),
+            line: 0,
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn parse(mut self) -> Result<HittableList, ObjError> {
+        let source =
+            fs::read_to_string(self.path).map_err(|error| ObjError::Io(self.path.into(), error))?;
+
+        for (index, line) in source.lines().enumerate() {
+            self.line = index + 1;
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+
+            match keyword {
+                "v" => {
+                    let position = self.vec3(&mut tokens)?;
+                    self.positions.push(position);
+                }
+                "vn" => {
+                    let normal = self.vec3(&mut tokens)?;
+                    self.normals.push(normal);
+                }
+                "vt" => {
+                    let u = self.number(tokens.next())?;
+                    let v = match tokens.next() {
+                        Some(token) => self.number(Some(token))?,
+                        None => 0.0,
+                    };
+                    self.uvs.push((u, v));
+                }
+                "f" => self.face(tokens)?,
+                "g" | "o" => self.current_group = tokens.collect::<Vec<_>>().join(" "),
+                "usemtl" => {
+                    let name = tokens.collect::<Vec<_>>().join(" ");
+                    if self.material_override.is_none() && !self.materials.contains_key(&name) {
+                        return Err(self.error(format!("unknown material `{name}`")));
+                    }
+                    self.current_material = name;
+                }
+                "mtllib" if self.material_override.is_none() => {
+                    for library in tokens {
+                        self.material_library(library)?;
+                    }
+                }
+                // Comments, smoothing groups, lines and points don't affect the meshes.
+                _ => {}
+            }
+        }
+
+        let mut list = HittableList::default();
+        for ((_, material), mesh) in std::mem::take(&mut self.meshes) {
+            let mat = match &self.material_override {
+                Some(mat) => mat.clone(),
+                None => self
+                    .materials
+                    .get(&material)
+                    .cloned()
+                    .unwrap_or_else(default_material),
+            };
+            list.add(Arc::new(mesh.build(mat, self.split_method)));
+        }
+        Ok(list)
+    }
+
+    fn number(&self, token: Option<&str>) -> Result<f64, ObjError> {
+        let Some(token) = token else {
+            return Err(self.error("missing number"));
+        };
+        match token.parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(number),
+            _ => Err(self.error(format!("invalid number `{token}`"))),
+        }
+    }
+
+    fn vec3(&self, tokens: &mut SplitWhitespace) -> Result<Vec3, ObjError> {
+        Ok(vec3(
+            self.number(tokens.next())?,
+            self.number(tokens.next())?,
+            self.number(tokens.next())?,
+        ))
+    }
+
+    /// Resolves a one-based, possibly negative (relative) OBJ index into a zero-based index.
+    fn index(&self, token: &str, count: usize, what: &str) -> Result<usize, ObjError> {
+        let index: i64 = token
+            .parse()
+            .map_err(|_| self.error(format!("invalid {what} index `{token}`")))?;
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("{what} index {index} out of range")));
+        }
+        Ok(resolved as usize)
+    }
+
+    /// Parses a face and adds it as a fan of triangles to the current mesh.
+    fn face(&mut self, tokens: SplitWhitespace) -> Result<(), ObjError> {
+        let mut corners = Vec::new();
+        for token in tokens {
+            let mut parts = token.split('/');
+            let position =
+                self.index(parts.next().unwrap_or(""), self.positions.len(), "vertex")?;
+            let uv = match parts.next() {
+                Some("") | None => None,
+                Some(uv) => Some(self.index(uv, self.uvs.len(), "texture coordinate")?),
+            };
+            let normal = match parts.next() {
+                Some("") | None => None,
+                Some(normal) => Some(self.index(normal, self.normals.len(), "normal")?),
+            };
+            if parts.next().is_some() {
+                return Err(self.error(format!("invalid face vertex `{token}`")));
+            }
+            corners.push((position, uv, normal));
+        }
+        if corners.len() < 3 {
+            return Err(self.error("face needs at least three vertices"));
+        }
+
+        let key = (self.current_group.clone(), self.current_material.clone());
+        let mesh_index = match self.meshes.iter().position(|(k, _)| *k == key) {
+            Some(index) => index,
+            None => {
+                self.meshes.push((key, MeshBuilder::new()));
+                self.meshes.len() - 1
+            }
+        };
+
+        let mut mesh = std::mem::take(&mut self.meshes[mesh_index].1);
+        let indices: Vec<u32> = corners
+            .into_iter()
+            .map(|corner| mesh.vertex(corner, self))
+            .collect();
+        for i in 1..indices.len() - 1 {
+            mesh.triangles
+                .push([indices[0], indices[i], indices[i + 1]]);
+        }
+        self.meshes[mesh_index].1 = mesh;
+
+        Ok(())
+    }
+
+    /// Loads the materials of an MTL library referenced by the OBJ file.
+    fn material_library(&mut self, library: &str) -> Result<(), ObjError> {
+        let path = self.base_dir.join(library);
+        let source =
+            fs::read_to_string(&path).map_err(|error| ObjError::Io(path.clone(), error))?;
+
+        let mut parser = MtlParser {
+            path: &path,
+            base_dir: self.base_dir,
+            line: 0,
+        };
+        let mut current: Option<(String, MtlMaterial)> = None;
+
+        for (index, line) in source.lines().enumerate() {
+            parser.line = index + 1;
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+
+            if keyword == "newmtl" {
+                if let Some((name, mtl)) = current.take() {
+                    self.materials.insert(name, mtl.to_material());
+                }
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                current = Some((name, MtlMaterial::default()));
+                continue;
+            }
+            if keyword.starts_with('#') {
+                continue;
+            }
+
+            let Some((_, mtl)) = current.as_mut() else {
+                return Err(parser.error(format!("`{keyword}` before `newmtl`")));
+            };
+            match keyword {
+                "Kd" => mtl.diffuse = parser.color(&mut tokens)?,
+                "Ks" => mtl.specular = parser.color(&mut tokens)?,
+                "Ke" => mtl.emission = parser.color(&mut tokens)?,
+                "Ns" => mtl.shininess = parser.number(tokens.next())?,
+                "Ni" => mtl.refraction_index = parser.number(tokens.next())?,
+                "d" => mtl.dissolve = parser.number(tokens.next())?,
+                "Tr" => mtl.dissolve = 1.0 - parser.number(tokens.next())?,
+                "map_Kd" => {
+                    // Options like `-s 1 1 1` may precede the file name, which comes last.
+                    let Some(file) = tokens.last() else {
+                        return Err(parser.error("missing texture file name"));
+                    };
+                    let texture_path = parser.base_dir.join(file);
+                    let texture = ImageTexture::open(&texture_path).map_err(|error| {
+                        parser.error(format!("can't load texture `{file}`: {error}"))
+                    })?;
+                    mtl.diffuse_texture = Some(Arc::new(texture));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some((name, mtl)) = current {
+            self.materials.insert(name, mtl.to_material());
+        }
+        Ok(())
+    }
+}
+
+struct MtlParser<'a> {
+    path: &'a Path,
+    base_dir: &'a Path,
+    line: usize,
+}
+
+impl MtlParser<'_> {
+    fn error(&self, message: impl Into<String>) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn number(&self, token: Option<&str>) -> Result<f64, ObjError> {
+        let Some(token) = token else {
+            return Err(self.error("missing number"));
+        };
+        match token.parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(number),
+            _ => Err(self.error(format!("invalid number `{token}`"))),
+        }
+    }
+
+    fn color(&self, tokens: &mut SplitWhitespace) -> Result<Color, ObjError> {
+        let r = self.number(tokens.next())?;
+        // A single value is a grey level.
+        let (g, b) = match tokens.next() {
+            Some(g) => (self.number(Some(g))?, self.number(tokens.next())?),
+            None => (r, r),
+        };
+        Ok(Color::new(r, g, b))
+    }
+}
+
+/// Material statements of an MTL library entry
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    emission: Color,
+    shininess: f64,
+    refraction_index: f64,
+    dissolve: f64,
+    diffuse_texture: Option<Arc<ImageTexture>>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::black(),
+            emission: Color::black(),
+            shininess: 0.0,
+            refraction_index: REFRACTION_GLASS,
+            dissolve: 1.0,
+            diffuse_texture: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    /// Maps the Phong style parameters onto the closest of the available materials.
+    fn to_material(&self) -> Arc<dyn Material> {
+        if max_component(self.emission) > 0.0 {
+            return Arc::new(DiffuseLight::new(self.emission));
+        }
+        if self.dissolve < 1.0 {
+            return Arc::new(Dielectric::new(self.refraction_index));
+        }
+        if self.diffuse_texture.is_none()
+            && max_component(self.specular) > max_component(self.diffuse)
+        {
+            // Convert the Phong exponent into a fuzz radius, sharp highlights mean little fuzz.
+            let fuzz = f64::sqrt(2.0 / (self.shininess.max(0.0) + 2.0));
+            return Arc::new(Metal::new(self.specular, fuzz));
+        }
+        match &self.diffuse_texture {
+            Some(texture) => Arc::new(Lambertian::from_tex(texture.clone())),
+            None => Arc::new(Lambertian::new(self.diffuse)),
+        }
+    }
+}
+
+fn max_component(color: Color) -> f64 {
+    f64::max(color.r, f64::max(color.g, color.b))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::{
+        interval::Interval,
+        material::ScatterRecord,
+        ray::ray,
+        sphere::{HitRecord, Hittable},
+    };
+
+    /// Writes files into a fresh temporary directory and returns it.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("obj-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn load_source(name: &str, source: &str) -> Result<HittableList, ObjError> {
+        let dir = write_files(name, &[("mesh.obj", source)]);
+        let result = load(dir.join("mesh.obj"));
+        fs::remove_dir_all(dir).unwrap();
+        result
+    }
+
+    /// Checks that loading fails at `line` with a message containing `message`.
+    fn assert_parse_error(name: &str, source: &str, line: usize, message: &str) {
+        match load_source(name, source) {
+            Err(ObjError::Parse {
+                line: error_line,
+                message: error_message,
+                ..
+            }) => {
+                assert_eq!(error_line, line, "{source:?}: {error_message}");
+                assert!(
+                    error_message.contains(message),
+                    "{source:?}: {error_message}"
+                );
+            }
+            Err(error) => panic!("{source:?}: unexpected error {error}"),
+            Ok(_) => panic!("{source:?} loaded without an error"),
+        }
+    }
+
+    /// Hits the object with a ray coming down the z axis through `x`, `y`.
+    fn hit(object: &dyn Hittable, x: f64, y: f64) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        let r = ray(vec3(x, y, 1.0), vec3(0.0, 0.0, -1.0));
+        object
+            .hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec)
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn loads_faces_with_relative_indices_and_polygons() {
+        let source = format!(
+            "{SQUARE}vt 0 0\nvt 1 0\nvt 1 1\nvn 0 0 1\nf -4/-3/-1 -3/-2/-1 -2/-1/-1 -1/-1/-1\n"
+        );
+        let list = load_source("relative", &source).unwrap();
+        assert_eq!(list.objects.len(), 1);
+        let mesh = (&*list.objects[0] as &dyn std::any::Any)
+            .downcast_ref::<TriangleMesh>()
+            .unwrap();
+        // The quad is split into a fan of two triangles.
+        assert_eq!(mesh.triangle_count(), 2);
+        assert!(hit(mesh, 0.9, 0.1).is_some() && hit(mesh, 0.1, 0.9).is_some());
+        assert!(hit(mesh, 1.1, 0.5).is_none());
+    }
+
+    #[test]
+    fn groups_and_materials_get_separate_meshes() {
+        let source = format!("{SQUARE}g a\nf 1 2 3\ng b\nf 1 3 4\nf 1 2 4\ng a\nf 2 3 4\n");
+        let list = load_source("groups", &source).unwrap();
+        assert_eq!(list.objects.len(), 2);
+    }
+
+    #[test]
+    fn bad_indices_are_errors() {
+        let cases = [
+            ("f 1 2 5", "vertex index 5 out of range"),
+            ("f 0 1 2", "vertex index 0 out of range"),
+            ("f -5 1 2", "vertex index -5 out of range"),
+            ("f 1 2 x", "invalid vertex index `x`"),
+            ("f 1/1 2/1 3/1", "texture coordinate index 1 out of range"),
+            ("f 1//1 2//1 3//1", "normal index 1 out of range"),
+            ("f 1/// 2 3", "invalid face vertex `1///`"),
+            ("f 1 2", "face needs at least three vertices"),
+            ("f", "face needs at least three vertices"),
+        ];
+        for (face, message) in cases {
+            assert_parse_error("indices", &format!("{SQUARE}{face}\n"), 5, message);
+        }
+    }
+
+    #[test]
+    fn malformed_vertex_lines_are_errors() {
+        let cases = [
+            ("v 1 2", "missing number"),
+            ("v 1 x 2", "invalid number `x`"),
+            ("v nan 0 0", "invalid number `nan`"),
+            ("v 0 1e999 0", "invalid number `1e999`"),
+            ("vn 0 0", "missing number"),
+            ("vn 0 nope 1", "invalid number `nope`"),
+            ("vt", "missing number"),
+            ("vt 0.5 half", "invalid number `half`"),
+        ];
+        for (line, message) in cases {
+            assert_parse_error("vertices", &format!("# comment\n{line}\n"), 2, message);
+        }
+    }
+
+    #[test]
+    fn missing_material_libraries_and_materials_are_errors() {
+        let source = format!("mtllib missing.mtl\n{SQUARE}f 1 2 3\n");
+        match load_source("missing-mtl", &source) {
+            Err(ObjError::Io(path, _)) => assert!(path.ends_with("missing.mtl")),
+            _ => panic!("missing material library loaded"),
+        }
+
+        assert_parse_error(
+            "usemtl",
+            "usemtl nothing\n",
+            1,
+            "unknown material `nothing`",
+        );
+
+        // An explicit material ignores the libraries.
+        let dir = write_files("override", &[("mesh.obj", &source)]);
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.1, 0.2, 0.3)));
+        let list = load_with_material(dir.join("mesh.obj"), mat).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        assert_eq!(list.objects.len(), 1);
+    }
+
+    #[test]
+    fn texture_paths_are_relative_to_the_obj_directory() {
+        let dir = write_files(
+            "textures",
+            &[
+                (
+                    "models/mesh.obj",
+                    &format!("mtllib looks/mesh.mtl\n{SQUARE}usemtl red\nf 1 2 3\n"),
+                ),
+                (
+                    "models/looks/mesh.mtl",
+                    "newmtl red\nmap_Kd -s 1 1 1 maps/red.png\n",
+                ),
+            ],
+        );
+        let texture_path = dir.join("models/maps/red.png");
+        fs::create_dir_all(texture_path.parent().unwrap()).unwrap();
+        image::RgbImage::from_pixel(2, 2, image::Rgb([255, 0, 0]))
+            .save(&texture_path)
+            .unwrap();
+
+        let list = load(dir.join("models/mesh.obj"));
+        let missing_texture = {
+            fs::remove_file(&texture_path).unwrap();
+            load(dir.join("models/mesh.obj"))
+        };
+        fs::remove_dir_all(dir).unwrap();
+
+        let rec = hit(&list.unwrap(), 0.8, 0.1).unwrap();
+        let mut srec = ScatterRecord::default();
+        let r_in = ray(vec3(0.8, 0.1, 1.0), vec3(0.0, 0.0, -1.0));
+        assert!(rec.mat.scatter(&r_in, &rec, &mut srec));
+        assert!(srec.attenuation.r > 0.9 && srec.attenuation.g < 0.1);
+
+        match missing_texture {
+            Err(ObjError::Parse {
+                path,
+                line,
+                message,
+            }) => {
+                assert!(path.ends_with("mesh.mtl"));
+                assert_eq!(line, 2);
+                assert!(
+                    message.contains("can't load texture `maps/red.png`"),
+                    "{message}"
+                );
+            }
+            _ => panic!("missing texture loaded"),
+        }
+    }
+
+    #[test]
+    fn mtl_statements_pick_the_closest_material() {
+        let dir = write_files(
+            "mtl",
+            &[
+                (
+                    "mesh.obj",
+                    &format!(
+                        "mtllib mesh.mtl\n{SQUARE}usemtl lamp\nf 1 2 3\nusemtl glass\nf 1 3 4\n"
+                    ),
+                ),
+                (
+                    "mesh.mtl",
+                    "newmtl lamp\nKe 2 2 2\n\nnewmtl glass\nd 0.5\nNi 1.3\n",
+                ),
+            ],
+        );
+        let list = load(dir.join("mesh.obj")).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        let lamp = hit(&list, 0.8, 0.1).unwrap();
+        assert_eq!(lamp.mat.emitted(lamp.u, lamp.v, &lamp.p).g, 2.0);
+        let glass = hit(&list, 0.1, 0.8).unwrap();
+        let mut srec = ScatterRecord::default();
+        let r_in = ray(vec3(0.1, 0.8, 1.0), vec3(0.0, 0.0, -1.0));
+        assert!(glass.mat.scatter(&r_in, &glass, &mut srec) && srec.pdf.is_none());
+    }
+}
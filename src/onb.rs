@@ -0,0 +1,36 @@
+use crate::vec3::{cross, unit_vector, vec3, Vec3};
+
+/// Orthonormal basis built around a given direction
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    /// Creates a basis whose `w` axis points along `n`
+    ///
+    /// # Arguments
+    ///
+    /// * `n` - Direction of the third basis vector, doesn't need to be normalized
+    pub fn new(n: &Vec3) -> Onb {
+        let w = unit_vector(n);
+        // Pick any vector that isn't parallel to w to build the other two axes from.
+        let a = if f64::abs(w.x) > 0.9 {
+            vec3(0.0, 1.0, 0.0)
+        } else {
+            vec3(1.0, 0.0, 0.0)
+        };
+        let v = unit_vector(&cross(&w, &a));
+        let u = cross(&w, &v);
+
+        Onb { axis: [u, v, w] }
+    }
+
+    pub fn w(&self) -> &Vec3 {
+        &self.axis[2]
+    }
+
+    /// Transforms a vector from basis coordinates to world coordinates.
+    pub fn transform(&self, v: &Vec3) -> Vec3 {
+        (v.x * self.axis[0]) + (v.y * self.axis[1]) + (v.z * self.axis[2])
+    }
+}
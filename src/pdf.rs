@@ -0,0 +1,162 @@
+use std::f64::consts::PI;
+
+use crate::{
+    onb::Onb,
+    sphere::Hittable,
+    vec3::{dot, random_cosine_direction, random_unit_vector, unit_vector, Vec3},
+};
+
+/// Probability distribution of directions, used to importance sample scattering and lights
+pub trait Pdf {
+    /// Probability density, with respect to solid angle, of generating `direction`.
+    fn value(&self, direction: &Vec3) -> f64;
+
+    /// Returns a random direction distributed according to the density.
+    fn generate(&self) -> Vec3;
+}
+
+/// Uniform distribution over all directions
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self) -> Vec3 {
+        random_unit_vector()
+    }
+}
+
+/// Cosine weighted distribution over the hemisphere around a normal
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    /// Creates a distribution over the hemisphere around `w`
+    ///
+    /// # Arguments
+    ///
+    /// * `w` - Surface normal the hemisphere is centered on
+    pub fn new(w: &Vec3) -> CosinePdf {
+        CosinePdf { uvw: Onb::new(w) }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine_theta = dot(&unit_vector(direction), self.uvw.w());
+        f64::max(0.0, cosine_theta / PI)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.uvw.transform(&random_cosine_direction())
+    }
+}
+
+/// Distribution of directions from a point towards a set of objects
+pub struct HittablePdf<'a> {
+    objects: &'a dyn Hittable,
+    origin: Vec3,
+}
+
+impl<'a> HittablePdf<'a> {
+    /// Creates a distribution of directions towards `objects`
+    ///
+    /// # Arguments
+    ///
+    /// * `objects` - Objects to sample, usually the light sources of a scene
+    /// * `origin` - Point the directions start from
+    pub fn new(objects: &'a dyn Hittable, origin: Vec3) -> HittablePdf<'a> {
+        HittablePdf { objects, origin }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.objects.pdf_value(&self.origin, direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.objects.random(&self.origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        color::Color, material::DiffuseLight, quad::Quad, rtweekend::seed_random, sphere::Sphere,
+        vec3::vec3,
+    };
+
+    const SAMPLES: usize = 200_000;
+
+    fn light() -> Arc<DiffuseLight> {
+        Arc::new(DiffuseLight::new(Color::white()))
+    }
+
+    /// Monte Carlo estimate of the integral of the density over all directions
+    fn integral(pdf: &dyn Pdf) -> f64 {
+        let sum: f64 = (0..SAMPLES).map(|_| pdf.value(&SpherePdf.generate())).sum();
+        sum / SAMPLES as f64 / SpherePdf.value(&vec3(0.0, 0.0, 1.0))
+    }
+
+    /// Monte Carlo estimate of the solid angle covered by the generated directions
+    fn solid_angle(pdf: &dyn Pdf) -> f64 {
+        let sum: f64 = (0..SAMPLES)
+            .map(|_| {
+                let density = pdf.value(&pdf.generate());
+                assert!(density > 0.0, "generated direction misses");
+                1.0 / density
+            })
+            .sum();
+        sum / SAMPLES as f64
+    }
+
+    #[test]
+    fn hittable_pdf_towards_a_quad() {
+        seed_random(1, 0);
+        // One face of a cube around the origin, which covers a sixth of all directions.
+        let quad = Quad::new(
+            vec3(-1.0, 1.0, -1.0),
+            vec3(2.0, 0.0, 0.0),
+            vec3(0.0, 0.0, 2.0),
+            light(),
+        );
+        let pdf = HittablePdf::new(&quad, vec3(0.0, 0.0, 0.0));
+
+        // Straight up the density is distance² / (cosine · area) = 1 / 4.
+        assert!((pdf.value(&vec3(0.0, 3.0, 0.0)) - 0.25).abs() < 1e-12);
+        let slanted = f64::powf(1.5, 1.5) / 4.0;
+        assert!((pdf.value(&vec3(0.5, 1.0, 0.5)) - slanted).abs() < 1e-12);
+        assert_eq!(pdf.value(&vec3(0.0, -1.0, 0.0)), 0.0);
+        assert_eq!(pdf.value(&vec3(1.5, 1.0, 0.0)), 0.0);
+
+        assert!((integral(&pdf) - 1.0).abs() < 0.01);
+        assert!((solid_angle(&pdf) - 4.0 * PI / 6.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn hittable_pdf_towards_a_sphere() {
+        seed_random(2, 0);
+        // Seen from twice its radius, the sphere fills a cone of 30 degrees.
+        let sphere = Sphere::new(vec3(0.0, 0.0, -2.0), 1.0, light());
+        let pdf = HittablePdf::new(&sphere, vec3(0.0, 0.0, 0.0));
+        let expected = 2.0 * PI * (1.0 - f64::sqrt(0.75));
+
+        assert!((pdf.value(&vec3(0.0, 0.0, -1.0)) - 1.0 / expected).abs() < 1e-12);
+        assert_eq!(pdf.value(&vec3(0.0, 0.0, 1.0)), 0.0);
+        assert_eq!(pdf.value(&vec3(0.0, 1.0, -1.0)), 0.0);
+
+        assert!((integral(&pdf) - 1.0).abs() < 0.01);
+        assert!((solid_angle(&pdf) - expected).abs() < 1e-9);
+
+        // Points inside the sphere can't sample it.
+        let inside = HittablePdf::new(&sphere, vec3(0.0, 0.0, -2.5));
+        assert_eq!(inside.value(&vec3(0.0, 1.0, 0.0)), 0.0);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
-    rtweekend::random_int_from,
-    vec3::{dot, random_from, unit_vector, vec3, Vec3},
+    rtweekend::Pcg32,
+    vec3::{dot, unit_vector, vec3, Vec3},
 };
 
 pub struct Perlin {
@@ -11,18 +11,27 @@ pub struct Perlin {
 }
 
 impl Perlin {
-    pub fn new() -> Self {
+    /// Creates the noise tables from their own random sequence, so the noise only depends on
+    /// `seed`.
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Pcg32::new(seed);
+
         let point_count: i32 = 256;
         let mut randvec = Vec::with_capacity(256);
         for _i in 0..point_count {
-            randvec.push(unit_vector(&random_from(-1.0, 1.0)));
+            let v = vec3(
+                2.0 * rng.next_f64() - 1.0,
+                2.0 * rng.next_f64() - 1.0,
+                2.0 * rng.next_f64() - 1.0,
+            );
+            randvec.push(unit_vector(&v));
         }
 
         Perlin {
             randvec,
-            perm_x: Perlin::generate_perm(),
-            perm_y: Perlin::generate_perm(),
-            perm_z: Perlin::generate_perm(),
+            perm_x: Perlin::generate_perm(&mut rng),
+            perm_y: Perlin::generate_perm(&mut rng),
+            perm_z: Perlin::generate_perm(&mut rng),
         }
     }
 
@@ -66,21 +75,19 @@ impl Perlin {
         f64::abs(accum)
     }
 
-    fn generate_perm() -> Vec<i32> {
+    fn generate_perm(rng: &mut Pcg32) -> Vec<i32> {
         let mut p = Vec::with_capacity(256);
         for i in 0..256 {
             p.push(i);
         }
-        Perlin::permute(&mut p, 256);
+        Perlin::permute(&mut p, 256, rng);
         p
     }
 
-    fn permute(p: &mut Vec<i32>, n: i32) {
+    fn permute(p: &mut [i32], n: i32, rng: &mut Pcg32) {
         for i in 0..n - 1 {
-            let target = random_int_from(0, i);
-            let tmp = p[i as usize];
-            p[i as usize] = p[target as usize];
-            p[target as usize] = tmp;
+            let target = (rng.next_f64() * (i + 1) as f64) as i32;
+            p.swap(i as usize, target as usize);
         }
     }
 
@@ -89,14 +96,15 @@ impl Perlin {
         let vv = v * v * (3.0 - 2.0 * v);
         let ww = w * w * (3.0 - 2.0 * w);
         let mut accum = 0.0;
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let weight = vec3(u - i as f64, v - j as f64, w - k as f64);
-                    accum += (i as f64 * uu + (1 - i) as f64 * (1.0 - uu))
-                        * (j as f64 * vv + (1 - j) as f64 * (1.0 - vv))
-                        * (k as f64 * ww + (1 - k) as f64 * (1.0 - ww))
-                        * dot(&c[i][j][k], &weight);
+        for (i, ci) in c.iter().enumerate() {
+            for (j, cij) in ci.iter().enumerate() {
+                for (k, cijk) in cij.iter().enumerate() {
+                    let (i, j, k) = (i as f64, j as f64, k as f64);
+                    let weight = vec3(u - i, v - j, w - k);
+                    accum += (i * uu + (1.0 - i) * (1.0 - uu))
+                        * (j * vv + (1.0 - j) * (1.0 - vv))
+                        * (k * ww + (1.0 - k) * (1.0 - ww))
+                        * dot(cijk, &weight);
                 }
             }
         }
@@ -1,11 +1,14 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::{
     aabb::Aabb,
     hittable_list::HittableList,
     interval::Interval,
     material::Material,
-    sphere::{HitRecord, Hittable},
+    ray::{ray, Ray},
+    sampler::{sample_2d, Dimension},
+    sphere::{debug_assert_hit_in_bounds, HitRecord, Hittable},
+    stats::{self, Counter},
     vec3::{cross, dot, unit_vector, vec3, Vec3},
 };
 
@@ -15,10 +18,11 @@ pub struct Quad {
     u: Vec3,
     v: Vec3,
     w: Vec3,
-    mat: Material,
+    mat: Arc<dyn Material>,
     bbox: Aabb,
     normal: Vec3,
     d: f64,
+    area: f64,
 }
 impl Quad {
     /// Creates a new quadrilateral
@@ -29,23 +33,36 @@ impl Quad {
     /// * `u` - Vector along first edge
     /// * `v` - Vector along second edge
     /// * `mat` - Material of quadrilateral
-    pub fn new(q: Vec3, u: Vec3, v: Vec3, mat: Material) -> Self {
+    ///
+    /// Parallel edges make a quadrilateral without area, which is never hit.
+    pub fn new(q: Vec3, u: Vec3, v: Vec3, mat: Arc<dyn Material>) -> Self {
         let n = cross(&u, &v);
-        let normal = unit_vector(&n);
+        let degenerate = dot(&n, &n) == 0.0;
+        // A zero normal makes every ray parallel to the plane, instead of dividing by zero.
+        let normal = if degenerate {
+            Vec3::default()
+        } else {
+            unit_vector(&n)
+        };
         let mut quad = Quad {
             q,
             u,
             v,
-            w: n / dot(&n, &n),
+            w: if degenerate { n } else { n / dot(&n, &n) },
             mat,
             bbox: Default::default(),
             normal,
             d: dot(&normal, &q),
+            area: n.length(),
         };
         quad.set_bounding_box();
         quad
     }
 
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.mat
+    }
+
     /// Compute the bounding box of all four vertices.
     pub fn set_bounding_box(&mut self) {       
         let bbox_diagonal1 = Aabb::from_point(&self.q, &(self.q + self.u + self.v));
@@ -72,15 +89,10 @@ impl Quad {
         rec.v = b;
         true
     }
-}
 
-impl Hittable for Quad {
-    fn hit(
-        &self,
-        r: &crate::ray::Ray,
-        ray_t: crate::interval::Interval,
-        rec: &mut crate::sphere::HitRecord,
-    ) -> bool {
+    /// Intersects the quad like [`Hittable::hit`], without counting it as a primitive test of
+    /// the render.
+    fn intersect(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         let denom = dot(&self.normal, r.direction());
 
         // No hit if the ray is parallel to the plane.
@@ -109,16 +121,51 @@ impl Hittable for Quad {
         rec.p = intersection;
         rec.mat = self.mat.clone();
         rec.set_face_normal(r, &self.normal);
+        debug_assert_hit_in_bounds(self, rec);
 
         true
     }
+}
+
+impl Hittable for Quad {
+    fn hit(
+        &self,
+        r: &crate::ray::Ray,
+        ray_t: crate::interval::Interval,
+        rec: &mut crate::sphere::HitRecord,
+    ) -> bool {
+        stats::count(Counter::PrimitiveTests, 1);
+        self.intersect(r, ray_t, rec)
+    }
 
     fn bounding_box(&self) -> &crate::aabb::Aabb {
         &self.bbox
     }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        let mut rec = HitRecord::default();
+        if !self.intersect(&ray(*origin, *direction), Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return 0.0;
+        }
+
+        // Convert the uniform density over the area into a density over solid angle.
+        let distance_squared = rec.t * rec.t * direction.length_squared();
+        let cosine = f64::abs(dot(direction, &rec.normal) / direction.length());
+        if cosine * self.area == 0.0 {
+            return 0.0;
+        }
+
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        let (a, b) = sample_2d(Dimension::Light);
+        let p = self.q + (a * self.u) + (b * self.v);
+        p - *origin
+    }
 }
 
-pub fn bx(a: &Vec3, b: &Vec3, mat: Material) -> Rc<HittableList> {
+pub fn bx(a: &Vec3, b: &Vec3, mat: Arc<dyn Material>) -> Arc<HittableList> {
     // Returns the 3D box (six sides) that contains the two opposite vertices a & b.
     let mut sides = HittableList::default();
 
@@ -130,37 +177,63 @@ pub fn bx(a: &Vec3, b: &Vec3, mat: Material) -> Rc<HittableList> {
     let dy = vec3(0.0, max.y - min.y, 0.0);
     let dz = vec3(0.0, 0.0, max.z - min.z);
 
-    sides.add(Rc::new(Quad::new(
+    sides.add(Arc::new(Quad::new(
         vec3(min.x, min.y, max.z),
         dx,
         dy,
         mat.clone(),
     ))); // front
-    sides.add(Rc::new(Quad::new(
+    sides.add(Arc::new(Quad::new(
         vec3(max.x, min.y, max.z),
         -dz,
         dy,
         mat.clone(),
     ))); // right
-    sides.add(Rc::new(Quad::new(
+    sides.add(Arc::new(Quad::new(
         vec3(max.x, min.y, min.z),
         -dx,
         dy,
         mat.clone(),
     ))); // back
-    sides.add(Rc::new(Quad::new(
+    sides.add(Arc::new(Quad::new(
         vec3(min.x, min.y, min.z),
         dz,
         dy,
         mat.clone(),
     ))); // left
-    sides.add(Rc::new(Quad::new(
+    sides.add(Arc::new(Quad::new(
         vec3(min.x, max.y, max.z),
         dx,
         -dz,
         mat.clone(),
     ))); // top
-    sides.add(Rc::new(Quad::new(vec3(min.x, min.y, min.z), dx, dz, mat))); // bottom
+    sides.add(Arc::new(Quad::new(vec3(min.x, min.y, min.z), dx, dz, mat))); // bottom
+
+    Arc::new(sides)
+}
 
-    Rc::new(sides)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::DiffuseLight, stats::RenderStats};
+
+    #[test]
+    fn only_hits_count_as_primitive_tests() {
+        let light = Arc::new(DiffuseLight::new(Color::white()));
+        let u = vec3(2.0, 0.0, 0.0);
+        let quad = Quad::new(vec3(-1.0, 1.0, -1.0), u, vec3(0.0, 0.0, 2.0), light);
+        let mut stats = RenderStats::default();
+        stats.take_thread_counts();
+
+        // Sampling the quad as a light intersects it without counting.
+        assert!(quad.pdf_value(&Vec3::default(), &vec3(0.0, 1.0, 0.0)) > 0.0);
+        stats.take_thread_counts();
+        assert_eq!(stats.primitive_tests, 0);
+
+        let mut rec = HitRecord::default();
+        let r = ray(Vec3::default(), vec3(0.0, 1.0, 0.0));
+        assert!(quad.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        stats.take_thread_counts();
+        assert_eq!(stats.primitive_tests, 1);
+    }
 }
@@ -29,6 +29,6 @@ impl Ray {
     }
 
     pub fn at(&self, t: f64) -> Vec3 {
-        self.orig.clone() + self.dir.clone() * t
+        self.orig + self.dir * t
     }
 }
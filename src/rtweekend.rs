@@ -1,7 +1,6 @@
-use rand::prelude::*;
+use std::{cell::Cell, f64::consts::PI};
 
 // Constants
-const PI: f64 = 3.1415926535897932385;
 pub const REFRACTION_GLASS: f64 = 1.50;
 pub const REFRACTION_WATER: f64 = 1.33;
 pub const REFRACTION_AIR: f64 = 1.00;
@@ -11,9 +10,73 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+/// Small, fast PCG32 random number generator whose output only depends on its seed
+#[derive(Clone, Copy)]
+pub struct Pcg32 {
+    state: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6364136223846793005;
+    const INCREMENT: u64 = 1442695040888963407;
+
+    pub fn new(seed: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old_state = self.state;
+        self.state = old_state
+            .wrapping_mul(Pcg32::MULTIPLIER)
+            .wrapping_add(Pcg32::INCREMENT);
+
+        // Output function (XSH RR): xorshift the high bits and apply a random rotation.
+        let xorshifted = (((old_state >> 18) ^ old_state) >> 27) as u32;
+        let rot = (old_state >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /// Returns a random real in [0,1) with 53 bits of precision.
+    pub fn next_f64(&mut self) -> f64 {
+        let bits = ((self.next_u32() as u64) << 21) ^ (self.next_u32() as u64 >> 11);
+        bits as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+}
+
+thread_local! {
+    static RNG: Cell<Pcg32> = Cell::new(Pcg32::new(0));
+}
+
+/// Scrambles the bits of `x`, so that nearby inputs give unrelated outputs (SplitMix64 finalizer).
+pub(crate) fn hash_u64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Reseeds the random number generator of the current thread. Every combination of `seed` and
+/// `sequence` starts an independent sequence of random numbers.
+///
+/// # Arguments
+///
+/// * `seed` - Seed of the whole render
+/// * `sequence` - Identifies the sequence within the render, like a pixel sample
+pub fn seed_random(seed: u64, sequence: u64) {
+    RNG.with(|rng| rng.set(Pcg32::new(hash_u64(seed ^ hash_u64(sequence)))));
+}
+
 pub fn random_double() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen()
+    RNG.with(|rng| {
+        let mut state = rng.get();
+        let value = state.next_f64();
+        rng.set(state);
+        value
+    })
 }
 
 pub fn random_double_from(min: f64, max: f64) -> f64 {
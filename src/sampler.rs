@@ -0,0 +1,552 @@
+use std::{
+    cell::{Cell, RefCell},
+    sync::{Arc, LazyLock},
+};
+
+use crate::rtweekend::{hash_u64, random_double};
+
+/// Largest value below 1 representable as an `f64`
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// Identifies a sample of a pixel. Samplers place the sample from this and the dimension alone, so
+/// a sample is the same no matter which thread takes it when.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PixelSample {
+    /// Seed of the render
+    pub seed: u64,
+    /// Index of the pixel in the image
+    pub pixel: u64,
+    /// Index of the sample within the pixel
+    pub index: u32,
+    /// Number of samples the pixel is going to take, which stratified samplers divide the
+    /// dimensions into
+    pub samples_per_pixel: u32,
+}
+
+/// Decides where the samples of a pixel lie in every dimension of the paths traced for it, like
+/// the position within the pixel, on the lens and the directions of the bounces.
+///
+/// Every use of random numbers along a path has dimensions of its own, see [`Dimension`],
+/// [`sample_1d`] and [`sample_2d`].
+pub trait Sampler: Send + Sync {
+    /// Name the sampler is selected by
+    fn name(&self) -> &'static str;
+
+    /// Whether the samples depend on [`PixelSample::samples_per_pixel`], so that the samples of
+    /// a pixel change with the number of samples it is going to take.
+    fn depends_on_sample_count(&self) -> bool {
+        false
+    }
+
+    /// Returns the coordinate of a sample in one dimension, in [0, 1).
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64;
+
+    /// Returns the coordinates of a sample in two dimensions that are used together, like the
+    /// position within the pixel, in [0, 1) each.
+    fn get_2d(&self, sample: &PixelSample, dimension: u32) -> (f64, f64);
+}
+
+/// Independent uniform random numbers for every dimension
+pub struct IndependentSampler;
+
+impl Sampler for IndependentSampler {
+    fn name(&self) -> &'static str {
+        "independent"
+    }
+
+    fn get_1d(&self, _sample: &PixelSample, _dimension: u32) -> f64 {
+        // The camera seeds the random numbers for every sample already.
+        random_double()
+    }
+
+    fn get_2d(&self, _sample: &PixelSample, _dimension: u32) -> (f64, f64) {
+        (random_double(), random_double())
+    }
+}
+
+/// Jittered samples in strata, a grid of sqrt(spp) x sqrt(spp) cells for two dimensions and spp
+/// intervals for one. Every dimension visits the strata in its own random order, and pixels
+/// taking more samples than there are strata start over with a new order.
+pub struct StratifiedSampler;
+
+impl Sampler for StratifiedSampler {
+    fn name(&self) -> &'static str {
+        "stratified"
+    }
+
+    fn depends_on_sample_count(&self) -> bool {
+        true
+    }
+
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64 {
+        let strata = sample.samples_per_pixel.max(1);
+        let (round, index) = (sample.index / strata, sample.index % strata);
+        let hash = sample_hash(sample, dimension, round as u64);
+        let stratum = permute(index, strata, hash as u32);
+        let jitter = hash_to_unit(hash_u64(hash ^ hash_u64(index as u64)));
+        (stratum as f64 + jitter) / strata as f64
+    }
+
+    fn get_2d(&self, sample: &PixelSample, dimension: u32) -> (f64, f64) {
+        let size = f64::sqrt(sample.samples_per_pixel.max(1) as f64) as u32;
+        let strata = size * size;
+        let (round, index) = (sample.index / strata, sample.index % strata);
+        let hash = sample_hash(sample, dimension, round as u64);
+        let stratum = permute(index, strata, hash as u32);
+        let jitter = hash_u64(hash ^ hash_u64(index as u64));
+        let (jitter_x, jitter_y) = (hash_to_unit(jitter), hash_to_unit(hash_u64(jitter)));
+        (
+            ((stratum % size) as f64 + jitter_x) / size as f64,
+            ((stratum / size) as f64 + jitter_y) / size as f64,
+        )
+    }
+}
+
+/// Halton sequence with a prime base per dimension, Owen scrambled differently for every pixel.
+/// Dimensions beyond the supported primes get independent random numbers.
+pub struct HaltonSampler;
+
+/// Number of dimensions the Halton sampler has primes for
+const HALTON_DIMENSIONS: usize = 256;
+
+static PRIMES: LazyLock<Vec<u64>> = LazyLock::new(|| {
+    let mut primes = Vec::with_capacity(HALTON_DIMENSIONS);
+    let mut candidate = 2;
+    while primes.len() < HALTON_DIMENSIONS {
+        if primes.iter().all(|&p| candidate % p != 0) {
+            primes.push(candidate);
+        }
+        candidate += 1;
+    }
+    primes
+});
+
+impl Sampler for HaltonSampler {
+    fn name(&self) -> &'static str {
+        "halton"
+    }
+
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64 {
+        let Some(&base) = PRIMES.get(dimension as usize) else {
+            return random_double();
+        };
+        let hash = sample_hash(sample, dimension, 0);
+        owen_scrambled_radical_inverse(base, sample.index as u64, hash)
+    }
+
+    fn get_2d(&self, sample: &PixelSample, dimension: u32) -> (f64, f64) {
+        (
+            self.get_1d(sample, dimension),
+            self.get_1d(sample, dimension + 1),
+        )
+    }
+}
+
+/// First two dimensions of the Sobol sequence, Owen scrambled for every pair of dimensions and
+/// every pixel, which keeps its excellent two dimensional distribution for any number of
+/// dimensions. The order of the samples is shuffled per dimension as well, so that the dimensions
+/// aren't correlated (Burley 2020, "Practical Hash-based Owen Scrambling").
+pub struct SobolSampler;
+
+impl Sampler for SobolSampler {
+    fn name(&self) -> &'static str {
+        "sobol"
+    }
+
+    fn get_1d(&self, sample: &PixelSample, dimension: u32) -> f64 {
+        let hash = sample_hash(sample, dimension, 0);
+        let index = nested_uniform_scramble(sample.index, hash as u32);
+        let x = nested_uniform_scramble(index.reverse_bits(), (hash >> 32) as u32);
+        bits_to_unit(x)
+    }
+
+    fn get_2d(&self, sample: &PixelSample, dimension: u32) -> (f64, f64) {
+        let hash = sample_hash(sample, dimension, 0);
+        let index = nested_uniform_scramble(sample.index, hash as u32);
+        let hash = hash_u64(hash);
+        let x = nested_uniform_scramble(index.reverse_bits(), hash as u32);
+        let y = nested_uniform_scramble(sobol_second_dimension(index), (hash >> 32) as u32);
+        (bits_to_unit(x), bits_to_unit(y))
+    }
+}
+
+/// Returns the sampler with the given name.
+pub fn by_name(name: &str) -> Result<Arc<dyn Sampler>, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "independent" => Ok(Arc::new(IndependentSampler)),
+        "stratified" => Ok(Arc::new(StratifiedSampler)),
+        "halton" => Ok(Arc::new(HaltonSampler)),
+        "sobol" => Ok(Arc::new(SobolSampler)),
+        _ => Err(format!(
+            "unknown sampler `{name}`, expected independent, stratified, halton or sobol"
+        )),
+    }
+}
+
+/// Uses of the random numbers of a path. The camera takes the first dimensions of every sample
+/// and each bounce of the path the next ones, every use at a fixed offset. So a dimension serves
+/// the same purpose in all samples of a pixel, whatever the bounces before it did, which keeps
+/// the samples of every use stratified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dimension {
+    /// Position within the pixel, two dimensions
+    Pixel,
+    /// Position on the lens, two dimensions
+    Lens,
+    /// Time within the shutter interval
+    Time,
+    /// Which light a light sample goes to
+    LightSelect,
+    /// Position on the light, two dimensions
+    Light,
+    /// Direction the material scatters into, two dimensions
+    Scatter,
+    /// Choice between reflection and refraction
+    Choice,
+    /// Distance a ray travels through a medium
+    Medium,
+}
+
+/// Number of dimensions taken by the camera, before those of the first bounce
+const CAMERA_DIMENSIONS: u32 = 5;
+
+/// Number of dimensions taken by every bounce
+const BOUNCE_DIMENSIONS: u32 = 7;
+
+impl Dimension {
+    /// Index of the first of the dimensions of this use at the given bounce
+    fn index(self, bounce: u32) -> u32 {
+        let bounce_start = CAMERA_DIMENSIONS + bounce * BOUNCE_DIMENSIONS;
+        match self {
+            Dimension::Pixel => 0,
+            Dimension::Lens => 2,
+            Dimension::Time => 4,
+            Dimension::LightSelect => bounce_start,
+            Dimension::Light => bounce_start + 1,
+            Dimension::Scatter => bounce_start + 3,
+            Dimension::Choice => bounce_start + 5,
+            Dimension::Medium => bounce_start + 6,
+        }
+    }
+}
+
+/// Sample taken on a thread, with the bounce its path is at and the uses of the bounce so far
+#[derive(Clone, Copy, Default)]
+struct SampleState {
+    sample: PixelSample,
+    bounce: u32,
+    used: u8,
+}
+
+thread_local! {
+    static SAMPLER: RefCell<Option<Arc<dyn Sampler>>> = const { RefCell::new(None) };
+    static SAMPLE: Cell<SampleState> = Cell::new(SampleState::default());
+}
+
+/// Makes the current thread take its samples from `sampler`, or from independent random numbers
+/// if it's `None`.
+pub fn set_thread_sampler(sampler: Option<Arc<dyn Sampler>>) {
+    SAMPLER.with(|current| *current.borrow_mut() = sampler);
+}
+
+/// Starts a new sample on the current thread, with the dimensions of the camera.
+pub fn start_sample(sample: PixelSample) {
+    SAMPLE.with(|current| {
+        current.set(SampleState {
+            sample,
+            ..SampleState::default()
+        })
+    });
+}
+
+/// Moves the current sample on to the dimensions of the given bounce of its path, where the
+/// camera ray is bounce 0.
+pub fn start_bounce(bounce: u32) {
+    SAMPLE.with(|current| {
+        current.set(SampleState {
+            bounce,
+            used: 0,
+            ..current.get()
+        })
+    });
+}
+
+/// Returns the coordinate of the current sample in the dimension of `dimension`.
+pub fn sample_1d(dimension: Dimension) -> f64 {
+    let Some((sample, dimension)) = use_dimension(dimension) else {
+        return random_double();
+    };
+    SAMPLER.with(|sampler| match &*sampler.borrow() {
+        Some(sampler) => sampler.get_1d(&sample, dimension),
+        None => random_double(),
+    })
+}
+
+/// Returns the coordinates of the current sample in the two dimensions of `dimension`.
+pub fn sample_2d(dimension: Dimension) -> (f64, f64) {
+    let Some((sample, dimension)) = use_dimension(dimension) else {
+        return (random_double(), random_double());
+    };
+    SAMPLER.with(|sampler| match &*sampler.borrow() {
+        Some(sampler) => sampler.get_2d(&sample, dimension),
+        None => (random_double(), random_double()),
+    })
+}
+
+/// Picks one of `count` items with equal probability from the dimension of `dimension` of the
+/// current sample.
+pub fn sample_index(dimension: Dimension, count: usize) -> usize {
+    usize::min((sample_1d(dimension) * count as f64) as usize, count - 1)
+}
+
+/// Returns the current sample and the index of the dimension of `dimension` at its bounce, or
+/// `None` if the bounce used it already. A path can use a dimension more than once per bounce,
+/// like a ray passing through several media, and only the first use is stratified while the
+/// others get independent random numbers.
+fn use_dimension(dimension: Dimension) -> Option<(PixelSample, u32)> {
+    SAMPLE.with(|current| {
+        let state = current.get();
+        let bit = 1 << dimension as u8;
+        if state.used & bit != 0 {
+            return None;
+        }
+        current.set(SampleState {
+            used: state.used | bit,
+            ..state
+        });
+        Some((state.sample, dimension.index(state.bounce)))
+    })
+}
+
+/// Combines the sample's seed and pixel with the dimension into a hash, which decides how the
+/// dimension is scrambled in this pixel.
+fn sample_hash(sample: &PixelSample, dimension: u32, round: u64) -> u64 {
+    let pixel = hash_u64(sample.seed ^ hash_u64(sample.pixel));
+    hash_u64(pixel ^ hash_u64(((dimension as u64) << 32) | round))
+}
+
+fn hash_to_unit(hash: u64) -> f64 {
+    (hash >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+fn bits_to_unit(bits: u32) -> f64 {
+    f64::min(bits as f64 * (1.0 / (1u64 << 32) as f64), ONE_MINUS_EPSILON)
+}
+
+/// Returns the element at index `i` of a random permutation of 0..`l` chosen by `p`
+/// (Kensler 2013, "Correlated Multi-Jittered Sampling").
+fn permute(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    // Permute within the next power of two and walk the cycle until the result is in range.
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    (i.wrapping_add(p)) % l
+}
+
+/// Mirrors the digits of `index` in `base` around the decimal point, with every digit permuted
+/// depending on the digits before it, which is an Owen scrambling of the radical inverse.
+fn owen_scrambled_radical_inverse(base: u64, mut index: u64, hash: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut digit_weight = 1.0;
+    let mut reversed_digits: u64 = 0;
+    let mut result = 0.0;
+
+    // Digits continue as zeros beyond the index, which scrambling turns into arbitrary digits, so
+    // keep going until they fall below the precision of the result.
+    while 1.0 - (base - 1) as f64 * digit_weight < 1.0 {
+        let digit_hash = hash_u64(hash ^ reversed_digits);
+        let digit = permute((index % base) as u32, base as u32, digit_hash as u32) as u64;
+        digit_weight *= inverse_base;
+        result += digit as f64 * digit_weight;
+        reversed_digits = reversed_digits.wrapping_mul(base).wrapping_add(digit);
+        index /= base;
+    }
+    f64::min(result, ONE_MINUS_EPSILON)
+}
+
+/// Second dimension of the Sobol sequence as bits after the binary point.
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Owen scrambling of the bits of `x` after the binary point, where every bit is flipped
+/// depending on the bits before it.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    // Laine-Karras style hash with Burley's constants, which only lets bits affect less
+    // significant ones. Working on reversed bits makes that the bits after them instead.
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(sampler: &dyn Sampler, count: u32, dimension: u32) -> Vec<(f64, f64)> {
+        (0..count)
+            .map(|index| {
+                let sample = PixelSample {
+                    seed: 7,
+                    pixel: 1234,
+                    index,
+                    samples_per_pixel: count,
+                };
+                sampler.get_2d(&sample, dimension)
+            })
+            .collect()
+    }
+
+    /// Whether every cell of an n x n grid contains exactly one of the n² points
+    fn is_stratified(points: &[(f64, f64)], n: usize) -> bool {
+        let mut cells = vec![0; n * n];
+        for &(x, y) in points {
+            assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+            cells[(y * n as f64) as usize * n + (x * n as f64) as usize] += 1;
+        }
+        cells.iter().all(|&count| count == 1)
+    }
+
+    #[test]
+    fn permute_is_a_permutation() {
+        for l in [1, 2, 3, 7, 16, 100] {
+            let mut values: Vec<u32> = (0..l).map(|i| permute(i, l, 0xdeadbeef)).collect();
+            values.sort();
+            assert_eq!(values, (0..l).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn stratified_fills_every_cell() {
+        for dimension in [0, 2, 9] {
+            assert!(is_stratified(
+                &samples(&StratifiedSampler, 64, dimension),
+                8
+            ));
+        }
+    }
+
+    #[test]
+    fn sobol_fills_every_cell() {
+        // Scrambled (0, 2) sequences put one point into every cell of a 2^k grid.
+        for dimension in [0, 2, 9] {
+            let points = samples(&SobolSampler, 64, dimension);
+            assert!(is_stratified(&points, 8));
+        }
+    }
+
+    #[test]
+    fn halton_fills_every_interval() {
+        // 2^3 x 3^2 points put one point into every cell of an 8 x 9 grid.
+        let points = samples(&HaltonSampler, 72, 0);
+        let mut cells = [0; 72];
+        for (x, y) in points {
+            cells[(y * 9.0) as usize * 8 + (x * 8.0) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn bounce_dimensions_stay_stratified() {
+        set_thread_sampler(Some(Arc::new(StratifiedSampler)));
+        let points: Vec<(f64, f64)> = (0..64)
+            .map(|index| {
+                start_sample(PixelSample {
+                    seed: 3,
+                    pixel: 99,
+                    index,
+                    samples_per_pixel: 64,
+                });
+                sample_2d(Dimension::Pixel);
+
+                // The first bounce takes a varying number of random numbers, like a ray passing
+                // through several media and sampling a light only some of the time.
+                start_bounce(0);
+                let media: Vec<f64> = (0..index % 3)
+                    .map(|_| sample_1d(Dimension::Medium))
+                    .collect();
+                if media.len() == 2 {
+                    assert_ne!(media[0], media[1]);
+                }
+                if index % 2 == 0 {
+                    sample_index(Dimension::LightSelect, 3);
+                    sample_2d(Dimension::Light);
+                }
+                sample_2d(Dimension::Scatter);
+
+                start_bounce(1);
+                sample_2d(Dimension::Scatter)
+            })
+            .collect();
+        set_thread_sampler(None);
+        assert!(is_stratified(&points, 8));
+    }
+
+    #[test]
+    fn samplers_are_unbiased() {
+        // Mean of x² + y over the unit square is 1/3 + 1/2.
+        for sampler in ["independent", "stratified", "halton", "sobol"] {
+            let sampler = by_name(sampler).unwrap();
+            let mut sum = 0.0;
+            let mut count = 0;
+            for pixel in 0..64 {
+                for index in 0..16 {
+                    let sample = PixelSample {
+                        seed: 0,
+                        pixel,
+                        index,
+                        samples_per_pixel: 16,
+                    };
+                    let (x, y) = sampler.get_2d(&sample, 3);
+                    sum += x * x + y;
+                    count += 1;
+                }
+            }
+            let mean = sum / count as f64;
+            assert!(
+                (mean - 5.0 / 6.0).abs() < 0.02,
+                "{} mean {mean}",
+                sampler.name()
+            );
+        }
+    }
+}
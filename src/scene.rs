@@ -0,0 +1,48 @@
+use crate::{
+    accumulator::Accumulator, camera::Camera, framebuffer::Framebuffer, hittable_list::HittableList,
+};
+
+/// A world together with the camera set up to look at it
+pub struct Scene {
+    pub world: HittableList,
+    /// Objects that are sampled directly for light, usually the emitters of the world
+    pub lights: HittableList,
+    pub camera: Camera,
+}
+
+impl Scene {
+    pub fn new(world: HittableList, camera: Camera) -> Scene {
+        Scene::with_lights(world, HittableList::default(), camera)
+    }
+
+    /// Creates a scene whose light sources are sampled explicitly
+    ///
+    /// # Arguments
+    ///
+    /// * `world` - Objects making up the scene
+    /// * `lights` - Emitting objects, which have to be part of `world` as well
+    /// * `camera` - Camera looking at the scene
+    pub fn with_lights(world: HittableList, lights: HittableList, camera: Camera) -> Scene {
+        Scene {
+            world,
+            lights,
+            camera,
+        }
+    }
+
+    /// Renders the world through the scene camera
+    pub fn render(&mut self) -> Framebuffer {
+        self.camera.render(&self.world, &self.lights)
+    }
+
+    /// Adds samples to an accumulated image through the scene camera and returns whether it's
+    /// finished, see [`Camera::render_into`].
+    pub fn render_into(
+        &mut self,
+        accumulator: &mut Accumulator,
+        on_pass: impl FnMut(&Accumulator),
+    ) -> bool {
+        self.camera
+            .render_into(&self.world, &self.lights, accumulator, on_pass)
+    }
+}
@@ -0,0 +1,1533 @@
+use std::{collections::HashMap, error::Error, fmt, fs, io, path::Path, sync::Arc};
+
+use crate::{
+    bvh::{BvhNode, SplitMethod},
+    camera::{Camera, MAX_DEPTH, MAX_IMAGE_SIZE},
+    color::Color,
+    constant_medium::ConstantMedium,
+    denoise::{Denoiser, MAX_PASSES},
+    filter::{self, Filter, FilterKind},
+    hittable_list::{HittableList, RotateY, Transform, Translate},
+    mat4::Mat4,
+    material::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal},
+    obj,
+    quad::{bx, Quad},
+    rtweekend::{REFRACTION_AIR, REFRACTION_GLASS, REFRACTION_WATER},
+    sampler,
+    scene::Scene,
+    sphere::{Hittable, Sphere},
+    texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture},
+    triangle::Triangle,
+    vec3::{cross, vec3, Vec3},
+};
+
+/// Keywords that start an object block
+const OBJECT_KEYWORDS: [&str; 11] = [
+    "sphere",
+    "quad",
+    "triangle",
+    "mesh",
+    "box",
+    "translate",
+    "rotate_y",
+    "transform",
+    "constant_medium",
+    "bvh",
+    "group",
+];
+
+/// Syntax or semantic error in a scene description, located by line and column
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+/// Error loading a scene file from disk
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(error) => error.fmt(f),
+            SceneFileError::Parse(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Error for SceneFileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneFileError::Io(error) => Some(error),
+            SceneFileError::Parse(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for SceneFileError {
+    fn from(error: io::Error) -> Self {
+        SceneFileError::Io(error)
+    }
+}
+
+impl From<ParseError> for SceneFileError {
+    fn from(error: ParseError) -> Self {
+        SceneFileError::Parse(error)
+    }
+}
+
+/// Loads a scene file. Relative image paths inside the file are resolved against the directory
+/// the file lives in.
+pub fn load(path: impl AsRef<Path>) -> Result<Scene, SceneFileError> {
+    load_with_split(path, SplitMethod::default())
+}
+
+/// Loads a scene file like [`load`], with `method` dividing the objects of its `bvh` blocks and
+/// meshes between the nodes of their hierarchies.
+pub fn load_with_split(
+    path: impl AsRef<Path>,
+    method: SplitMethod,
+) -> Result<Scene, SceneFileError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path)?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    Ok(parse_with_split(&source, base_dir, method)?)
+}
+
+/// Parses a scene description, resolving relative image paths against `base_dir`.
+///
+/// A scene consists of an optional `camera` block, named `texture` and `material` definitions
+/// and the objects making up the world. Objects prefixed with `light` are also sampled directly
+/// as light sources:
+///
+/// ```text
+/// camera { image_width 600 aspect_ratio 16:9 lookfrom 13 2 3 lookat 0 0 0 }
+/// texture ground checker { scale 0.32 even 0.2 0.3 0.1 odd 0.9 0.9 0.9 }
+/// material floor lambertian { texture ground }
+/// material glass dielectric { refraction_index glass }
+/// material lamp diffuse_light { emit 4 4 4 }
+/// light sphere { center 0 7 0 radius 2 material lamp }
+/// bvh {
+///     sphere { center 0 -1000 0 radius 1000 material floor }
+///     sphere { center 0 1 0 radius 1 material glass }
+/// }
+/// ```
+pub fn parse(source: &str, base_dir: &Path) -> Result<Scene, ParseError> {
+    parse_with_split(source, base_dir, SplitMethod::default())
+}
+
+/// Parses a scene description like [`parse`], with `method` dividing the objects of its `bvh`
+/// blocks and meshes between the nodes of their hierarchies.
+pub fn parse_with_split(
+    source: &str,
+    base_dir: &Path,
+    method: SplitMethod,
+) -> Result<Scene, ParseError> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        base_dir,
+        split_method: method,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+    };
+    parser.scene()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    OpenBrace,
+    CloseBrace,
+    Colon,
+    Slash,
+    Eof,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Ident(name) => write!(f, "`{name}`"),
+            TokenKind::Number(number) => write!(f, "number {number}"),
+            TokenKind::Str(string) => write!(f, "string {string:?}"),
+            TokenKind::OpenBrace => write!(f, "`{{`"),
+            TokenKind::CloseBrace => write!(f, "`}}`"),
+            TokenKind::Colon => write!(f, "`:`"),
+            TokenKind::Slash => write!(f, "`/`"),
+            TokenKind::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    let mut column = 1;
+
+    while let Some(&c) = chars.peek() {
+        let start = Token {
+            kind: TokenKind::Eof,
+            line,
+            column,
+        };
+
+        // Skip whitespace and comments running to the end of the line.
+        if c == '\n' {
+            chars.next();
+            line += 1;
+            column = 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            chars.next();
+            column += 1;
+            continue;
+        }
+        if c == '#' {
+            while chars.peek().is_some_and(|&c| c != '\n') {
+                chars.next();
+            }
+            continue;
+        }
+
+        let kind = match c {
+            '{' | '}' | ':' | '/' => {
+                chars.next();
+                column += 1;
+                match c {
+                    '{' => TokenKind::OpenBrace,
+                    '}' => TokenKind::CloseBrace,
+                    ':' => TokenKind::Colon,
+                    _ => TokenKind::Slash,
+                }
+            }
+            '"' => {
+                chars.next();
+                column += 1;
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped @ ('"' | '\\')) => {
+                                string.push(escaped);
+                                column += 2;
+                            }
+                            _ => {
+                                return Err(ParseError {
+                                    line,
+                                    column,
+                                    message: String::from("invalid escape in string"),
+                                })
+                            }
+                        },
+                        Some('\n') | None => return Err(start.error("unterminated string")),
+                        Some(c) => {
+                            string.push(c);
+                            column += 1;
+                        }
+                    }
+                }
+                column += 1;
+                TokenKind::Str(string)
+            }
+            c if c.is_ascii_digit() || matches!(c, '-' | '+' | '.') => {
+                let mut number = String::from(c);
+                chars.next();
+                column += 1;
+                while let Some(&c) = chars.peek() {
+                    let exponent_sign = matches!(c, '-' | '+') && number.ends_with(['e', 'E']);
+                    if c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E') || exponent_sign {
+                        number.push(c);
+                        chars.next();
+                        column += 1;
+                    } else {
+                        break;
+                    }
+                }
+                match number.parse::<f64>() {
+                    Ok(value) if value.is_finite() => TokenKind::Number(value),
+                    Ok(_) => return Err(start.error(format!("number `{number}` is out of range"))),
+                    Err(_) => return Err(start.error(format!("invalid number `{number}`"))),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '-' {
+                        ident.push(c);
+                        chars.next();
+                        column += 1;
+                    } else {
+                        break;
+                    }
+                }
+                TokenKind::Ident(ident)
+            }
+            _ => return Err(start.error(format!("unexpected character `{c}`"))),
+        };
+
+        tokens.push(Token { kind, ..start });
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        line,
+        column,
+    });
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    base_dir: &'a Path,
+    split_method: SplitMethod,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<Token, ParseError> {
+        let token = self.next();
+        if token.kind != kind {
+            return Err(token.error(format!("expected {kind}, found {}", token.kind)));
+        }
+        Ok(token)
+    }
+
+    fn ident(&mut self, what: &str) -> Result<(String, Token), ParseError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Ident(name) => Ok((name.clone(), token)),
+            kind => Err(token.error(format!("expected {what}, found {kind}"))),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, ParseError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Number(number) => Ok(*number),
+            kind => Err(token.error(format!("expected a number, found {kind}"))),
+        }
+    }
+
+    fn positive_number(&mut self) -> Result<f64, ParseError> {
+        let token = self.peek().clone();
+        let number = self.number()?;
+        if number <= 0.0 {
+            return Err(token.error(format!(
+                "expected a positive number, found {}",
+                short(number)
+            )));
+        }
+        Ok(number)
+    }
+
+    /// A positive number no larger than `max`
+    fn number_up_to(&mut self, max: f64) -> Result<f64, ParseError> {
+        let token = self.peek().clone();
+        let number = self.number()?;
+        if number <= 0.0 || number > max {
+            return Err(token.error(format!(
+                "expected a positive number of at most {max}, found {}",
+                short(number)
+            )));
+        }
+        Ok(number)
+    }
+
+    /// A positive number smaller than `max`
+    fn number_below(&mut self, max: f64) -> Result<f64, ParseError> {
+        let token = self.peek().clone();
+        let number = self.number()?;
+        if number <= 0.0 || number >= max {
+            return Err(token.error(format!(
+                "expected a positive number below {max}, found {}",
+                short(number)
+            )));
+        }
+        Ok(number)
+    }
+
+    fn integer(&mut self) -> Result<i32, ParseError> {
+        self.integer_up_to(i32::MAX)
+    }
+
+    /// A positive integer no larger than `max`
+    fn integer_up_to(&mut self, max: i32) -> Result<i32, ParseError> {
+        let token = self.peek().clone();
+        let number = self.number()?;
+        if number.fract() != 0.0 || number < 1.0 || number > max as f64 {
+            return Err(token.error(format!(
+                "expected a positive integer of at most {max}, found {}",
+                short(number)
+            )));
+        }
+        Ok(number as i32)
+    }
+
+    fn seed(&mut self) -> Result<u64, ParseError> {
+        let token = self.peek().clone();
+        let number = self.number()?;
+        // Larger numbers can't be represented exactly by the tokenizer.
+        if number.fract() != 0.0 || number < 0.0 || number > (1u64 << 53) as f64 {
+            return Err(token.error(format!(
+                "expected a seed between 0 and 2^53, found {number}"
+            )));
+        }
+        Ok(number as u64)
+    }
+
+    fn string(&mut self) -> Result<(String, Token), ParseError> {
+        let token = self.next();
+        match &token.kind {
+            TokenKind::Str(string) => Ok((string.clone(), token)),
+            kind => Err(token.error(format!("expected a string, found {kind}"))),
+        }
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, ParseError> {
+        Ok(vec3(self.number()?, self.number()?, self.number()?))
+    }
+
+    fn color(&mut self) -> Result<Color, ParseError> {
+        Ok(Color::new(self.number()?, self.number()?, self.number()?))
+    }
+
+    /// A ratio given either as a single number or as `width:height` / `width/height`, between
+    /// `1 / max` and `max`
+    fn ratio(&mut self, max: f64) -> Result<f64, ParseError> {
+        let token = self.peek().clone();
+        let mut ratio = self.positive_number()?;
+        if matches!(self.peek().kind, TokenKind::Colon | TokenKind::Slash) {
+            self.next();
+            ratio /= self.positive_number()?;
+        }
+        if !(1.0 / max..=max).contains(&ratio) {
+            return Err(token.error(format!(
+                "expected a ratio between 1/{max} and {max}, found {}",
+                short(ratio)
+            )));
+        }
+        Ok(ratio)
+    }
+
+    /// A named texture, or an inline color that gets wrapped into a solid texture
+    fn texture_or_color(&mut self) -> Result<Arc<dyn Texture>, ParseError> {
+        if let TokenKind::Number(_) = self.peek().kind {
+            return Ok(Arc::new(SolidColor::from_color(self.color()?)));
+        }
+        self.texture_ref()
+    }
+
+    fn texture_ref(&mut self) -> Result<Arc<dyn Texture>, ParseError> {
+        let (name, token) = self.ident("a texture name")?;
+        match self.textures.get(&name) {
+            Some(texture) => Ok(texture.clone()),
+            None => Err(token.error(format!("unknown texture `{name}`"))),
+        }
+    }
+
+    fn material_ref(&mut self) -> Result<Arc<dyn Material>, ParseError> {
+        let (name, token) = self.ident("a material name")?;
+        match self.materials.get(&name) {
+            Some(material) => Ok(material.clone()),
+            None => Err(token.error(format!("unknown material `{name}`"))),
+        }
+    }
+
+    fn refraction_index(&mut self) -> Result<f64, ParseError> {
+        if let TokenKind::Number(_) = self.peek().kind {
+            return self.positive_number();
+        }
+        let (name, token) = self.ident("a refraction index")?;
+        match name.as_str() {
+            "glass" => Ok(REFRACTION_GLASS),
+            "water" => Ok(REFRACTION_WATER),
+            "air" => Ok(REFRACTION_AIR),
+            _ => Err(token.error(format!(
+                "unknown refraction index `{name}`, expected a number, `glass`, `water` or `air`"
+            ))),
+        }
+    }
+
+    /// Parses a `{ key value... }` block, handing each key to `entry` which consumes its values.
+    fn block<F>(&mut self, mut entry: F) -> Result<(), ParseError>
+    where
+        F: FnMut(&mut Self, &str, &Token) -> Result<(), ParseError>,
+    {
+        self.expect(TokenKind::OpenBrace)?;
+        loop {
+            let token = self.next();
+            match &token.kind {
+                TokenKind::CloseBrace => return Ok(()),
+                TokenKind::Ident(key) => entry(self, key, &token)?,
+                kind => {
+                    return Err(token.error(format!("expected a property or `}}`, found {kind}")))
+                }
+            }
+        }
+    }
+
+    fn scene(&mut self) -> Result<Scene, ParseError> {
+        let mut world = HittableList::default();
+        let mut lights = HittableList::default();
+        let mut camera = Camera::default();
+
+        loop {
+            let token = self.next();
+            match &token.kind {
+                TokenKind::Eof => break,
+                TokenKind::Ident(keyword) => match keyword.as_str() {
+                    "camera" => self.camera(&mut camera)?,
+                    "texture" => self.texture()?,
+                    "material" => self.material()?,
+                    "light" => {
+                        // A light is an ordinary object that is also sampled directly.
+                        let (keyword, token) = self.ident("an object")?;
+                        if !OBJECT_KEYWORDS.contains(&keyword.as_str()) {
+                            return Err(token.error(format!("expected an object, found `{keyword}`")));
+                        }
+                        let object = self.object(&keyword, &token)?;
+                        world.add(object.clone());
+                        lights.add(object);
+                    }
+                    keyword if OBJECT_KEYWORDS.contains(&keyword) => {
+                        world.add(self.object(keyword, &token)?)
+                    }
+                    _ => return Err(token.error(format!(
+                        "expected `camera`, `texture`, `material`, `light` or an object, found `{keyword}`"
+                    ))),
+                },
+                kind => {
+                    return Err(token.error(format!(
+                        "expected `camera`, `texture`, `material`, `light` or an object, found {kind}"
+                    )))
+                }
+            }
+        }
+
+        Ok(Scene::with_lights(world, lights, camera))
+    }
+
+    fn camera(&mut self, cam: &mut Camera) -> Result<(), ParseError> {
+        let mut filter = None;
+        let mut filter_radius = None;
+        let mut denoise_passes = None;
+        let mut denoise_strength = None;
+        let mut size_token = None;
+        self.block(|p, key, token| {
+            match key {
+                "aspect_ratio" => {
+                    size_token = Some(p.peek().clone());
+                    cam.aspect_ratio = p.ratio(MAX_IMAGE_SIZE as f64)?;
+                }
+                "image_width" => {
+                    size_token = Some(p.peek().clone());
+                    cam.image_width = p.integer_up_to(MAX_IMAGE_SIZE)?;
+                }
+                "samples_per_pixel" => cam.samples_per_pixel = p.integer()?,
+                "min_samples_per_pixel" => cam.min_samples_per_pixel = p.integer()?,
+                "error_threshold" => cam.error_threshold = p.positive_number()?,
+                "max_depth" => cam.max_depth = p.integer_up_to(MAX_DEPTH)?,
+                "background" => cam.background = p.color()?,
+                "vfov" => cam.vfov = p.number_below(180.0)?,
+                "lookfrom" => cam.lookfrom = p.vec3()?,
+                "lookat" => cam.lookat = p.vec3()?,
+                "vup" => cam.vup = p.vec3()?,
+                "defocus_angle" => cam.defocus_angle = p.number()?,
+                "focus_dist" => cam.focus_dist = p.positive_number()?,
+                "seed" => cam.seed = p.seed()?,
+                "sampler" => {
+                    let (name, name_token) = p.ident("a sampler name")?;
+                    cam.sampler =
+                        sampler::by_name(&name).map_err(|error| name_token.error(error))?;
+                }
+                "filter" => {
+                    let (name, name_token) = p.ident("a filter name")?;
+                    let kind: FilterKind = name.parse().map_err(|error| name_token.error(error))?;
+                    filter = Some(kind);
+                }
+                "filter_radius" => filter_radius = Some(p.number_up_to(filter::MAX_RADIUS)?),
+                "indirect_clamp" => cam.indirect_clamp = p.positive_number()?,
+                "denoise" => denoise_passes = Some(p.integer_up_to(MAX_PASSES as i32)? as u32),
+                "denoise_strength" => denoise_strength = Some(p.positive_number()?),
+                "exposure" => cam.display.exposure = p.number()?,
+                "tone_map" => {
+                    let (name, name_token) = p.ident("a tone mapper name")?;
+                    cam.display.tone_mapper =
+                        name.parse().map_err(|error| name_token.error(error))?;
+                }
+                "white_point" => cam.display.white_point = p.positive_number()?,
+                "dither" => {
+                    let (value, value_token) = p.ident("`on` or `off`")?;
+                    cam.display.dither = match value.as_str() {
+                        "on" => true,
+                        "off" => false,
+                        _ => {
+                            return Err(value_token
+                                .error(format!("expected `on` or `off`, found `{value}`")))
+                        }
+                    };
+                }
+                _ => return Err(unknown_property(token, key, "camera")),
+            }
+            Ok(())
+        })?;
+
+        // Both settings are in range, but together they may still give too tall an image.
+        let (_, image_height) = cam.image_size();
+        if let Some(token) = size_token.filter(|_| image_height > MAX_IMAGE_SIZE as u32) {
+            return Err(token.error(format!(
+                "image height {image_height} is larger than the maximum of {MAX_IMAGE_SIZE} pixels"
+            )));
+        }
+
+        // A filter without a radius gets the usual one for its kind.
+        if filter.is_some() || filter_radius.is_some() {
+            let kind = filter.unwrap_or(cam.filter.kind());
+            let radius = filter_radius.unwrap_or(kind.default_radius());
+            cam.filter = Filter::with_radius(kind, radius);
+        }
+
+        // Denoising is on with any of its settings.
+        if denoise_passes.is_some() || denoise_strength.is_some() {
+            let default = Denoiser::default();
+            cam.denoiser = Some(Denoiser {
+                passes: denoise_passes.unwrap_or(default.passes),
+                strength: denoise_strength.unwrap_or(default.strength),
+            });
+        }
+        Ok(())
+    }
+
+    fn texture(&mut self) -> Result<(), ParseError> {
+        let (name, name_token) = self.ident("a texture name")?;
+        if self.textures.contains_key(&name) {
+            return Err(name_token.error(format!("texture `{name}` is already defined")));
+        }
+        let (kind, kind_token) = self.ident("a texture type")?;
+
+        let texture: Arc<dyn Texture> = match kind.as_str() {
+            "solid" => {
+                let mut color = None;
+                self.block(|p, key, token| {
+                    match key {
+                        "color" => color = Some(p.color()?),
+                        _ => return Err(unknown_property(token, key, "solid texture")),
+                    }
+                    Ok(())
+                })?;
+                let color = required(color, &kind_token, "solid texture", "color")?;
+                Arc::new(SolidColor::from_rgb(color.r, color.g, color.b))
+            }
+            "checker" => {
+                let (mut scale, mut even, mut odd) = (None, None, None);
+                self.block(|p, key, token| {
+                    match key {
+                        "scale" => scale = Some(p.positive_number()?),
+                        "even" => even = Some(p.texture_or_color()?),
+                        "odd" => odd = Some(p.texture_or_color()?),
+                        _ => return Err(unknown_property(token, key, "checker texture")),
+                    }
+                    Ok(())
+                })?;
+                Arc::new(CheckerTexture::new(
+                    required(scale, &kind_token, "checker texture", "scale")?,
+                    required(even, &kind_token, "checker texture", "even")?,
+                    required(odd, &kind_token, "checker texture", "odd")?,
+                ))
+            }
+            "image" => {
+                let mut image = None;
+                self.block(|p, key, token| {
+                    match key {
+                        "file" => {
+                            let (file, file_token) = p.string()?;
+                            let path = p.base_dir.join(&file);
+                            let texture = ImageTexture::open(&path).map_err(|error| {
+                                file_token.error(format!("can't load image `{file}`: {error}"))
+                            })?;
+                            image = Some(texture);
+                        }
+                        _ => return Err(unknown_property(token, key, "image texture")),
+                    }
+                    Ok(())
+                })?;
+                Arc::new(required(image, &kind_token, "image texture", "file")?)
+            }
+            "noise" => {
+                let (mut scale, mut seed) = (None, 0);
+                self.block(|p, key, token| {
+                    match key {
+                        "scale" => scale = Some(p.positive_number()?),
+                        "seed" => seed = p.seed()?,
+                        _ => return Err(unknown_property(token, key, "noise texture")),
+                    }
+                    Ok(())
+                })?;
+                Arc::new(NoiseTexture::with_seed(
+                    required(scale, &kind_token, "noise texture", "scale")?,
+                    seed,
+                ))
+            }
+            _ => {
+                return Err(kind_token.error(format!(
+                    "unknown texture type `{kind}`, expected `solid`, `checker`, `image` or `noise`"
+                )))
+            }
+        };
+
+        self.textures.insert(name, texture);
+        Ok(())
+    }
+
+    fn material(&mut self) -> Result<(), ParseError> {
+        let (name, name_token) = self.ident("a material name")?;
+        if self.materials.contains_key(&name) {
+            return Err(name_token.error(format!("material `{name}` is already defined")));
+        }
+        let (kind, kind_token) = self.ident("a material type")?;
+
+        let material: Arc<dyn Material> = match kind.as_str() {
+            "lambertian" | "isotropic" => {
+                let mut tex = None;
+                self.block(|p, key, token| {
+                    match key {
+                        "albedo" => tex = Some(p.texture_or_color()?),
+                        "texture" => tex = Some(p.texture_ref()?),
+                        _ => return Err(unknown_property(token, key, &kind)),
+                    }
+                    Ok(())
+                })?;
+                let tex = required(tex, &kind_token, &kind, "albedo")?;
+                if kind == "lambertian" {
+                    Arc::new(Lambertian::from_tex(tex))
+                } else {
+                    Arc::new(Isotropic::from_tex(tex))
+                }
+            }
+            "metal" => {
+                let (mut albedo, mut fuzz) = (None, 0.0);
+                self.block(|p, key, token| {
+                    match key {
+                        "albedo" => albedo = Some(p.color()?),
+                        "fuzz" => fuzz = p.number()?,
+                        _ => return Err(unknown_property(token, key, "metal")),
+                    }
+                    Ok(())
+                })?;
+                Arc::new(Metal::new(
+                    required(albedo, &kind_token, "metal", "albedo")?,
+                    fuzz,
+                ))
+            }
+            "dielectric" => {
+                let mut refraction_index = None;
+                self.block(|p, key, token| {
+                    match key {
+                        "refraction_index" => refraction_index = Some(p.refraction_index()?),
+                        _ => return Err(unknown_property(token, key, "dielectric")),
+                    }
+                    Ok(())
+                })?;
+                Arc::new(Dielectric::new(required(
+                    refraction_index,
+                    &kind_token,
+                    "dielectric",
+                    "refraction_index",
+                )?))
+            }
+            "diffuse_light" => {
+                let mut tex = None;
+                self.block(|p, key, token| {
+                    match key {
+                        "emit" => tex = Some(p.texture_or_color()?),
+                        "texture" => tex = Some(p.texture_ref()?),
+                        _ => return Err(unknown_property(token, key, "diffuse_light")),
+                    }
+                    Ok(())
+                })?;
+                Arc::new(DiffuseLight::from_tex(required(
+                    tex,
+                    &kind_token,
+                    "diffuse_light",
+                    "emit",
+                )?))
+            }
+            _ => {
+                return Err(kind_token.error(format!(
+                    "unknown material type `{kind}`, expected `lambertian`, `metal`, \
+                     `dielectric`, `diffuse_light` or `isotropic`"
+                )))
+            }
+        };
+
+        self.materials.insert(name, material);
+        Ok(())
+    }
+
+    /// Parses the block of an object whose keyword has already been consumed.
+    fn object(&mut self, keyword: &str, token: &Token) -> Result<Arc<dyn Hittable>, ParseError> {
+        match keyword {
+            "sphere" => {
+                let (mut center, mut center2, mut radius, mut mat) = (None, None, None, None);
+                self.block(|p, key, token| {
+                    match key {
+                        "center" => center = Some(p.vec3()?),
+                        "center2" => center2 = Some(p.vec3()?),
+                        "radius" => radius = Some(p.positive_number()?),
+                        "material" => mat = Some(p.material_ref()?),
+                        _ => return Err(unknown_property(token, key, "sphere")),
+                    }
+                    Ok(())
+                })?;
+                let center = required(center, token, "sphere", "center")?;
+                let radius = required(radius, token, "sphere", "radius")?;
+                let mat = required(mat, token, "sphere", "material")?;
+                Ok(match center2 {
+                    Some(center2) => Arc::new(Sphere::moving(center, center2, radius, mat)),
+                    None => Arc::new(Sphere::new(center, radius, mat)),
+                })
+            }
+            "quad" => {
+                let (mut q, mut u, mut v, mut mat) = (None, None, None, None);
+                self.block(|p, key, token| {
+                    match key {
+                        "q" => q = Some(p.vec3()?),
+                        "u" => u = Some(p.vec3()?),
+                        "v" => v = Some(p.vec3()?),
+                        "material" => mat = Some(p.material_ref()?),
+                        _ => return Err(unknown_property(token, key, "quad")),
+                    }
+                    Ok(())
+                })?;
+                Ok(Arc::new(Quad::new(
+                    required(q, token, "quad", "q")?,
+                    required(u, token, "quad", "u")?,
+                    required(v, token, "quad", "v")?,
+                    required(mat, token, "quad", "material")?,
+                )))
+            }
+            "triangle" => {
+                let (mut a, mut b, mut c, mut mat) = (None, None, None, None);
+                self.block(|p, key, token| {
+                    match key {
+                        "a" => a = Some(p.vec3()?),
+                        "b" => b = Some(p.vec3()?),
+                        "c" => c = Some(p.vec3()?),
+                        "material" => mat = Some(p.material_ref()?),
+                        _ => return Err(unknown_property(token, key, "triangle")),
+                    }
+                    Ok(())
+                })?;
+                Ok(Arc::new(Triangle::new(
+                    required(a, token, "triangle", "a")?,
+                    required(b, token, "triangle", "b")?,
+                    required(c, token, "triangle", "c")?,
+                    required(mat, token, "triangle", "material")?,
+                )))
+            }
+            "mesh" => {
+                let (mut file, mut mat) = (None, None);
+                self.block(|p, key, token| {
+                    match key {
+                        "file" => file = Some(p.string()?),
+                        "material" => mat = Some(p.material_ref()?),
+                        _ => return Err(unknown_property(token, key, "mesh")),
+                    }
+                    Ok(())
+                })?;
+                let (file, file_token) = required(file, token, "mesh", "file")?;
+                let path = self.base_dir.join(&file);
+                // Without a material the ones from the OBJ's material libraries are used.
+                let list =
+                    obj::load_with_split(&path, mat, self.split_method).map_err(|error| {
+                        file_token.error(format!("can't load mesh `{file}`: {error}"))
+                    })?;
+                if list.objects.is_empty() {
+                    return Err(file_token.error(format!("mesh `{file}` has no faces")));
+                }
+                Ok(Arc::new(BvhNode::with_split(
+                    &list.objects,
+                    self.split_method,
+                )))
+            }
+            "box" => {
+                let (mut a, mut b, mut mat) = (None, None, None);
+                self.block(|p, key, token| {
+                    match key {
+                        "a" => a = Some(p.vec3()?),
+                        "b" => b = Some(p.vec3()?),
+                        "material" => mat = Some(p.material_ref()?),
+                        _ => return Err(unknown_property(token, key, "box")),
+                    }
+                    Ok(())
+                })?;
+                Ok(bx(
+                    &required(a, token, "box", "a")?,
+                    &required(b, token, "box", "b")?,
+                    required(mat, token, "box", "material")?,
+                ))
+            }
+            "translate" => {
+                let (mut offset, mut children) = (None, Vec::new());
+                self.block(|p, key, token| {
+                    match key {
+                        "offset" => offset = Some(p.vec3()?),
+                        _ => children.push(p.child(key, token, "translate")?),
+                    }
+                    Ok(())
+                })?;
+                let offset = required(offset, token, "translate", "offset")?;
+                let object = combine(children, token, "translate")?;
+                Ok(Arc::new(Translate::new(object, offset)))
+            }
+            "rotate_y" => {
+                let (mut angle, mut children) = (None, Vec::new());
+                self.block(|p, key, token| {
+                    match key {
+                        "angle" => angle = Some(p.number()?),
+                        _ => children.push(p.child(key, token, "rotate_y")?),
+                    }
+                    Ok(())
+                })?;
+                let angle = required(angle, token, "rotate_y", "angle")?;
+                let object = combine(children, token, "rotate_y")?;
+                Ok(Arc::new(RotateY::new(object, angle)))
+            }
+            "transform" => {
+                // Steps apply in the order they are listed, each on top of the previous ones.
+                let (mut matrix, mut children) = (Mat4::identity(), Vec::new());
+                self.block(|p, key, token| {
+                    let step = match key {
+                        // `translate {` starts a nested translate object instead.
+                        "translate" if p.peek().kind != TokenKind::OpenBrace => {
+                            Mat4::translation(&p.vec3()?)
+                        }
+                        "rotate" => {
+                            let axis = p.vec3()?;
+                            if axis.length_squared() == 0.0 {
+                                return Err(token.error("rotation axis can't be zero"));
+                            }
+                            Mat4::rotation(&axis, p.number()?)
+                        }
+                        "scale" => {
+                            let x = p.number()?;
+                            let factors = match p.peek().kind {
+                                TokenKind::Number(_) => vec3(x, p.number()?, p.number()?),
+                                _ => vec3(x, x, x),
+                            };
+                            if factors.x * factors.y * factors.z == 0.0 {
+                                return Err(token.error("scale factors can't be zero"));
+                            }
+                            Mat4::scaling(&factors)
+                        }
+                        "look_at" => {
+                            let (from, to, up) = (p.vec3()?, p.vec3()?, p.vec3()?);
+                            if cross(&(to - from), &up).length_squared() == 0.0 {
+                                return Err(token.error(
+                                    "look_at needs distinct points and an up direction that \
+                                     isn't parallel to the view",
+                                ));
+                            }
+                            Mat4::look_at(&from, &to, &up)
+                        }
+                        _ => {
+                            children.push(p.child(key, token, "transform")?);
+                            return Ok(());
+                        }
+                    };
+                    matrix = step * matrix;
+                    Ok(())
+                })?;
+                let object = combine(children, token, "transform")?;
+                match Transform::try_new(object, matrix) {
+                    Some(transform) => Ok(Arc::new(transform)),
+                    None => {
+                        Err(token.error("transformation flattens the object, it can't be inverted"))
+                    }
+                }
+            }
+            "constant_medium" => {
+                let (mut density, mut tex, mut children) = (None, None, Vec::new());
+                self.block(|p, key, token| {
+                    match key {
+                        "density" => density = Some(p.positive_number()?),
+                        "albedo" => tex = Some(p.texture_or_color()?),
+                        "texture" => tex = Some(p.texture_ref()?),
+                        _ => children.push(p.child(key, token, "constant_medium")?),
+                    }
+                    Ok(())
+                })?;
+                let density = required(density, token, "constant_medium", "density")?;
+                let tex = required(tex, token, "constant_medium", "albedo")?;
+                let boundary = combine(children, token, "constant_medium")?;
+                Ok(Arc::new(ConstantMedium::from_tex(boundary, density, tex)))
+            }
+            "bvh" | "group" => {
+                let mut list = HittableList::default();
+                self.block(|p, key, token| {
+                    list.add(p.child(key, token, keyword)?);
+                    Ok(())
+                })?;
+                if list.objects.is_empty() {
+                    return Err(token.error(format!("{keyword} needs at least one object")));
+                }
+                if keyword == "bvh" {
+                    Ok(Arc::new(BvhNode::with_split(
+                        &list.objects,
+                        self.split_method,
+                    )))
+                } else {
+                    Ok(Arc::new(list))
+                }
+            }
+            _ => Err(token.error(format!("unknown object `{keyword}`"))),
+        }
+    }
+
+    /// Parses a nested object inside the block of `parent`.
+    fn child(
+        &mut self,
+        keyword: &str,
+        token: &Token,
+        parent: &str,
+    ) -> Result<Arc<dyn Hittable>, ParseError> {
+        if !OBJECT_KEYWORDS.contains(&keyword) {
+            return Err(unknown_property(token, keyword, parent));
+        }
+        self.object(keyword, token)
+    }
+}
+
+/// Formats a number in scientific notation if it's very large or small.
+fn short(number: f64) -> String {
+    if number != 0.0 && !(1e-4..1e9).contains(&number.abs()) {
+        return format!("{number:e}");
+    }
+    format!("{number}")
+}
+
+fn unknown_property(token: &Token, key: &str, block: &str) -> ParseError {
+    token.error(format!("unknown property `{key}` in {block}"))
+}
+
+fn required<T>(value: Option<T>, token: &Token, block: &str, key: &str) -> Result<T, ParseError> {
+    value.ok_or_else(|| token.error(format!("{block} is missing `{key}`")))
+}
+
+/// Wraps the children of an instance block into a single object.
+fn combine(
+    mut children: Vec<Arc<dyn Hittable>>,
+    token: &Token,
+    block: &str,
+) -> Result<Arc<dyn Hittable>, ParseError> {
+    match children.len() {
+        0 => Err(token.error(format!("{block} needs at least one object"))),
+        1 => Ok(children.remove(0)),
+        _ => {
+            let mut list = HittableList::default();
+            for child in children {
+                list.add(child);
+            }
+            Ok(Arc::new(list))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{any::Any, path::PathBuf};
+
+    use super::*;
+    use crate::{interval::Interval, material::ScatterRecord, ray::ray, sphere::HitRecord};
+
+    /// Directory of the example scenes, which relative paths in the tests resolve against
+    fn scenes_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes")
+    }
+
+    fn parse_str(source: &str) -> Result<Scene, ParseError> {
+        parse(source, &scenes_dir())
+    }
+
+    fn is<T: Any>(object: &Arc<dyn Hittable>) -> bool {
+        (&**object as &dyn Any).is::<T>()
+    }
+
+    /// Hits the world with a ray going down the z axis at `x`.
+    fn hit_at(world: &dyn Hittable, x: f64) -> HitRecord {
+        let mut rec = HitRecord::default();
+        let r = ray(vec3(x, 0.0, 5.0), vec3(0.0, 0.0, -1.0));
+        assert!(
+            world.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec),
+            "{x}"
+        );
+        rec
+    }
+
+    fn assert_color(color: Color, r: f64, g: f64, b: f64) {
+        assert_eq!((color.r, color.g, color.b), (r, g, b));
+    }
+
+    #[test]
+    fn parses_every_object() {
+        let scene = parse_str(
+            r#"
+            material white lambertian { albedo 0.8 0.8 0.8 }
+            material lamp diffuse_light { emit 4 4 4 }
+            sphere { center 0 0 0 radius 1 material white }
+            sphere { center 0 0 0 center2 0 1 0 radius 1 material white }
+            quad { q 0 0 0 u 1 0 0 v 0 1 0 material white }
+            triangle { a 0 0 0 b 1 0 0 c 0 1 0 material white }
+            mesh { file "models/models.obj" }
+            mesh { file "models/models.obj" material white }
+            box { a 0 0 0 b 1 2 3 material white }
+            translate { offset 10 0 0 sphere { center 0 0 0 radius 1 material white } }
+            rotate_y { angle 90 box { a 0 0 0 b 1 1 2 material white } }
+            transform {
+                scale 2
+                rotate 0 0 1 90
+                translate 0 0 5
+                sphere { center 1 0 0 radius 1 material white }
+            }
+            constant_medium {
+                density 0.1
+                albedo 1 1 1
+                sphere { center 0 0 0 radius 1 material white }
+            }
+            bvh {
+                sphere { center 0 0 0 radius 1 material white }
+                sphere { center 3 0 0 radius 1 material white }
+            }
+            group {
+                sphere { center 0 0 0 radius 1 material white }
+                quad { q 0 0 0 u 1 0 0 v 0 1 0 material white }
+            }
+            light quad { q 0 5 0 u 1 0 0 v 0 0 1 material lamp }
+            "#,
+        )
+        .unwrap();
+
+        let objects = &scene.world.objects;
+        assert_eq!(objects.len(), 14);
+        assert!(is::<Sphere>(&objects[0]) && is::<Sphere>(&objects[1]));
+        assert!(is::<Quad>(&objects[2]));
+        assert!(is::<Triangle>(&objects[3]));
+        assert!(is::<BvhNode>(&objects[4]) && is::<BvhNode>(&objects[5]));
+        assert!(is::<HittableList>(&objects[6]));
+        assert!(is::<Translate>(&objects[7]));
+        assert!(is::<RotateY>(&objects[8]));
+        assert!(is::<Transform>(&objects[9]));
+        assert!(is::<ConstantMedium>(&objects[10]));
+        assert!(is::<BvhNode>(&objects[11]));
+        assert!(is::<HittableList>(&objects[12]));
+        assert!(is::<Quad>(&objects[13]));
+
+        // Bounding boxes are padded slightly, so they are only compared roughly.
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-3;
+        // The moving sphere covers both of its positions.
+        assert!(close(objects[1].bounding_box().y.max, 2.0));
+        assert!(close(objects[6].bounding_box().z.max, 3.0));
+        assert!(close(objects[7].bounding_box().x.min, 9.0));
+        // Rotating by 90 degrees around y swaps the extents along x and z.
+        let rotated = objects[8].bounding_box();
+        assert!(close(rotated.x.size(), 2.0) && close(rotated.z.size(), 1.0));
+        // The steps apply in order, the sphere ends up around (0, 2, 5) with radius 2.
+        let transformed = objects[9].bounding_box();
+        assert!(close(transformed.y.min, 0.0) && close(transformed.y.max, 4.0));
+        assert!(close(transformed.z.min, 3.0));
+        assert!(close(objects[11].bounding_box().x.max, 4.0));
+
+        // Lights are part of the world and also sampled directly.
+        assert_eq!(scene.lights.objects.len(), 1);
+        assert!(Arc::ptr_eq(&scene.lights.objects[0], &objects[13]));
+    }
+
+    #[test]
+    fn parses_every_material_and_texture() {
+        let scene = parse_str(
+            r#"
+            texture plain solid { color 0.4 0.5 0.6 }
+            texture tiles checker { scale 1 even plain odd 0 0 0 }
+            texture earth image { file "../misc/earthmap.jpg" }
+            texture marble noise { scale 4 seed 3 }
+            material a lambertian { albedo 0.1 0.2 0.3 }
+            material b lambertian { texture plain }
+            material c lambertian { albedo tiles }
+            material d lambertian { texture earth }
+            material e lambertian { texture marble }
+            material f metal { albedo 0.7 0.6 0.5 }
+            material g dielectric { refraction_index 1.5 }
+            material h dielectric { refraction_index water }
+            material i diffuse_light { emit 4 5 6 }
+            material j isotropic { albedo 0.3 0.3 0.3 }
+            sphere { center 0 0 0 radius 1 material a }
+            sphere { center 10 0 0 radius 1 material b }
+            sphere { center 20 0 0 radius 1 material c }
+            sphere { center 30 0 0 radius 1 material d }
+            sphere { center 40 0 0 radius 1 material e }
+            sphere { center 50 0 0 radius 1 material f }
+            sphere { center 60 0 0 radius 1 material g }
+            sphere { center 70 0 0 radius 1 material h }
+            sphere { center 80 0 0 radius 1 material i }
+            sphere { center 90 0 0 radius 1 material j }
+            "#,
+        )
+        .unwrap();
+
+        let scatter = |x: f64| {
+            let rec = hit_at(&scene.world, x);
+            let r_in = ray(vec3(x, 0.0, 5.0), vec3(0.0, 0.0, -1.0));
+            let mut srec = ScatterRecord::default();
+            let scattered = rec.mat.scatter(&r_in, &rec, &mut srec);
+            (rec, scattered, srec)
+        };
+
+        let (_, scattered, srec) = scatter(0.0);
+        assert!(scattered && srec.pdf.is_some());
+        assert_color(srec.attenuation, 0.1, 0.2, 0.3);
+        assert_color(scatter(10.0).2.attenuation, 0.4, 0.5, 0.6);
+        let checker = scatter(20.0).2.attenuation;
+        assert!(checker.r == 0.4 || checker.r == 0.0);
+        for x in [30.0, 40.0] {
+            let (_, scattered, srec) = scatter(x);
+            assert!(scattered && srec.attenuation.luminance() > 0.0, "{x}");
+        }
+
+        // Metal without fuzz reflects the ray straight back.
+        let (_, scattered, srec) = scatter(50.0);
+        assert!(scattered && srec.pdf.is_none());
+        assert_color(srec.attenuation, 0.7, 0.6, 0.5);
+        assert_eq!(srec.skip_pdf_ray.direction().z, 1.0);
+
+        for x in [60.0, 70.0] {
+            let (_, scattered, srec) = scatter(x);
+            assert!(scattered && srec.pdf.is_none(), "{x}");
+            assert_color(srec.attenuation, 1.0, 1.0, 1.0);
+        }
+
+        let (rec, scattered, _) = scatter(80.0);
+        assert!(!scattered);
+        assert_color(rec.mat.emitted(rec.u, rec.v, &rec.p), 4.0, 5.0, 6.0);
+
+        let (_, scattered, srec) = scatter(90.0);
+        assert!(scattered);
+        assert_color(srec.attenuation, 0.3, 0.3, 0.3);
+    }
+
+    #[test]
+    fn parses_camera_settings() {
+        let scene = parse_str(
+            "camera {
+                aspect_ratio 16:9 image_width 320 samples_per_pixel 8 min_samples_per_pixel 4
+                error_threshold 0.01 max_depth 7 background 0.1 0.2 0.3 vfov 40
+                lookfrom 1 2 3 lookat 0 1 0 vup 0 0 1 defocus_angle 0.5 focus_dist 4
+                seed 42 sampler sobol filter gaussian filter_radius 2.5 indirect_clamp 10
+                denoise 3 exposure -1 tone_map aces white_point 6 dither off
+            }",
+        )
+        .unwrap();
+
+        let cam = &scene.camera;
+        assert_eq!(cam.aspect_ratio, 16.0 / 9.0);
+        assert_eq!(cam.image_size(), (320, 180));
+        assert_eq!((cam.samples_per_pixel, cam.min_samples_per_pixel), (8, 4));
+        assert_eq!((cam.error_threshold, cam.max_depth), (0.01, 7));
+        assert_color(cam.background, 0.1, 0.2, 0.3);
+        assert_eq!(cam.vfov, 40.0);
+        assert_eq!((cam.lookfrom.x, cam.lookat.y, cam.vup.z), (1.0, 1.0, 1.0));
+        assert_eq!((cam.defocus_angle, cam.focus_dist), (0.5, 4.0));
+        assert_eq!(cam.seed, 42);
+        assert_eq!(cam.sampler.name(), "sobol");
+        assert_eq!(cam.filter, Filter::with_radius(FilterKind::Gaussian, 2.5));
+        assert_eq!(cam.indirect_clamp, 10.0);
+        let denoiser = cam.denoiser.unwrap();
+        assert_eq!(denoiser.passes, 3);
+        assert_eq!(denoiser.strength, Denoiser::default().strength);
+        assert_eq!(cam.display.exposure, -1.0);
+        assert_eq!(cam.display.tone_mapper.name(), "aces");
+        assert_eq!(cam.display.white_point, 6.0);
+        assert!(!cam.display.dither);
+    }
+
+    #[test]
+    fn bvh_blocks_use_the_split_method() {
+        // Spheres bunched up at one end of the row, where the median and SAH splits differ.
+        let spheres: String = (0..20)
+            .map(|i| {
+                format!(
+                    "sphere {{ center {} 0 0 radius 0.5 material white }}\n",
+                    i * i
+                )
+            })
+            .collect();
+        let source = format!(
+            "material white lambertian {{ albedo 1 1 1 }}
+            bvh {{ {spheres} }}
+            mesh {{ file \"models/models.obj\" }}"
+        );
+        let costs = |scene: Scene| -> Vec<f64> {
+            crate::bvh::hierarchies(&scene.world)
+                .iter()
+                .map(|bvh| bvh.stats().sah_cost)
+                .collect()
+        };
+
+        let median = costs(parse_with_split(&source, &scenes_dir(), SplitMethod::Median).unwrap());
+        let sah = costs(parse_with_split(&source, &scenes_dir(), SplitMethod::Sah).unwrap());
+        assert_eq!(median.len(), 2);
+        assert!(sah[0] < median[0], "{sah:?} {median:?}");
+        assert_eq!(costs(parse_str(&source).unwrap()), sah);
+    }
+
+    #[test]
+    fn example_scenes_parse() {
+        for name in ["cornell_box.scene", "mesh.scene", "textures.scene"] {
+            let scene = load(scenes_dir().join(name)).unwrap_or_else(|e| panic!("{name}: {e}"));
+            assert!(!scene.world.objects.is_empty(), "{name}");
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        let cases = [
+            // Unknown keywords and properties
+            (
+                "sphere { center 0 0 0 radius 1\n",
+                2,
+                1,
+                "expected a property or `}`",
+            ),
+            ("camera { }\n\n  teapot { }", 3, 3, "found `teapot`"),
+            (
+                "light camera { }",
+                1,
+                7,
+                "expected an object, found `camera`",
+            ),
+            (
+                "material m metal {\n    albedo 1 1 1\n    colour 1 }",
+                3,
+                5,
+                "unknown property `colour` in metal",
+            ),
+            ("texture t wood { }", 1, 11, "unknown texture type `wood`"),
+            (
+                "material m plastic { }",
+                1,
+                12,
+                "unknown material type `plastic`",
+            ),
+            // Missing braces
+            ("sphere center 0 0 0", 1, 8, "expected `{`, found `center`"),
+            (
+                "material m metal { albedo 1 1 1",
+                1,
+                32,
+                "found end of file",
+            ),
+            ("camera { } }", 1, 12, "found `}`"),
+            // Bad numbers
+            (
+                "camera {\n    vfov 1.2.3\n}",
+                2,
+                10,
+                "invalid number `1.2.3`",
+            ),
+            (
+                "camera { vfov 1e999 }",
+                1,
+                15,
+                "number `1e999` is out of range",
+            ),
+            (
+                "camera { vfov wide }",
+                1,
+                15,
+                "expected a number, found `wide`",
+            ),
+            (
+                "camera { max_depth 2.5 }",
+                1,
+                20,
+                "expected a positive integer",
+            ),
+            ("camera { vfov -10 }", 1, 15, "expected a positive number"),
+            (
+                "camera { lookfrom 1 2 }",
+                1,
+                23,
+                "expected a number, found `}`",
+            ),
+            // Undefined names
+            (
+                "sphere { center 0 0 0 radius 1 material missing }",
+                1,
+                41,
+                "unknown material `missing`",
+            ),
+            (
+                "material m lambertian { texture nope }",
+                1,
+                33,
+                "unknown texture `nope`",
+            ),
+            (
+                "material m lambertian { albedo 1 1 1 }\nmaterial m metal { albedo 1 1 1 }",
+                2,
+                10,
+                "material `m` is already defined",
+            ),
+            // Out of range values
+            ("camera { image_width 100000 }", 1, 22, "at most 16384"),
+            (
+                "camera { aspect_ratio 1e-300 }",
+                1,
+                23,
+                "expected a ratio between",
+            ),
+            (
+                "camera { aspect_ratio 1:100000 }",
+                1,
+                23,
+                "expected a ratio between",
+            ),
+            (
+                "camera { image_width 16000 aspect_ratio 0.001 }",
+                1,
+                41,
+                "image height 16000000 is larger than the maximum",
+            ),
+            ("camera { filter_radius 1e300 }", 1, 24, "at most 16"),
+            ("camera { max_depth 2000000 }", 1, 20, "at most 1000"),
+            ("camera { vfov 180 }", 1, 15, "below 180"),
+            ("camera { denoise 100 }", 1, 18, "at most 16"),
+            (
+                "camera { denoise -1 }",
+                1,
+                18,
+                "expected a positive integer",
+            ),
+            ("camera { seed 0.5 }", 1, 15, "expected a seed"),
+            (
+                "material m lambertian { albedo 1 1 1 }\n\
+                 transform { scale 1 1e-14 1 sphere { center 0 0 0 radius 1 material m } }",
+                2,
+                1,
+                "can't be inverted",
+            ),
+            (
+                "material m lambertian { albedo 1 1 1 }\n\
+                 transform { scale 1 1e-7 1\n\
+                 transform { scale 1 1e-7 1 sphere { center 0 0 0 radius 1 material m } } }",
+                2,
+                1,
+                "can't be inverted",
+            ),
+            // Missing properties and objects
+            (
+                "sphere { center 0 0 0 }",
+                1,
+                1,
+                "sphere is missing `radius`",
+            ),
+            ("group { }", 1, 1, "group needs at least one object"),
+            (
+                "texture t image { file \"missing.png }",
+                1,
+                24,
+                "unterminated string",
+            ),
+        ];
+
+        for (source, line, column, message) in cases {
+            let Err(error) = parse_str(source) else {
+                panic!("{source:?} parsed without an error");
+            };
+            assert_eq!(
+                (error.line, error.column),
+                (line, column),
+                "{source:?}: {error}"
+            );
+            assert!(error.message.contains(message), "{source:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn tiny_uniform_scales_are_invertible() {
+        let scene = parse_str(
+            "material white lambertian { albedo 1 1 1 }\n\
+             transform { scale 0.00001 sphere { center 0 0 -1 radius 1 material white } }",
+        )
+        .unwrap();
+        assert!(is::<Transform>(&scene.world.objects[0]));
+    }
+
+    #[test]
+    fn missing_files_are_parse_errors() {
+        let Err(error) = parse_str("texture t image { file \"missing.png\" }") else {
+            panic!("missing image loaded");
+        };
+        assert_eq!((error.line, error.column), (1, 24));
+        assert!(error.message.contains("can't load image `missing.png`"));
+
+        let Err(error) = parse_str("mesh { file \"missing.obj\" }") else {
+            panic!("missing mesh loaded");
+        };
+        assert_eq!((error.line, error.column), (1, 13));
+        assert!(error.message.contains("can't load mesh `missing.obj`"));
+    }
+}
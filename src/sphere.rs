@@ -1,13 +1,27 @@
 
-use std::f64::consts::PI;
+use std::{any::Any, f64::consts::PI, sync::Arc};
 
 use crate::{
-    aabb::Aabb, interval::Interval, material::Material, ray::{ray, Ray}, vec3::{dot, vec3, Vec3}
+    aabb::Aabb, interval::Interval, material::{default_material, Material}, onb::Onb, ray::{ray, Ray},
+    sampler::{sample_2d, Dimension}, stats::{self, Counter}, vec3::{dot, vec3, Vec3}
 };
 
-pub trait Hittable {
+/// Object that rays can hit. The `Any` supertrait lets wrappers such as
+/// [`Transform`](crate::hittable_list::Transform) recognize objects of their own type.
+pub trait Hittable: Any + Send + Sync {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self) -> &Aabb;
+
+    /// Probability density, with respect to solid angle, of `random` returning `direction` when
+    /// sampling the object from `origin`. Objects that can't be sampled return zero.
+    fn pdf_value(&self, _origin: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Returns a random direction from `origin` towards the object.
+    fn random(&self, _origin: &Vec3) -> Vec3 {
+        vec3(1.0, 0.0, 0.0)
+    }
 }
 
 #[derive(Clone)]
@@ -18,19 +32,19 @@ pub struct HitRecord {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
-    pub mat: Material,
+    pub mat: Arc<dyn Material>,
 }
 
 impl Default for HitRecord {
     fn default() -> Self {
         HitRecord {
-            p: Default::default(),
-            normal: Default::default(),
-            t: Default::default(),
-            u: Default::default(),
-            v: Default::default(),
-            front_face: Default::default(),
-            mat: Default::default(),
+            p: Vec3::default(),
+            normal: Vec3::default(),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: false,
+            mat: default_material(),
         }
     }
 }
@@ -40,39 +54,57 @@ impl HitRecord {
         // Sets the hit record normal vector.
         // Note: the parameter `outward_normal` is assumed to have unit length.
 
-        self.front_face = dot(&r.direction(), outward_normal) < 0.0;
+        self.front_face = dot(r.direction(), outward_normal) < 0.0;
         self.normal = if self.front_face {
-            outward_normal.clone()
+            *outward_normal
         } else {
-            -outward_normal.clone()
+            -*outward_normal
         };
     }
 }
 
+/// Checks in debug builds that the hit point just stored in `rec` lies inside the bounding box of
+/// `object`. A box that is too small lets bounding volume hierarchies skip an object that a ray
+/// actually hits.
+#[track_caller]
+pub(crate) fn debug_assert_hit_in_bounds(object: &dyn Hittable, rec: &HitRecord) {
+    if cfg!(debug_assertions) {
+        // Leave some room for the rounding errors of the intersection.
+        let tolerance = 1e-6 * f64::max(1.0, rec.p.length());
+        let bbox = object.bounding_box();
+        assert!(
+            bbox.expand(tolerance).contains(&rec.p),
+            "hit point {:?} lies outside of the bounding box {:?}",
+            rec.p,
+            bbox
+        );
+    }
+}
+
 pub struct Sphere {
     pub center: Ray,
     pub radius: f64,
-    mat: Material,
+    mat: Arc<dyn Material>,
     bbox: Aabb,
 }
 impl Sphere {
     // Stationary Sphere
-    pub fn new(center: Vec3, radius: f64, mat: Material) -> Sphere {
+    pub fn new(center: Vec3, radius: f64, mat: Arc<dyn Material>) -> Sphere {
         let rvec = vec3(radius, radius, radius);
         Sphere {
-            center: ray(center.clone(), vec3(0.0, 0.0, 0.0)),
+            center: ray(center, vec3(0.0, 0.0, 0.0)),
             radius,
             mat,
-            bbox: Aabb::from_point(&(center.clone() - rvec.clone()), &(center + rvec)),
+            bbox: Aabb::from_point(&(center - rvec), &(center + rvec)),
         }
     }
 
     // Moving Sphere
-    pub fn moving(center1: Vec3, center2: Vec3, radius: f64, mat: Material) -> Sphere {
+    pub fn moving(center1: Vec3, center2: Vec3, radius: f64, mat: Arc<dyn Material>) -> Sphere {
         let rvec = vec3(radius, radius, radius);
-        let center = ray(center1.clone(), center2 - center1);
-        let box1 = &Aabb::from_point(&(center.at(0.0) - rvec.clone()), &(center.at(0.0) + rvec.clone()));
-        let box2 = &Aabb::from_point(&(center.at(1.0) - rvec.clone()), &(&center.at(1.0) + rvec));
+        let center = ray(center1, center2 - center1);
+        let box1 = &Aabb::from_point(&(center.at(0.0) - rvec), &(center.at(0.0) + rvec));
+        let box2 = &Aabb::from_point(&(center.at(1.0) - rvec), &(center.at(1.0) + rvec));
         Sphere {
             center,
             radius,
@@ -80,14 +112,18 @@ impl Sphere {
             bbox: Aabb::from_aabb(box1, box2)
         }
     }
-}
 
-impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.mat
+    }
+
+    /// Intersects the sphere like [`Hittable::hit`], without counting it as a primitive test of
+    /// the render.
+    fn intersect(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
         let current_center = self.center.at(r.time());
         let oc = &current_center - r.origin();
         let a = r.direction().length_squared();
-        let h = dot(&r.direction(), &oc);
+        let h = dot(r.direction(), &oc);
         let c = oc.length_squared() - self.radius * self.radius;
         let discriminant = h * h - a * c;
 
@@ -107,17 +143,68 @@ impl Hittable for Sphere {
 
         rec.t = root;
         rec.p = r.at(rec.t);
-        let outward_normal = (&rec.p - current_center) / self.radius;
+        let outward_normal = (rec.p - current_center) / self.radius;
         rec.set_face_normal(r, &outward_normal);
         get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
         rec.mat = self.mat.clone();
+        debug_assert_hit_in_bounds(self, rec);
 
         true
     }
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        stats::count(Counter::PrimitiveTests, 1);
+        self.intersect(r, ray_t, rec)
+    }
 
     fn bounding_box(&self) -> &Aabb {
         &self.bbox
     }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        // This method only works for stationary spheres.
+        let mut rec = HitRecord::default();
+        if !self.intersect(&ray(*origin, *direction), Interval::new(0.001, f64::INFINITY), &mut rec) {
+            return 0.0;
+        }
+
+        // Points inside the sphere can't sample it through the cone of directions.
+        let distance_squared = (self.center.at(0.0) - *origin).length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return 0.0;
+        }
+
+        let cos_theta_max = f64::sqrt(1.0 - self.radius * self.radius / distance_squared);
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Vec3) -> Vec3 {
+        let direction = self.center.at(0.0) - *origin;
+        let distance_squared = direction.length_squared();
+        let uvw = Onb::new(&direction);
+        uvw.transform(&random_to_sphere(self.radius, distance_squared))
+    }
+}
+
+/// Returns a random direction, around the z axis, within the cone a sphere subtends
+///
+/// # Arguments
+///
+/// * `radius` - Radius of the sphere
+/// * `distance_squared` - Squared distance from the origin to the center of the sphere
+fn random_to_sphere(radius: f64, distance_squared: f64) -> Vec3 {
+    let (r1, r2) = sample_2d(Dimension::Light);
+    let z = 1.0 + r2 * (f64::sqrt(1.0 - radius * radius / distance_squared) - 1.0);
+
+    let phi = 2.0 * PI * r1;
+    let x = f64::cos(phi) * f64::sqrt(1.0 - z * z);
+    let y = f64::sin(phi) * f64::sqrt(1.0 - z * z);
+
+    vec3(x, y, z)
 }
 
 /// Translate a point on the sphere two dimensional coordinates consisting on
@@ -135,4 +222,29 @@ fn get_sphere_uv(p: &Vec3, u: &mut f64, v: &mut f64) {
 
     *u = phi / (2.0 * PI);
     *v = theta / PI;
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::DiffuseLight, stats::RenderStats};
+
+    #[test]
+    fn only_hits_count_as_primitive_tests() {
+        let light = Arc::new(DiffuseLight::new(Color::white()));
+        let sphere = Sphere::new(vec3(0.0, 0.0, -2.0), 1.0, light);
+        let mut stats = RenderStats::default();
+        stats.take_thread_counts();
+
+        // Sampling the sphere as a light intersects it without counting.
+        assert!(sphere.pdf_value(&Vec3::default(), &vec3(0.0, 0.0, -1.0)) > 0.0);
+        stats.take_thread_counts();
+        assert_eq!(stats.primitive_tests, 0);
+
+        let mut rec = HitRecord::default();
+        let r = ray(Vec3::default(), vec3(0.0, 0.0, -1.0));
+        assert!(sphere.hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec));
+        stats.take_thread_counts();
+        assert_eq!(stats.primitive_tests, 1);
+    }
+}
@@ -0,0 +1,130 @@
+use std::{cell::Cell, fmt, time::Duration};
+
+/// Work counted while rendering
+#[derive(Clone, Copy)]
+pub(crate) enum Counter {
+    PrimaryRays,
+    BounceRays,
+    ShadowRays,
+    BvhNodeVisits,
+    PrimitiveTests,
+}
+
+const COUNTERS: usize = 5;
+
+thread_local! {
+    // Every thread counts in plain cells of its own, which costs no more than an addition. The
+    // render threads collect them once per scanline.
+    static COUNTS: [Cell<u64>; COUNTERS] = const { [const { Cell::new(0) }; COUNTERS] };
+}
+
+/// Adds `amount` to a counter of the current thread.
+#[inline]
+pub(crate) fn count(counter: Counter, amount: u64) {
+    COUNTS.with(|counts| {
+        let count = &counts[counter as usize];
+        count.set(count.get() + amount);
+    });
+}
+
+/// Work done by a render and the time it took
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// Rays leaving the camera, one per sample
+    pub primary_rays: u64,
+    /// Rays scattered by the surfaces and media along the paths
+    pub bounce_rays: u64,
+    /// Rays testing whether a point sampled on a light is visible
+    pub shadow_rays: u64,
+    pub bvh_node_visits: u64,
+    /// Intersection tests of spheres, quads and triangles
+    pub primitive_tests: u64,
+    pub wall_time: Duration,
+    /// CPU time the render threads used, summed over the threads. `None` on platforms that
+    /// don't report the CPU time of a thread.
+    pub cpu_time: Option<Duration>,
+}
+
+impl RenderStats {
+    /// Moves the counts of the current thread into these statistics.
+    pub(crate) fn take_thread_counts(&mut self) {
+        let [primary, bounce, shadow, visits, tests] =
+            COUNTS.with(|counts| counts.each_ref().map(|count| count.take()));
+        self.primary_rays += primary;
+        self.bounce_rays += bounce;
+        self.shadow_rays += shadow;
+        self.bvh_node_visits += visits;
+        self.primitive_tests += tests;
+    }
+
+    /// Rays other than the ones leaving the camera
+    pub fn secondary_rays(&self) -> u64 {
+        self.bounce_rays + self.shadow_rays
+    }
+
+    /// Average number of segments of the paths, counting the camera ray and every bounce
+    pub fn average_path_length(&self) -> f64 {
+        (self.primary_rays + self.bounce_rays) as f64 / self.primary_rays.max(1) as f64
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rays = (self.primary_rays + self.secondary_rays()).max(1) as f64;
+        let wall_seconds = self.wall_time.as_secs_f64();
+        writeln!(f, "Primary rays:        {}", self.primary_rays)?;
+        writeln!(
+            f,
+            "Secondary rays:      {} ({} bounces, {} shadow rays)",
+            self.secondary_rays(),
+            self.bounce_rays,
+            self.shadow_rays
+        )?;
+        writeln!(
+            f,
+            "BVH node visits:     {} ({:.1} per ray)",
+            self.bvh_node_visits,
+            self.bvh_node_visits as f64 / rays
+        )?;
+        writeln!(
+            f,
+            "Primitive tests:     {} ({:.1} per ray)",
+            self.primitive_tests,
+            self.primitive_tests as f64 / rays
+        )?;
+        writeln!(f, "Average path length: {:.2}", self.average_path_length())?;
+        writeln!(
+            f,
+            "Wall time:           {:.2} s ({:.0} rays/s)",
+            wall_seconds,
+            rays / wall_seconds.max(1e-9)
+        )?;
+        match self.cpu_time {
+            Some(cpu_time) => write!(
+                f,
+                "CPU time:            {:.2} s ({:.1} cores busy on average)",
+                cpu_time.as_secs_f64(),
+                cpu_time.as_secs_f64() / wall_seconds.max(1e-9)
+            ),
+            None => write!(f, "CPU time:            not available on this platform"),
+        }
+    }
+}
+
+/// CPU time the current thread has used so far, or `None` if the platform doesn't report it
+#[cfg(unix)]
+pub(crate) fn thread_cpu_time() -> Option<Duration> {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `time` is a valid timespec for the call to write to.
+    let result = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    (result == 0).then(|| Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+/// CPU time the current thread has used so far, or `None` if the platform doesn't report it
+#[cfg(not(unix))]
+pub(crate) fn thread_cpu_time() -> Option<Duration> {
+    None
+}
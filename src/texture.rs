@@ -1,10 +1,10 @@
-use std::rc::Rc;
+use std::{path::Path, sync::Arc};
 
-use image::{DynamicImage, GenericImageView, ImageReader};
+use image::{DynamicImage, GenericImageView, ImageReader, ImageResult};
 
 use crate::{color::Color, interval::Interval, perlin::Perlin, vec3::Vec3};
 
-pub trait Texture {
+pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Color;
 }
 
@@ -25,19 +25,19 @@ impl SolidColor {
 }
 
 impl Texture for SolidColor {
-    fn value(&self, u: f64, v: f64, p: &Vec3) -> Color {
+    fn value(&self, _u: f64, _v: f64, _p: &Vec3) -> Color {
         self.albedo
     }
 }
 
 pub struct CheckerTexture {
     inv_scale: f64,
-    even: Rc<dyn Texture>,
-    odd: Rc<dyn Texture>,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
 }
 
 impl CheckerTexture {
-    pub fn new(scale: f64, even: Rc<dyn Texture>, odd: Rc<dyn Texture>) -> CheckerTexture {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> CheckerTexture {
         CheckerTexture {
             inv_scale: 1.0 / scale,
             even,
@@ -48,8 +48,8 @@ impl CheckerTexture {
     pub fn from_colors(scale: f64, c1: Color, c2: Color) -> CheckerTexture {
         CheckerTexture::new(
             scale,
-            Rc::new(SolidColor::from_color(c1)),
-            Rc::new(SolidColor::from_color(c2)),
+            Arc::new(SolidColor::from_color(c1)),
+            Arc::new(SolidColor::from_color(c2)),
         )
     }
 }
@@ -75,6 +75,12 @@ pub struct ImageTexture {
 }
 
 impl ImageTexture {
+    /// Loads an image texture, returning an error if the file can't be opened or decoded
+    pub fn open(filename: impl AsRef<Path>) -> ImageResult<Self> {
+        let image = ImageReader::open(filename)?.decode()?;
+        Ok(ImageTexture { image })
+    }
+
     pub fn new(filename: &str) -> Self {
         let image = match ImageReader::open(filename) {
             Ok(image_reader) => match image_reader.decode() {
@@ -88,9 +94,9 @@ impl ImageTexture {
 }
 
 impl Texture for ImageTexture {
-    fn value(&self, u: f64, v: f64, p: &Vec3) -> Color {
+    fn value(&self, u: f64, v: f64, _p: &Vec3) -> Color {
         // If we have no texture data, then return solid cyan as a debugging aid.
-        if self.image.height() <= 0 {
+        if self.image.height() == 0 {
             return Color::new(0.0, 1.0, 1.0);
         }
 
@@ -118,15 +124,25 @@ pub struct NoiseTexture {
 
 impl NoiseTexture {
     pub fn new(scale: f64) -> Self {
+        NoiseTexture::with_seed(scale, 0)
+    }
+
+    /// Creates a marble-like noise texture
+    ///
+    /// # Arguments
+    ///
+    /// * `scale` - Frequency of the stripes
+    /// * `seed` - Seed of the random noise tables
+    pub fn with_seed(scale: f64, seed: u64) -> Self {
         NoiseTexture {
-            noise: Perlin::new(),
+            noise: Perlin::new(seed),
             scale,
         }
     }
 }
 
 impl Texture for NoiseTexture {
-    fn value(&self, u: f64, v: f64, p: &Vec3) -> Color {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Color {
         Color::new(0.5, 0.5, 0.5)
             * (1.0 + f64::sin(self.scale * p.z + 10.0 * self.noise.turb(p, 7)))
     }
@@ -0,0 +1,266 @@
+use std::str::FromStr;
+
+use crate::color::{self, Color};
+
+/// Operator compressing the unbounded brightness of a rendered image into the range a display
+/// can show
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapper {
+    /// Clips everything brighter than white
+    #[default]
+    Clamp,
+    /// `c / (1 + c)`, which never quite reaches white
+    Reinhard,
+    /// Reinhard with a white point, brightness at the white point and above maps to white
+    ExtendedReinhard,
+    /// Stephen Hill's fit of the ACES reference rendering and sRGB output transforms, a filmic
+    /// curve with a toe and a shoulder that desaturates highlights
+    Aces,
+    /// Minimal AgX by Benjamin Wrensch, which fades bright saturated colors towards white more
+    /// gracefully than ACES
+    Agx,
+}
+
+impl ToneMapper {
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMapper::Clamp => "clamp",
+            ToneMapper::Reinhard => "reinhard",
+            ToneMapper::ExtendedReinhard => "extended-reinhard",
+            ToneMapper::Aces => "aces",
+            ToneMapper::Agx => "agx",
+        }
+    }
+}
+
+impl FromStr for ToneMapper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "clamp" | "none" => Ok(ToneMapper::Clamp),
+            "reinhard" => Ok(ToneMapper::Reinhard),
+            "extended-reinhard" => Ok(ToneMapper::ExtendedReinhard),
+            "aces" => Ok(ToneMapper::Aces),
+            "agx" => Ok(ToneMapper::Agx),
+            _ => Err(format!(
+                "unknown tone mapper `{s}`, expected one of clamp, reinhard, extended-reinhard, aces, agx"
+            )),
+        }
+    }
+}
+
+/// Conversion of the linear colors of a rendered image to 8 bit sRGB values for display formats.
+/// HDR formats store the linear colors as they are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTransform {
+    /// Exposure adjustment in stops, every stop doubles the brightness
+    pub exposure: f64,
+    pub tone_mapper: ToneMapper,
+    /// Brightness the extended Reinhard operator maps to white, after the exposure
+    pub white_point: f64,
+    /// Whether to add noise of up to one step before quantizing, which breaks up banding in
+    /// smooth gradients
+    pub dither: bool,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        DisplayTransform {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Clamp,
+            white_point: 4.0,
+            dither: true,
+        }
+    }
+}
+
+impl DisplayTransform {
+    /// Applies the exposure and the tone mapper to a linear color, leaving linear values between 0
+    /// and 1 ready to be encoded.
+    pub fn tone_map(&self, color: Color) -> Color {
+        let color = f64::powf(2.0, self.exposure) * color;
+        let per_channel = |f: &dyn Fn(f64) -> f64| Color::new(f(color.r), f(color.g), f(color.b));
+        let mapped = match self.tone_mapper {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => per_channel(&|c| c / (1.0 + c)),
+            ToneMapper::ExtendedReinhard => {
+                let white = self.white_point * self.white_point;
+                per_channel(&|c| c * (1.0 + c / white) / (1.0 + c))
+            }
+            ToneMapper::Aces => aces(color),
+            ToneMapper::Agx => agx(color),
+        };
+        let clamp = |c: f64| c.clamp(0.0, 1.0);
+        Color::new(clamp(mapped.r), clamp(mapped.g), clamp(mapped.b))
+    }
+
+    /// Converts a linear color of pixel `x`, `y` to 8 bit sRGB. The dithering noise only depends
+    /// on the pixel position, so the same image always gives the same bytes.
+    pub fn to_rgb8(&self, color: Color, x: u32, y: u32) -> [u8; 3] {
+        let color = self.tone_map(color);
+        let mut dither = [0.0; 3];
+        if self.dither {
+            // Triangular noise between -1 and 1, independent for every channel
+            let mut state = hash(x.wrapping_mul(0x9e37_79b9) ^ hash(y));
+            for channel in &mut dither {
+                let first = hash(state);
+                state = hash(first);
+                *channel = unit(first) + unit(state) - 1.0;
+            }
+        }
+        color::to_rgb8(color, dither)
+    }
+}
+
+/// Integer hash by Chris Wellons, mixing all input bits into all output bits
+fn hash(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+/// Maps a hash to [0, 1)
+fn unit(hash: u32) -> f64 {
+    hash as f64 / (u32::MAX as f64 + 1.0)
+}
+
+/// Multiplies a color by a matrix given as rows.
+fn transform(m: &[[f64; 3]; 3], c: Color) -> Color {
+    let row = |r: &[f64; 3]| r[0] * c.r + r[1] * c.g + r[2] * c.b;
+    Color::new(row(&m[0]), row(&m[1]), row(&m[2]))
+}
+
+fn aces(color: Color) -> Color {
+    // sRGB to the ACES working space, combined with the exposure and saturation of the RRT
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    // ODT saturation and the ACES working space back to sRGB
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let fit = |v: f64| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+        a / b
+    };
+    let c = transform(&INPUT, color);
+    transform(&OUTPUT, Color::new(fit(c.r), fit(c.g), fit(c.b)))
+}
+
+fn agx(color: Color) -> Color {
+    // Insets the primaries, so that bright saturated colors bleed into the other channels
+    const INSET: [[f64; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    // Sigmoid in log space approximated by a polynomial, giving display encoded values
+    let curve = |v: f64| {
+        let x = (v.max(0.0).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+    let c = transform(&INSET, color);
+    let c = transform(&OUTSET, Color::new(curve(c.r), curve(c.g), curve(c.b)));
+    // Back to linear with the gamma 2.2 display the curve was made for
+    let linear = |v: f64| v.max(0.0).powf(2.2);
+    Color::new(linear(c.r), linear(c.g), linear(c.b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPERS: [ToneMapper; 5] = [
+        ToneMapper::Clamp,
+        ToneMapper::Reinhard,
+        ToneMapper::ExtendedReinhard,
+        ToneMapper::Aces,
+        ToneMapper::Agx,
+    ];
+
+    fn gray(value: f64) -> Color {
+        Color::new(value, value, value)
+    }
+
+    #[test]
+    fn tone_mappers_are_monotonic_and_bounded() {
+        for tone_mapper in MAPPERS {
+            let display = DisplayTransform {
+                tone_mapper,
+                ..DisplayTransform::default()
+            };
+            assert!(display.tone_map(gray(0.0)).g < 0.01, "{tone_mapper:?}");
+            let mut previous = -1.0;
+            for step in 0..200 {
+                let value = display
+                    .tone_map(gray(f64::powf(2.0, step as f64 / 10.0 - 10.0)))
+                    .g;
+                assert!(value >= previous && value <= 1.0, "{tone_mapper:?} {step}");
+                previous = value;
+            }
+            assert!(previous > 0.9, "{tone_mapper:?} {previous}");
+        }
+    }
+
+    #[test]
+    fn extended_reinhard_reaches_white_at_the_white_point() {
+        let display = DisplayTransform {
+            tone_mapper: ToneMapper::ExtendedReinhard,
+            white_point: 7.0,
+            ..DisplayTransform::default()
+        };
+        assert!((display.tone_map(gray(7.0)).r - 1.0).abs() < 1e-12);
+        assert!(display.tone_map(gray(6.0)).r < 1.0);
+    }
+
+    #[test]
+    fn exposure_doubles_per_stop() {
+        let display = DisplayTransform {
+            exposure: 2.0,
+            ..DisplayTransform::default()
+        };
+        assert_eq!(display.tone_map(gray(0.1)).b, 0.4);
+    }
+
+    #[test]
+    fn dithering_keeps_the_average() {
+        let display = DisplayTransform::default();
+        let value = color::srgb_to_linear(100.3 / 255.0);
+        let sum: u32 = (0..64)
+            .flat_map(|y| (0..64).map(move |x| (x, y)))
+            .map(|(x, y)| display.to_rgb8(gray(value), x, y)[1] as u32)
+            .sum();
+        let average = sum as f64 / 4096.0;
+        assert!((average - 100.3).abs() < 0.05, "{average}");
+
+        // Black and white stay exact.
+        assert_eq!(display.to_rgb8(gray(0.0), 3, 5), [0, 0, 0]);
+        assert_eq!(display.to_rgb8(gray(2.0), 3, 5), [255, 255, 255]);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for tone_mapper in MAPPERS {
+            assert_eq!(tone_mapper.name().parse::<ToneMapper>(), Ok(tone_mapper));
+        }
+    }
+}
@@ -0,0 +1,517 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    bvh::{FlatBvh, SplitMethod},
+    interval::Interval,
+    material::Material,
+    ray::Ray,
+    sphere::{debug_assert_hit_in_bounds, HitRecord, Hittable},
+    stats::{self, Counter},
+    vec3::{cross, dot, unit_vector, Vec3},
+};
+
+/// Texture coordinates of a vertex
+pub type Uv = (f64, f64);
+
+/// Maximum number of triangles stored in a single leaf of the mesh hierarchy
+const MAX_TRIANGLES_IN_LEAF: usize = 4;
+
+/// Single triangle with optional per-vertex normals and texture coordinates.
+pub struct Triangle {
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[Uv; 3]>,
+    mat: Arc<dyn Material>,
+    bbox: Aabb,
+}
+
+impl Triangle {
+    /// Creates a flat shaded triangle
+    ///
+    /// # Arguments
+    ///
+    /// * `a`, `b`, `c` - Corner points in counter-clockwise order as seen from the front side
+    /// * `mat` - Material of the triangle
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, mat: Arc<dyn Material>) -> Self {
+        Triangle::with_attributes([a, b, c], None, None, mat)
+    }
+
+    /// Creates a triangle with interpolated vertex normals and texture coordinates
+    ///
+    /// # Arguments
+    ///
+    /// * `vertices` - Corner points in counter-clockwise order as seen from the front side
+    /// * `normals` - Shading normals at the corners, the geometric normal is used if `None`
+    /// * `uvs` - Texture coordinates at the corners, barycentric coordinates are used if `None`
+    /// * `mat` - Material of the triangle
+    pub fn with_attributes(
+        vertices: [Vec3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[Uv; 3]>,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        let bbox = triangle_bbox(&vertices[0], &vertices[1], &vertices[2]);
+        Triangle {
+            vertices,
+            normals,
+            uvs,
+            mat,
+            bbox,
+        }
+    }
+
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.mat
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let [p0, p1, p2] = &self.vertices;
+        let Some((t, b1, b2)) = intersect_triangle(r, ray_t, p0, p1, p2) else {
+            return false;
+        };
+
+        rec.t = t;
+        rec.p = r.at(t);
+        set_surface(
+            rec,
+            r,
+            [p0, p1, p2],
+            self.normals.as_ref(),
+            self.uvs,
+            b1,
+            b2,
+        );
+        rec.mat = self.mat.clone();
+        debug_assert_hit_in_bounds(self, rec);
+
+        true
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        &self.bbox
+    }
+}
+
+/// Triangle mesh sharing vertex attributes between triangles, with its own acceleration
+/// structure over the triangles.
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Uv>,
+    triangles: Vec<[u32; 3]>,
+    mat: Arc<dyn Material>,
+    hierarchy: FlatBvh,
+}
+
+impl TriangleMesh {
+    /// Creates a mesh from shared vertex buffers
+    ///
+    /// # Arguments
+    ///
+    /// * `positions` - Vertex positions
+    /// * `normals` - Shading normal per vertex, or empty to shade flat
+    /// * `uvs` - Texture coordinates per vertex, or empty to use barycentric coordinates
+    /// * `triangles` - Three vertex indices per triangle in counter-clockwise order
+    /// * `mat` - Material of the whole mesh
+    ///
+    /// # Panics
+    ///
+    /// If `normals` or `uvs` are non-empty but differ in length from `positions`, or if an index
+    /// is out of range.
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<Uv>,
+        triangles: Vec<[u32; 3]>,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        let method = SplitMethod::default();
+        TriangleMesh::with_split(positions, normals, uvs, triangles, mat, method)
+    }
+
+    /// Creates a mesh like [`TriangleMesh::new`], with `method` dividing the triangles between
+    /// the nodes of its hierarchy.
+    pub fn with_split(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<Uv>,
+        triangles: Vec<[u32; 3]>,
+        mat: Arc<dyn Material>,
+        method: SplitMethod,
+    ) -> Self {
+        assert!(normals.is_empty() || normals.len() == positions.len());
+        assert!(uvs.is_empty() || uvs.len() == positions.len());
+        assert!(triangles
+            .iter()
+            .flatten()
+            .all(|&index| (index as usize) < positions.len()));
+
+        // Reorder the triangles so that every leaf of the hierarchy covers a consecutive range
+        // of them.
+        let bounds: Vec<Aabb> = triangles
+            .iter()
+            .map(|triangle| {
+                let [p0, p1, p2] = triangle.map(|index| &positions[index as usize]);
+                triangle_bbox(p0, p1, p2)
+            })
+            .collect();
+        let (hierarchy, order) = FlatBvh::build(&bounds, method, MAX_TRIANGLES_IN_LEAF);
+        let triangles = order
+            .iter()
+            .map(|&triangle| triangles[triangle as usize])
+            .collect();
+
+        TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            triangles,
+            mat,
+            hierarchy,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.mat
+    }
+
+    fn corners(&self, triangle: usize) -> [&Vec3; 3] {
+        self.triangles[triangle].map(|index| &self.positions[index as usize])
+    }
+
+    fn hit_triangle(&self, triangle: usize, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let [p0, p1, p2] = self.corners(triangle);
+        let Some((t, b1, b2)) = intersect_triangle(r, ray_t, p0, p1, p2) else {
+            return false;
+        };
+
+        let indices = self.triangles[triangle].map(|index| index as usize);
+        let normals = (!self.normals.is_empty()).then(|| indices.map(|i| self.normals[i]));
+        let uvs = (!self.uvs.is_empty()).then(|| indices.map(|i| self.uvs[i]));
+
+        rec.t = t;
+        rec.p = r.at(t);
+        set_surface(rec, r, [p0, p1, p2], normals.as_ref(), uvs, b1, b2);
+        true
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let hit_anything = self.hierarchy.hit(r, ray_t, |triangle, interval| {
+            self.hit_triangle(triangle, r, interval, rec)
+                .then_some(rec.t)
+        });
+
+        if hit_anything {
+            rec.mat = self.mat.clone();
+            debug_assert_hit_in_bounds(self, rec);
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> &Aabb {
+        self.hierarchy.bounding_box()
+    }
+}
+
+fn triangle_bbox(p0: &Vec3, p1: &Vec3, p2: &Vec3) -> Aabb {
+    Aabb::from_aabb(&Aabb::from_point(p0, p1), &Aabb::from_point(p1, p2))
+}
+
+/// Möller-Trumbore ray/triangle intersection. Returns the ray parameter and the barycentric
+/// coordinates of the hit point relative to the second and third vertex.
+fn intersect_triangle(
+    r: &Ray,
+    ray_t: Interval,
+    p0: &Vec3,
+    p1: &Vec3,
+    p2: &Vec3,
+) -> Option<(f64, f64, f64)> {
+    stats::count(Counter::PrimitiveTests, 1);
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let pvec = cross(r.direction(), &edge2);
+    let det = dot(&edge1, &pvec);
+
+    // No hit if the ray is parallel to the triangle plane.
+    if f64::abs(det) < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.origin() - p0;
+    let b1 = dot(&tvec, &pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let qvec = cross(&tvec, &edge1);
+    let b2 = dot(r.direction(), &qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = dot(&edge2, &qvec) * inv_det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+
+    Some((t, b1, b2))
+}
+
+/// Fills in normal and texture coordinates of a hit at barycentric coordinates `b1`, `b2`.
+fn set_surface(
+    rec: &mut HitRecord,
+    r: &Ray,
+    [p0, p1, p2]: [&Vec3; 3],
+    normals: Option<&[Vec3; 3]>,
+    uvs: Option<[Uv; 3]>,
+    b1: f64,
+    b2: f64,
+) {
+    let b0 = 1.0 - b1 - b2;
+    let geometric_normal = unit_vector(&cross(&(p1 - p0), &(p2 - p0)));
+
+    // Interpolated normals are flipped onto the side of the geometric normal, so front and back
+    // faces stay consistent with the actual surface.
+    let mut normal = match normals {
+        Some([n0, n1, n2]) => unit_vector(&(b0 * n0 + b1 * n1 + b2 * n2)),
+        None => geometric_normal,
+    };
+    if dot(&normal, &geometric_normal) < 0.0 {
+        normal = -normal;
+    }
+
+    rec.front_face = dot(r.direction(), &geometric_normal) < 0.0;
+    rec.normal = if rec.front_face { normal } else { -normal };
+
+    (rec.u, rec.v) = match uvs {
+        Some([uv0, uv1, uv2]) => (
+            b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+            b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+        ),
+        None => (b1, b2),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::Lambertian, ray::ray, vec3::vec3};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    /// Right triangle in the z = 0 plane, facing +z
+    fn corners() -> [Vec3; 3] {
+        [
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ]
+    }
+
+    /// Hits an object with a ray along z through `x`, `y`, coming from the `side` of the plane.
+    fn hit(object: &dyn Hittable, x: f64, y: f64, side: f64) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        let r = ray(vec3(x, y, side), vec3(0.0, 0.0, -side));
+        object
+            .hit(&r, Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec)
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn hit_reports_point_barycentric_uvs_and_face() {
+        let [a, b, c] = corners();
+        let triangle = Triangle::new(a, b, c, material());
+
+        let rec = hit(&triangle, 0.25, 0.5, 1.0).unwrap();
+        assert_eq!(rec.t, 1.0);
+        assert_close(rec.p, vec3(0.25, 0.5, 0.0));
+        assert_eq!((rec.u, rec.v), (0.25, 0.5));
+        assert!(rec.front_face);
+        assert_close(rec.normal, vec3(0.0, 0.0, 1.0));
+
+        // From behind the normal faces the ray.
+        let rec = hit(&triangle, 0.25, 0.5, -1.0).unwrap();
+        assert!(!rec.front_face);
+        assert_close(rec.normal, vec3(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn misses_outside_the_edges_and_the_interval() {
+        let [a, b, c] = corners();
+        let triangle = Triangle::new(a, b, c, material());
+        assert!(hit(&triangle, 0.6, 0.6, 1.0).is_none());
+        assert!(hit(&triangle, -0.1, 0.5, 1.0).is_none());
+        assert!(hit(&triangle, 0.5, -0.1, 1.0).is_none());
+
+        let mut rec = HitRecord::default();
+        let r = ray(vec3(0.2, 0.2, 1.0), vec3(0.0, 0.0, -1.0));
+        assert!(!triangle.hit(&r, Interval::new(0.001, 0.5), &mut rec));
+        // Parallel to the plane
+        let r = ray(vec3(-1.0, 0.2, 0.0), vec3(1.0, 0.0, 0.0));
+        assert!(!triangle.hit(&r, Interval::universe(), &mut rec));
+    }
+
+    #[test]
+    fn vertex_attributes_are_interpolated() {
+        let normals = [
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.0, 1.0, 1.0),
+        ];
+        let uvs = [(0.5, 0.5), (1.0, 0.5), (0.5, 1.0)];
+        let triangle = Triangle::with_attributes(corners(), Some(normals), Some(uvs), material());
+
+        let rec = hit(&triangle, 0.0, 0.5, 1.0).unwrap();
+        assert_close(rec.normal, unit_vector(&vec3(0.0, 0.5, 1.0)));
+        assert_eq!((rec.u, rec.v), (0.5, 0.75));
+    }
+
+    #[test]
+    fn shading_normals_stay_on_the_side_of_the_surface() {
+        let normals = [vec3(0.0, 0.0, -1.0); 3];
+        let triangle = Triangle::with_attributes(corners(), Some(normals), None, material());
+
+        let rec = hit(&triangle, 0.2, 0.2, 1.0).unwrap();
+        assert!(rec.front_face);
+        assert_close(rec.normal, vec3(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn degenerate_triangles_are_never_hit() {
+        let collinear = Triangle::new(
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 0.0),
+            vec3(2.0, 2.0, 0.0),
+            material(),
+        );
+        let point = Triangle::new(
+            vec3(1.0, 1.0, 1.0),
+            vec3(1.0, 1.0, 1.0),
+            vec3(1.0, 1.0, 1.0),
+            material(),
+        );
+        for triangle in [&collinear, &point] {
+            for side in [1.0, -1.0] {
+                assert!(hit(triangle, 1.0, 1.0, side).is_none());
+            }
+            let bbox = triangle.bounding_box();
+            assert!(bbox.x.size().is_finite() && bbox.y.size() > 0.0 && bbox.z.size() > 0.0);
+        }
+    }
+
+    #[test]
+    fn bounding_box_encloses_the_corners() {
+        let triangle = Triangle::new(
+            vec3(-1.0, 2.0, 0.5),
+            vec3(3.0, -2.0, 0.5),
+            vec3(0.0, 0.0, 4.0),
+            material(),
+        );
+        // Boxes are padded a little where two corners share a coordinate.
+        let bbox = triangle.bounding_box();
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-3;
+        assert!(close(bbox.x.min, -1.0) && close(bbox.x.max, 3.0));
+        assert!(close(bbox.y.min, -2.0) && close(bbox.y.max, 2.0));
+        assert!(close(bbox.z.min, 0.5) && close(bbox.z.max, 4.0));
+
+        // A triangle in an axis plane still gets a box with volume.
+        let [a, b, c] = corners();
+        assert!(Triangle::new(a, b, c, material()).bounding_box().z.size() > 0.0);
+    }
+
+    /// Grid of `n` x `n` unit squares in the z = 0 plane, two triangles each
+    fn grid(n: u32, normals: bool) -> TriangleMesh {
+        let mut positions = Vec::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                positions.push(vec3(x as f64, y as f64, 0.0));
+            }
+        }
+        let index = |x: u32, y: u32| y * (n + 1) + x;
+        let mut triangles = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                triangles.push([index(x, y), index(x + 1, y), index(x + 1, y + 1)]);
+                triangles.push([index(x, y), index(x + 1, y + 1), index(x, y + 1)]);
+            }
+        }
+        let normals = if normals {
+            vec![vec3(0.0, 0.0, 1.0); positions.len()]
+        } else {
+            Vec::new()
+        };
+        TriangleMesh::new(positions, normals, Vec::new(), triangles, material())
+    }
+
+    #[test]
+    fn mesh_hits_the_triangle_under_the_ray() {
+        let mesh = grid(8, false);
+        assert_eq!(mesh.triangle_count(), 128);
+
+        for (x, y) in [(0.3, 0.1), (3.7, 5.2), (7.9, 7.95), (4.5, 0.5)] {
+            let rec = hit(&mesh, x, y, 1.0).unwrap();
+            assert_close(rec.p, vec3(x, y, 0.0));
+            assert_close(rec.normal, vec3(0.0, 0.0, 1.0));
+        }
+        assert!(hit(&mesh, 8.5, 4.0, 1.0).is_none());
+        assert!(hit(&mesh, -0.5, 4.0, -1.0).is_none());
+        assert!(!hit(&mesh, 2.5, 2.5, -1.0).unwrap().front_face);
+    }
+
+    #[test]
+    fn mesh_reports_the_closest_of_overlapping_triangles() {
+        let positions = vec![
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            vec3(0.0, 0.0, 0.5),
+            vec3(1.0, 0.0, 0.5),
+            vec3(0.0, 1.0, 0.5),
+        ];
+        let mesh = TriangleMesh::new(
+            positions,
+            Vec::new(),
+            Vec::new(),
+            vec![[0, 1, 2], [3, 4, 5]],
+            material(),
+        );
+        assert_eq!(hit(&mesh, 0.2, 0.2, 1.0).unwrap().t, 0.5);
+        assert_eq!(hit(&mesh, 0.2, 0.2, -1.0).unwrap().t, 1.0);
+    }
+
+    #[test]
+    fn mesh_bounding_box_covers_all_triangles() {
+        let bbox = *grid(4, true).bounding_box();
+        assert!(bbox.x.min <= 0.0 && bbox.x.max >= 4.0);
+        assert!(bbox.y.min <= 0.0 && bbox.y.max >= 4.0);
+        assert!(bbox.z.size() > 0.0 && bbox.z.size() < 0.01);
+    }
+
+    #[test]
+    #[should_panic]
+    fn mesh_rejects_out_of_range_indices() {
+        TriangleMesh::new(
+            corners().to_vec(),
+            Vec::new(),
+            Vec::new(),
+            vec![[0, 1, 3]],
+            material(),
+        );
+    }
+}
@@ -3,8 +3,9 @@ use std::ops;
 use crate::{
     color::Color,
     rtweekend::{random_double, random_double_from},
+    sampler::{sample_2d, Dimension},
 };
-#[derive(Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct Vec3 {
     pub x: f64,
     pub y: f64,
@@ -24,7 +25,15 @@ impl Vec3 {
         self.x * self.x + self.y * self.y + self.z * self.z
     }
 
-    pub fn to_color(&self) -> Color {
+    pub fn axis(&self, n: i32) -> f64 {
+        match n {
+            1 => self.y,
+            2 => self.z,
+            _ => self.x,
+        }
+    }
+
+    pub fn to_color(self) -> Color {
         Color::new(self.x, self.y, self.z)
     }
 
@@ -62,7 +71,7 @@ impl ops::Add<Vec3> for &Vec3 {
     type Output = Vec3;
 
     fn add(self, rhs: Vec3) -> Self::Output {
-        self + &rhs
+        *self + rhs
     }
 }
 
@@ -258,27 +267,35 @@ pub fn random_from(min: f64, max: f64) -> Vec3 {
     )
 }
 
+/// Returns a uniformly distributed direction, placed by the sampler of the current thread in
+/// the scattering dimensions.
 pub fn random_unit_vector() -> Vec3 {
-    loop {
-        let p = random_from(-1.0, 1.0);
-        let lensq = p.length_squared();
-        if 1e-160 < lensq && lensq <= 1.0 {
-            return p / f64::sqrt(lensq);
-        }
-    }
+    // Uniform heights on the sphere cover equal areas (Archimedes' hat-box theorem).
+    let (u1, u2) = sample_2d(Dimension::Scatter);
+    let z = 1.0 - 2.0 * u1;
+    let r = f64::sqrt(f64::max(0.0, 1.0 - z * z));
+    let phi = 2.0 * std::f64::consts::PI * u2;
+    vec3(r * f64::cos(phi), r * f64::sin(phi), z)
 }
 
+/// Returns a uniformly distributed point in the unit disk in the xy plane, placed by the sampler
+/// of the current thread in the lens dimensions.
 pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = vec3(
-            random_double_from(-1.0, 1.0),
-            random_double_from(-1.0, 1.0),
-            0.0,
-        );
-        if p.length_squared() < 1.0 {
-            return p;
-        }
+    // Concentric mapping from the square, which keeps the samples' stratification intact unlike
+    // rejecting points outside of the disk (Shirley and Chiu 1997).
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    let (u1, u2) = sample_2d(Dimension::Lens);
+    let (x, y) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return Vec3::default();
     }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, FRAC_PI_4 * (y / x))
+    } else {
+        (y, FRAC_PI_2 - FRAC_PI_4 * (x / y))
+    };
+    vec3(r * f64::cos(theta), r * f64::sin(theta), 0.0)
 }
 
 // pub fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
@@ -290,8 +307,21 @@ pub fn random_in_unit_disk() -> Vec3 {
 //     }
 // }
 
+pub fn random_cosine_direction() -> Vec3 {
+    // Returns a direction on the hemisphere around +z, distributed proportionally to the cosine of
+    // its angle with the z axis, placed in the scattering dimensions.
+    let (r1, r2) = sample_2d(Dimension::Scatter);
+
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    let x = f64::cos(phi) * f64::sqrt(r2);
+    let y = f64::sin(phi) * f64::sqrt(r2);
+    let z = f64::sqrt(1.0 - r2);
+
+    vec3(x, y, z)
+}
+
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
-    return v - 2.0 * dot(v, n) * n;
+    v - 2.0 * dot(v, n) * n
 }
 
 pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {